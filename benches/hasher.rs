@@ -0,0 +1,58 @@
+//! Counts the canonical k-mers of a random sequence into a `DashMap` with each
+//! hasher, at several k.
+//!
+//! `cargo bench --bench hasher`
+
+use bytes::Bytes;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use dashmap::DashMap;
+use krust::{
+    hasher::{HasherKind, KmerBuildHasher},
+    kmer,
+};
+use rayon::prelude::{ParallelBridge, ParallelIterator};
+
+const SEQ_LEN: usize = 1 << 20;
+
+fn sequence() -> Bytes {
+    let mut state = 0x5eed_0005_u64;
+    (0..SEQ_LEN)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            b"ACGT"[(state >> 62) as usize]
+        })
+        .collect::<Vec<_>>()
+        .into()
+}
+
+fn count(seq: &Bytes, k: usize, hasher: HasherKind) -> usize {
+    let counts = DashMap::with_hasher(KmerBuildHasher(hasher));
+    seq.chunks(SEQ_LEN / 64).par_bridge().for_each(|chunk| {
+        for packed_bits in kmer::canonical_kmers::<u64>(&Bytes::copy_from_slice(chunk), k) {
+            *counts.entry(packed_bits).or_insert(0) += 1
+        }
+    });
+
+    counts.len()
+}
+
+fn hashers(c: &mut Criterion) {
+    let seq = sequence();
+    let mut group = c.benchmark_group("count");
+    group.throughput(Throughput::Bytes(SEQ_LEN as u64));
+
+    for k in [11, 21, 31] {
+        for hasher in [HasherKind::Fx, HasherKind::Identity] {
+            group.bench_with_input(BenchmarkId::new(hasher.to_string(), k), &k, |b, &k| {
+                b.iter(|| count(&seq, k, hasher))
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, hashers);
+criterion_main!(benches);
@@ -0,0 +1,43 @@
+//! Records where and how `krust` was built, for `krust info --build`.
+
+use std::{env, process::Command};
+
+/// Trimmed `stdout` of a command, if it ran and succeeded
+fn output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn main() {
+    // Outside a git checkout, as when built from crates.io, there's no hash
+    let git_hash = output("git", &["rev-parse", "--short=12", "HEAD"]).map(|hash| {
+        match output("git", &["status", "--porcelain", "--untracked-files=no"]) {
+            Some(status) if !status.is_empty() => format!("{hash}-dirty"),
+            _ => hash,
+        }
+    });
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".into());
+
+    println!(
+        "cargo:rustc-env=KRUST_GIT_HASH={}",
+        git_hash.unwrap_or_default()
+    );
+    println!(
+        "cargo:rustc-env=KRUST_TARGET={}",
+        env::var("TARGET").unwrap()
+    );
+    println!(
+        "cargo:rustc-env=KRUST_PROFILE={}",
+        env::var("PROFILE").unwrap()
+    );
+    println!(
+        "cargo:rustc-env=KRUST_RUSTC={}",
+        output(&rustc, &["--version"]).unwrap_or_default()
+    );
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/index");
+    println!("cargo:rerun-if-changed=build.rs");
+}
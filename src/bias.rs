@@ -0,0 +1,105 @@
+//! `--bias-report`: strand and GC bias of counted k-mers, by GC content.
+
+use std::io::{self, Write};
+
+use super::{
+    kmer::Packed,
+    run::{CountMap, Tally},
+};
+
+/// Totals over the canonical k-mers with one GC content.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct GcClass {
+    /// Distinct k-mers
+    pub kmers: u64,
+    pub forward: u64,
+    pub reverse: u64,
+}
+
+impl GcClass {
+    pub(crate) fn total(&self) -> u64 {
+        self.forward + self.reverse
+    }
+}
+
+/// `G` and `C` bases in a packed k-mer, the same for both strands
+fn gc<P: Packed>(packed_bits: P, k: usize) -> usize {
+    (0..k)
+        .filter(|i| matches!((packed_bits >> (2 * i)).low_byte() & 0b11, 0b01 | 0b10))
+        .count()
+}
+
+/// Tallies counts into one class per GC content, from 0 to `k` `G`s and `C`s
+pub(crate) fn gc_classes<P: Packed, V: Tally>(
+    counts: &CountMap<P, V>,
+    reverse: &CountMap<P, V>,
+    k: usize,
+) -> Vec<GcClass> {
+    let mut classes = vec![GcClass::default(); k + 1];
+
+    for entry in counts.iter() {
+        let (packed_bits, count) = (*entry.key(), entry.value().count() as u64);
+        let reverse = reverse
+            .get(&packed_bits)
+            .map_or(0, |count| count.count() as u64);
+        let class = &mut classes[gc(packed_bits, k)];
+        class.kmers += 1;
+        class.forward += count - reverse;
+        class.reverse += reverse;
+    }
+
+    classes
+}
+
+/// Writes one tab-separated row per GC content with any k-mers
+///
+/// # Notes
+/// `mean_count` against `gc_fraction` is the GC-bias curve: coverage should be
+/// flat across GC. `forward_fraction` should sit near 0.5 in every class;
+/// drifting away in some classes points at library preparation artifacts.
+pub(crate) fn write<W: Write>(out: &mut W, classes: &[GcClass]) -> io::Result<()> {
+    let k = classes.len() - 1;
+    writeln!(
+        out,
+        "gc\tgc_fraction\tkmers\tmean_count\tforward\treverse\tforward_fraction"
+    )?;
+    for (gc, class) in classes.iter().enumerate() {
+        if class.kmers == 0 {
+            continue;
+        }
+        writeln!(
+            out,
+            "{gc}\t{:.4}\t{}\t{:.4}\t{}\t{}\t{:.4}",
+            gc as f64 / k as f64,
+            class.kmers,
+            class.total() as f64 / class.kmers as f64,
+            class.forward,
+            class.reverse,
+            class.forward as f64 / class.total() as f64
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::run::KmerMap;
+    use bytes::Bytes;
+
+    #[test]
+    fn classes_split_strands_by_gc() {
+        let map = KmerMap::<u64>::new(true);
+        map.process_pieces(&[Bytes::from_static(b"ACGTNAAAT")], &3);
+
+        let classes = gc_classes(&map.counts, map.reverse.as_ref().unwrap(), 3);
+        let mut out = Vec::new();
+        write(&mut out, &classes).unwrap();
+        insta::assert_snapshot!(String::from_utf8(out).unwrap(), @r###"
+        gc	gc_fraction	kmers	mean_count	forward	reverse	forward_fraction
+        0	0.0000	2	1.0000	2	0	1.0000
+        2	0.6667	1	2.0000	1	1	0.5000
+        "###);
+    }
+}
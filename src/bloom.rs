@@ -0,0 +1,64 @@
+//! `--filter-singletons`: a Bloom filter of k-mers seen so far, so only those
+//! seen again need a place in the count map.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::{hasher, kmer::Packed};
+
+/// Filter bits per k-mer, for about a 1% false positive rate.
+const BITS_PER_KMER: usize = 10;
+
+/// Bits set per k-mer, the best for [`BITS_PER_KMER`].
+const HASHES: u64 = 7;
+
+/// A Bloom filter of packed k-mers, shared between threads
+pub(crate) struct Bloom {
+    words: Vec<AtomicU64>,
+}
+
+impl Bloom {
+    /// Sized for `kmers` distinct k-mers
+    pub(crate) fn new(kmers: usize) -> Self {
+        let words = (kmers.max(1) * BITS_PER_KMER).div_ceil(64);
+        Self {
+            words: (0..words).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    /// Adds a k-mer, returning whether it was, as far as the filter can tell,
+    /// already there
+    ///
+    /// # Notes
+    /// Bits are set one at a time, so two threads adding the same new k-mer
+    /// at once may both find it missing.
+    pub(crate) fn insert<P: Packed>(&self, packed_bits: P) -> bool {
+        let hash = fxhash::hash64(&packed_bits);
+        // Double hashing: bit `i` is at `h1 + i * h2`, with `h2` odd
+        let (h1, h2) = (hash, hasher::remix(hash) | 1);
+        let bits = self.words.len() as u64 * 64;
+
+        (0..HASHES).fold(true, |seen, i| {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) % bits;
+            let mask = 1 << (bit % 64);
+            let word = self.words[(bit / 64) as usize].fetch_or(mask, Ordering::Relaxed);
+            seen && word & mask != 0
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn insert_reports_kmers_seen_before() {
+        let bloom = Bloom::new(1000);
+        (0..1000u64).for_each(|packed_bits| _ = bloom.insert(packed_bits));
+        assert!((0..1000u64).all(|packed_bits| bloom.insert(packed_bits)));
+
+        let false_positives = (1000..1100u64)
+            .filter(|packed_bits| bloom.insert(*packed_bits))
+            .count();
+        assert!(false_positives < 10, "{false_positives} false positives");
+    }
+}
@@ -0,0 +1,201 @@
+//! `krust classify`: screens reads against reference sketches by containment.
+
+use std::{
+    error::Error,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use bytes::Bytes;
+use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+
+use super::{
+    kmer, samples,
+    sketch::{self, Sketch},
+};
+
+/// A reference's name and sketch.
+pub struct Reference {
+    pub name: String,
+    pub sketch: Sketch,
+}
+
+/// Sketches every file in `dir`, in name order
+pub fn references<P: AsRef<Path>>(
+    dir: P,
+    k: usize,
+    size: usize,
+) -> Result<Vec<Reference>, Box<dyn Error>> {
+    let mut paths = fs::read_dir(dir)?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<Result<Vec<PathBuf>, std::io::Error>>()?;
+    paths.retain(|path| path.is_file());
+    paths.sort();
+
+    let references = paths
+        .par_iter()
+        .map(|path| {
+            let sketch =
+                Sketch::from_path(path, k, size).map_err(|e| format!("{}: {e}", path.display()))?;
+            Ok(Reference {
+                name: samples::sample_name(path),
+                sketch,
+            })
+        })
+        .collect::<Result<_, String>>()?;
+
+    Ok(references)
+}
+
+/// The reference best containing a batch of reads.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Classification {
+    /// ID of the batch's first read
+    pub first: String,
+    pub reads: usize,
+    /// `None` when no reference shares a hash with the batch
+    pub reference: Option<String>,
+    pub containment: f64,
+}
+
+/// Classifies the reads of `path`, `batch_len` at a time, writing one
+/// tab-separated line per batch to `out`
+///
+/// # Notes
+/// A bottom-k reference sketch only says whether k-mers hashing below its
+/// largest hash are in the reference, so each batch keeps exactly those of
+/// its k-mers and is scored by the fraction found. Short reads have few, so a
+/// batch of one read is often left unclassified against small sketches.
+pub fn classify<P: AsRef<Path>, W: Write>(
+    path: P,
+    references: &[Reference],
+    batch_len: usize,
+    out: &mut W,
+) -> Result<(), Box<dyn Error>> {
+    let k = references.first().map_or(0, |reference| reference.sketch.k);
+    let mut reader = needletail::parse_fastx_file(path)?;
+    let mut batch = Vec::with_capacity(batch_len);
+    let mut first = None;
+
+    writeln!(out, "first\treads\treference\tcontainment")?;
+    loop {
+        let record = reader.next().transpose()?;
+        if let Some(record) = &record {
+            first.get_or_insert_with(|| read_id(record.id()));
+            batch.push(Bytes::from(record.seq().to_ascii_uppercase()));
+        }
+
+        if batch.len() == batch_len || (record.is_none() && !batch.is_empty()) {
+            let classification =
+                classify_batch(first.take().unwrap_or_default(), &batch, references, k);
+            write_classification(out, &classification)?;
+            batch.clear();
+        }
+
+        if record.is_none() {
+            return Ok(());
+        }
+    }
+}
+
+fn read_id(id: &[u8]) -> String {
+    String::from_utf8_lossy(id)
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn classify_batch(
+    first: String,
+    batch: &[Bytes],
+    references: &[Reference],
+    k: usize,
+) -> Classification {
+    let max = references
+        .iter()
+        .filter_map(|reference| reference.sketch.hashes.last())
+        .max()
+        .copied()
+        .unwrap_or_default();
+
+    let mut hashes = batch
+        .iter()
+        .flat_map(|seq| kmer::canonical_kmers(seq, k))
+        .map(|packed_bits| sketch::hash(packed_bits, k))
+        .filter(|hash| *hash <= max)
+        .collect::<Vec<_>>();
+    hashes.sort_unstable();
+    hashes.dedup();
+    let reads = Sketch {
+        k,
+        size: hashes.len(),
+        hashes,
+    };
+
+    let best = references
+        .iter()
+        .map(|reference| (reference, reads.containment(&reference.sketch)))
+        .filter(|(_, containment)| *containment > 0.0)
+        .fold(
+            None,
+            |best: Option<(&Reference, f64)>, candidate| match best {
+                Some(best) if best.1 >= candidate.1 => Some(best),
+                _ => Some(candidate),
+            },
+        );
+
+    Classification {
+        first,
+        reads: batch.len(),
+        reference: best.map(|(reference, _)| reference.name.clone()),
+        containment: best.map_or(0.0, |(_, containment)| containment),
+    }
+}
+
+fn write_classification<W: Write>(
+    out: &mut W,
+    classification: &Classification,
+) -> std::io::Result<()> {
+    writeln!(
+        out,
+        "{}\t{}\t{}\t{:.4}",
+        classification.first,
+        classification.reads,
+        classification
+            .reference
+            .as_deref()
+            .unwrap_or("unclassified"),
+        classification.containment
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn reference(name: &str, seq: &'static [u8]) -> Reference {
+        Reference {
+            name: name.to_string(),
+            sketch: Sketch::from_seqs(&[Bytes::from_static(seq)], 5, 1000),
+        }
+    }
+
+    #[test]
+    fn batches_go_to_the_reference_containing_them() {
+        let references = [
+            reference("a", b"ACGGTCAGTTCAGAGCTAGGCTTACCGATGCATTGACC"),
+            reference("c", b"CACACACACACACACA"),
+        ];
+
+        let reads = [Bytes::from_static(b"CAGAGCTAGGCTTAC")];
+        let classification = classify_batch("r1".into(), &reads, &references, 5);
+        assert_eq!(classification.reference.as_deref(), Some("a"));
+        assert_eq!(classification.containment, 1.0);
+
+        let reads = [Bytes::from_static(b"GGGGGGGGGG")];
+        let classification = classify_batch("r2".into(), &reads, &references, 5);
+        assert_eq!(classification.reference, None);
+    }
+}
@@ -1,18 +1,787 @@
-use clap::{Arg, Command};
+use std::path::PathBuf;
+
+use clap::{value_parser, Arg, ArgAction, Command};
+
+use crate::{
+    hasher::HasherKind,
+    histo::HistoFormat,
+    output::{Compression, OutputFormat},
+    progress::ProgressFormat,
+    run::Backend,
+    set_ops::SetOp,
+    sort::SortOrder,
+    suggest,
+};
 
 pub fn cli() -> Command {
     Command::new("krust")
-        .version("1.0")
+        .version(env!("CARGO_PKG_VERSION"))
         .author("Joseph L. <jlivesey@gmail.com>")
         .about("krust: counts k-mers, written in rust")
+        .args_conflicts_with_subcommands(true)
+        .subcommand_negates_reqs(true)
+        .subcommand(
+            Command::new("classify")
+                .about("reports the reference sketch best containing each batch of reads")
+                .arg(
+                    Arg::new("path")
+                        .help("reads as FASTA or FASTQ, optionally compressed")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("refs")
+                        .long("refs")
+                        .help("directory of reference FASTA files, one per reference")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("k")
+                        .short('k')
+                        .help("k-mer length")
+                        .default_value("21")
+                        .value_parser(value_parser!(u64).range(1..=32)),
+                )
+                .arg(
+                    Arg::new("sketch")
+                        .long("sketch")
+                        .help("hashes kept per reference; more classifies smaller batches")
+                        .default_value("10000")
+                        .value_parser(value_parser!(usize)),
+                )
+                .arg(
+                    Arg::new("batch")
+                        .long("batch")
+                        .help("reads classified together; 1 classifies each read")
+                        .default_value("1000")
+                        .value_parser(value_parser!(u64).range(1..)),
+                ),
+        )
+        .subcommand(
+            Command::new("cycles")
+                .about("writes k-mer composition at the start, middle and end of reads, to flag adapter read-through and chemistry artifacts")
+                .arg(
+                    Arg::new("path")
+                        .help("reads as FASTQ or FASTA, optionally compressed")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("k")
+                        .short('k')
+                        .help("k-mer length")
+                        .default_value("5")
+                        .value_parser(value_parser!(u64).range(1..=32)),
+                ),
+        )
+        .subcommand(
+            Command::new("diffkmers")
+                .about("counts k-mers per sample of a sample sheet and writes those differing between two of its groups, with fold changes and FDR")
+                .arg(
+                    Arg::new("samples")
+                        .long("samples")
+                        .help("tab-separated path, sample_name and group columns, one FASTA or FASTQ file per sample")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("groups")
+                        .long("groups")
+                        .help("the groups of the sample sheet to compare, as caseVScontrol")
+                        .required(true)
+                        .value_parser(value_parser!(String)),
+                )
+                .arg(
+                    Arg::new("k")
+                        .short('k')
+                        .help("k-mer length")
+                        .default_value("31")
+                        .value_parser(value_parser!(u64).range(1..=64)),
+                )
+                .arg(
+                    Arg::new("min-count")
+                        .long("min-count")
+                        .help("least count of a k-mer across the compared samples for it to be tested")
+                        .default_value("10")
+                        .value_parser(value_parser!(u64)),
+                )
+                .arg(
+                    Arg::new("fdr")
+                        .long("fdr")
+                        .help("highest Benjamini-Hochberg false discovery rate of a k-mer written")
+                        .default_value("0.05")
+                        .value_parser(value_parser!(f64)),
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .help("writes the k-mers to a file instead of stdout")
+                        .value_parser(value_parser!(PathBuf)),
+                ),
+        )
+        .subcommand(
+            Command::new("dist")
+                .about("writes the Mash distance, Jaccard index and shared hashes between the samples of two sketch or sequence files")
+                .arg(
+                    Arg::new("a")
+                        .help("a FASTA or FASTQ file, optionally compressed, or a .sig or .ksh sketch file")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("b")
+                        .help("another, each of whose samples is compared with each of the first's")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("k")
+                        .short('k')
+                        .help("k-mer length")
+                        .default_value("21")
+                        .value_parser(value_parser!(u64).range(1..=32)),
+                )
+                .arg(
+                    Arg::new("sketch")
+                        .long("sketch")
+                        .help("hashes kept per sequence file sketched; sketch files keep their own")
+                        .default_value("1000")
+                        .value_parser(value_parser!(usize)),
+                ),
+        )
+        .subcommand(
+            Command::new("dist-matrix")
+                .about("sketches every sample and writes all-vs-all Mash distances and a neighbour-joining tree")
+                .arg(
+                    Arg::new("paths")
+                        .help("FASTA or FASTQ files, optionally compressed, one per sample, or .sig signatures")
+                        .required_unless_present("samples")
+                        .num_args(1..)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("k")
+                        .short('k')
+                        .help("k-mer length")
+                        .default_value("21")
+                        .value_parser(value_parser!(u64).range(1..=32)),
+                )
+                .arg(
+                    Arg::new("sketch")
+                        .long("sketch")
+                        .help("hashes kept per sample; more is slower but more accurate")
+                        .default_value("10000")
+                        .value_parser(value_parser!(usize)),
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .help("writes the matrix to a file instead of stdout, and the tree beside it with a .nwk extension")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("tree")
+                        .long("tree")
+                        .help("writes the Newick tree to this file")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("samples")
+                        .long("samples")
+                        .help("tab-separated path, sample_name and group columns naming the samples in the matrix and tree, with a group column after the names; its paths are sketched if none are given")
+                        .value_parser(value_parser!(PathBuf)),
+                ),
+        )
+        .subcommand(
+            Command::new("estimate")
+                .about("estimates distinct and total canonical k-mers of an input with a HyperLogLog in one streaming pass, to size a full count")
+                .arg(
+                    Arg::new("k")
+                        .help("k-mer length")
+                        .required(true)
+                        .value_parser(value_parser!(u64).range(1..=32)),
+                )
+                .arg(
+                    Arg::new("path")
+                        .help("path to a FASTA or FASTQ file, optionally compressed, e.g. reads.fq.gz, or - for stdin")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("precision")
+                        .long("precision")
+                        .help("HyperLogLog registers as a power of two; each step up halves the variance of the estimate")
+                        .default_value("14")
+                        .value_parser(value_parser!(u8).range(4..=18)),
+                )
+                .arg(
+                    Arg::new("singletons")
+                        .long("singletons")
+                        .help("also estimates the k-mers seen only once, mostly sequencing errors, by counting 1 in 256 k-mers exactly, as KmerStream does")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("gather")
+                .about("breaks a sample down into the references it contains, by greedy containment of scaled sketches")
+                .arg(
+                    Arg::new("query")
+                        .help("the sample, as FASTA or FASTQ, optionally compressed, or a .sig signature")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("refs")
+                        .help("reference FASTA files, one per reference, or .sig signatures")
+                        .required(true)
+                        .num_args(1..)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("k")
+                        .short('k')
+                        .help("k-mer length")
+                        .default_value("31")
+                        .value_parser(value_parser!(u64).range(1..=32)),
+                )
+                .arg(
+                    Arg::new("scaled")
+                        .long("scaled")
+                        .help("keeps about one in this many k-mers of every sketch")
+                        .default_value("1000")
+                        .value_parser(value_parser!(u64).range(1..)),
+                )
+                .arg(
+                    Arg::new("threshold-bp")
+                        .long("threshold-bp")
+                        .help("stops once no reference explains this many more bases of the sample")
+                        .default_value("50000")
+                        .value_parser(value_parser!(u64)),
+                ),
+        )
+        .subcommand(
+            Command::new("histo")
+                .about("writes k-mer abundance histograms at several k, from one pass over the input, as a long-format table")
+                .arg(
+                    Arg::new("path")
+                        .help("path to a FASTA file")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("k")
+                        .long("k")
+                        .help("comma-separated k values, e.g. 17,21,25,31")
+                        .required(true)
+                        .value_delimiter(',')
+                        .value_parser(value_parser!(u64).range(1..=32)),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .help("a long table for any number of k, jellyfish histo's two columns for one k, e.g. for GenomeScope, or JSON with statistics")
+                        .default_value("long")
+                        .value_parser(HistoFormat::VARIANTS),
+                )
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .help("same as --format json: each k's histogram with its statistics, as one JSON object")
+                        .conflicts_with("format")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("low")
+                        .long("low")
+                        .short('l')
+                        .help("abundance of the first bin, which also holds any lower")
+                        .default_value("1")
+                        .value_parser(value_parser!(u64)),
+                )
+                .arg(
+                    Arg::new("high")
+                        .long("high")
+                        .short('H')
+                        .help("abundance past which k-mers share one last bin")
+                        .default_value("10000")
+                        .value_parser(value_parser!(u64)),
+                )
+                .arg(
+                    Arg::new("increment")
+                        .long("increment")
+                        .short('i')
+                        .help("bin width")
+                        .default_value("1")
+                        .value_parser(value_parser!(u64).range(1..)),
+                ),
+        )
+        .subcommand(
+            Command::new("minimizer-density")
+                .about("writes minimizer density and gaps along an assembly, flagging windows where minimizer-based mappers would find few anchors")
+                .arg(
+                    Arg::new("path")
+                        .help("path to an assembly, e.g. assembly.fa")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("k")
+                        .short('k')
+                        .help("minimizer length, as minimap2's -k")
+                        .default_value("15")
+                        .value_parser(value_parser!(u64).range(1..=64)),
+                )
+                .arg(
+                    Arg::new("w")
+                        .short('w')
+                        .help("k-mers per minimizer window, as minimap2's -w")
+                        .default_value("10")
+                        .value_parser(value_parser!(u64).range(1..)),
+                )
+                .arg(
+                    Arg::new("window")
+                        .long("window")
+                        .help("k-mer start positions per reported window")
+                        .default_value("10000")
+                        .value_parser(value_parser!(u64).range(1..)),
+                )
+                .arg(
+                    Arg::new("max-gap")
+                        .long("max-gap")
+                        .help("flags windows with a longer stretch than this without a minimizer, as well as those under half the density expected at random")
+                        .default_value("100")
+                        .value_parser(value_parser!(u64)),
+                ),
+        )
+        .subcommand(
+            Command::new("preview")
+                .about("summarizes read lengths, quality, GC and distinct k-mers from the start of an input plus a sample of the rest")
+                .arg(
+                    Arg::new("path")
+                        .help("path to a FASTA or FASTQ file, optionally compressed, e.g. reads.fq.gz")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("k")
+                        .short('k')
+                        .help("k for the distinct k-mer estimate")
+                        .default_value("21")
+                        .value_parser(value_parser!(u64).range(1..=32)),
+                )
+                .arg(
+                    Arg::new("head-mb")
+                        .long("head-mb")
+                        .help("megabytes of sequence to read from the start of the input")
+                        .default_value("16")
+                        .value_parser(value_parser!(usize)),
+                )
+                .arg(
+                    Arg::new("sample")
+                        .long("sample")
+                        .help("reads to sample from the rest of the input")
+                        .default_value("10000")
+                        .value_parser(value_parser!(usize)),
+                ),
+        )
+        .subcommand(
+            Command::new("repeats")
+                .about("writes BED intervals of a genome covered by high-copy k-mers, a quick repeat annotation")
+                .arg(
+                    Arg::new("path")
+                        .help("path to a genome, e.g. genome.fa")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("k")
+                        .short('k')
+                        .help("k-mer length")
+                        .default_value("31")
+                        .value_parser(value_parser!(u64).range(1..=64)),
+                )
+                .arg(
+                    Arg::new("min-count")
+                        .long("min-count")
+                        .help("least genome-wide count of a k-mer for its span to be reported, or to join a seed")
+                        .default_value("100")
+                        .value_parser(value_parser!(i32).range(2..)),
+                )
+                .arg(
+                    Arg::new("seeds")
+                        .long("seeds")
+                        .help("writes FASTA of candidate repeat elements, grown from the most abundant k-mers through their de Bruijn successors and predecessors, instead of BED intervals")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("min-length")
+                        .long("min-length")
+                        .help("shortest seed written, in bases")
+                        .requires("seeds")
+                        .default_value("100")
+                        .value_parser(value_parser!(usize)),
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .help("writes the BED intervals, or FASTA seeds, to a file instead of stdout")
+                        .value_parser(value_parser!(PathBuf)),
+                ),
+        )
+        .subcommand(
+            Command::new("set-op")
+                .about("combines two --format raw count tables with the same k into a new one, sorted by k-mer")
+                .arg(
+                    Arg::new("op")
+                        .help("union sums counts, intersect keeps the lesser count of shared k-mers, subtract keeps the first table's k-mers missing from the second")
+                        .required(true)
+                        .value_parser(SetOp::VARIANTS),
+                )
+                .arg(
+                    Arg::new("a")
+                        .help("first raw count table")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("b")
+                        .help("second raw count table")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .help("writes the combined table to a file instead of stdout")
+                        .value_parser(value_parser!(PathBuf)),
+                ),
+        )
+        .subcommand(
+            Command::new("sketch")
+                .about("writes MinHash sketches of each input as sourmash signatures, for gather, dist, dist-matrix or sourmash")
+                .arg(
+                    Arg::new("paths")
+                        .help("FASTA or FASTQ files, optionally compressed, one per sample")
+                        .required_unless_present("samples")
+                        .num_args(1..)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("k")
+                        .short('k')
+                        .help("k-mer length")
+                        .default_value("31")
+                        .value_parser(value_parser!(u64).range(1..=32)),
+                )
+                .arg(
+                    Arg::new("scaled")
+                        .long("scaled")
+                        .help("keeps about one in this many k-mers, for gather")
+                        .default_value("1000")
+                        .value_parser(value_parser!(u64).range(1..)),
+                )
+                .arg(
+                    Arg::new("num")
+                        .long("num")
+                        .help("keeps this many of the smallest hashes instead, for dist or dist-matrix")
+                        .conflicts_with("scaled")
+                        .value_parser(value_parser!(usize)),
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .help("writes the signatures to a file instead of stdout, e.g. samples.sig, or in the binary format to a .ksh file")
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("samples")
+                        .long("samples")
+                        .help("tab-separated path, sample_name and group columns naming each signature by sample instead of file; its paths are sketched if none are given")
+                        .value_parser(value_parser!(PathBuf)),
+                ),
+        )
+        .subcommand(
+            Command::new("info")
+                .about("describes this build of krust")
+                .arg(
+                    Arg::new("build")
+                        .long("build")
+                        .help("writes version, git hash, target, profile, rustc and enabled features as JSON, for bug reports and provenance")
+                        .required(true)
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("suggest-k")
+                .about("suggests a k for a genome by counting a sample of its reads at several k")
+                .arg(
+                    Arg::new("genome-size")
+                        .help("estimated genome size in bases, e.g. 4.6m or 3.1g")
+                        .required(true)
+                        .value_parser(suggest::parse_genome_size),
+                )
+                .arg(
+                    Arg::new("path")
+                        .help("path to a FASTA or FASTQ file, optionally compressed, e.g. reads.fq.gz")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf)),
+                )
+                .arg(
+                    Arg::new("head-mb")
+                        .long("head-mb")
+                        .help("megabytes of sequence to read from the start of the input")
+                        .default_value("16")
+                        .value_parser(value_parser!(usize)),
+                )
+                .arg(
+                    Arg::new("sample")
+                        .long("sample")
+                        .help("reads to sample from the rest of the input")
+                        .default_value("10000")
+                        .value_parser(value_parser!(usize)),
+                ),
+        )
         .arg(
             Arg::new("k")
-                .help("provides k length, e.g. 5")
+                .help("provides k length, from 1 to 64, e.g. 5")
                 .required(true),
         )
         .arg(
             Arg::new("path")
-                .help("path to a FASTA file, e.g. /home/lisa/bio/cerevisiae.pan.fa")
+                .help("paths to FASTA or FASTQ files, optionally gzipped, or pipes, or - for stdin, whose counts are merged, e.g. /home/lisa/bio/cerevisiae.pan.fa")
+                .num_args(1..)
                 .required(true),
         )
+        .arg(
+            Arg::new("strand")
+                .long("strand")
+                .visible_alias("by-strand")
+                .help("appends forward and reverse strand occurrence counts to each k-mer's count")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("report-palindromes")
+                .long("report-palindromes")
+                .help("only outputs k-mers equal to their reverse complement (requires an even k)")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("lower-count")
+                .short('L')
+                .long("lower-count")
+                .help("only outputs k-mers seen at least this many times, as jellyfish dump -L")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            Arg::new("upper-count")
+                .short('U')
+                .long("upper-count")
+                .help("only outputs k-mers seen at most this many times, as jellyfish dump -U")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            Arg::new("paired")
+                .long("paired")
+                .help("reads inputs as pairs of mate files, R1 then R2, checking that their reads pair up name for name before counting both mates")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("dedup-exact")
+                .long("dedup-exact")
+                .help("counts each distinct read sequence once, dropping exact duplicates such as PCR duplicates in amplicon data, and reports the duplicate fraction on stderr")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("trim-quality")
+                .long("trim-quality")
+                .value_name("Q")
+                .help("trims each FASTQ read's 3' end at Phred score Q, as bwa aln -q does, before counting its k-mers")
+                .value_parser(value_parser!(u8).range(0..=93)),
+        )
+        .arg(
+            Arg::new("filter-singletons")
+                .long("filter-singletons")
+                .help("only counts k-mers seen at least twice, passing k-mers through a Bloom filter first so those seen once, mostly sequencing errors, never take up memory")
+                .conflicts_with_all(["distinct", "max-memory"])
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("singleton-spill")
+                .long("singleton-spill")
+                .help("moves k-mers counted once so far to a temporary file whenever the count map doubles, keeping memory for repeated k-mers, and adds them back to the output with exact counts")
+                .conflicts_with_all(["distinct", "max-memory", "filter-singletons", "strand", "bias-report"])
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("max-memory")
+                .long("max-memory")
+                .help("counts through temporary bucket files, holding about this many MB of counts at once, for inputs with too many distinct k-mers for RAM")
+                .conflicts_with_all(["bias-report", "progress", "progress-format", "paired", "dedup-exact"])
+                .value_parser(value_parser!(u64).range(1..)),
+        )
+        .arg(
+            Arg::new("distinct")
+                .long("distinct")
+                .help("outputs only which canonical k-mers occur, one per line, from a set rather than a count map")
+                .conflicts_with_all(["strand", "lower-count", "upper-count", "format", "with-packed", "bias-report"])
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("stride")
+                .long("stride")
+                .help("counts only every S-th k-mer window of each record, starting with the first")
+                .value_parser(value_parser!(u64).range(1..)),
+        )
+        .arg(
+            Arg::new("minimizer-window")
+                .long("minimizer-window")
+                .help("counts only (k, W) minimizers, the canonical k-mer of least hash in each W consecutive k-mers, each once")
+                .conflicts_with("stride")
+                .value_parser(value_parser!(u64).range(1..)),
+        )
+        .arg(
+            Arg::new("no-canonical")
+                .long("no-canonical")
+                .help("counts k-mers as read, without merging each with its reverse complement, for strand-specific libraries")
+                .conflicts_with_all(["strand", "bias-report", "minimizer-window", "per-record", "summary-per-record"])
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("output")
+                .short('o')
+                .long("output")
+                .help("writes counts to a file instead of stdout")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .help("output format")
+                .value_parser(OutputFormat::VARIANTS)
+                .default_value("fasta"),
+        )
+        .arg(
+            Arg::new("delimiter")
+                .long("delimiter")
+                .help("column separator for tsv and csv output, e.g. ';'")
+                .value_parser(value_parser!(char)),
+        )
+        .arg(
+            Arg::new("with-packed")
+                .long("with-packed")
+                .help("adds each canonical k-mer's packed integer as a final tsv or csv column, or as a json string")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("json-flat")
+                .long("json-flat")
+                .help("writes json output as a bare array of records, without run metadata")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("fasta-header")
+                .long("fasta-header")
+                .help("starts fasta output with a ;-comment line of k, distinct k-mers and version")
+                .conflicts_with("distinct")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("compress")
+                .long("compress")
+                .help("compresses output, by default inferred from a .gz or .zst --output extension")
+                .value_parser(Compression::VARIANTS),
+        )
+        .arg(
+            Arg::new("sort")
+                .long("sort")
+                .help("sorts output, spilling to temporary files when results are large")
+                .value_parser(SortOrder::VARIANTS),
+        )
+        .arg(
+            Arg::new("progress")
+                .long("progress")
+                .help("reports sequences, bases and distinct k-mers counted so far on stderr")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("progress-format")
+                .long("progress-format")
+                .help("reports progress as a redrawn line (human) or one JSON object per line (json); implies --progress")
+                .value_parser(ProgressFormat::VARIANTS),
+        )
+        .arg(
+            Arg::new("deterministic")
+                .long("deterministic")
+                .help("makes output byte-identical across runs, sorting by k-mer unless --sort is given and leaving out timings")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("summary-per-record")
+                .long("summary-per-record")
+                .help("writes each record's length, GC content, k-mers and distinct k-mers instead of counts, as a QC sheet")
+                .conflicts_with_all(["distinct", "format", "sort", "with-packed", "json-flat", "fasta-header", "strand", "lower-count", "upper-count", "report-palindromes", "max-memory", "backend"])
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("per-record")
+                .long("per-record")
+                .help("counts each record's k-mers apart, writing record, k-mer and count lines")
+                .conflicts_with_all(["summary-per-record", "distinct", "format", "sort", "with-packed", "json-flat", "fasta-header", "strand", "lower-count", "upper-count", "report-palindromes", "max-memory", "backend"])
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("only-records")
+                .long("only-records")
+                .help("only counts the FASTA records with these comma-separated IDs, e.g. chr1,chrX")
+                .value_delimiter(','),
+        )
+        .arg(
+            Arg::new("records-file")
+                .long("records-file")
+                .help("only counts the FASTA records whose IDs are listed, one per line, in this file"),
+        )
+        .arg(
+            Arg::new("report")
+                .long("report")
+                .help("writes distinct k-mers, map memory and peak RSS to stderr after the run; map memory needs --features alloc-stats")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("bias-report")
+                .long("bias-report")
+                .help("writes mean count and forward strand fraction by k-mer GC content to this file, to reveal GC and strand bias")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            Arg::new("hasher")
+                .long("hasher")
+                .help("hashes packed k-mers with FxHash (fx) or by remixing their bits with one multiply, a bijection so no two k-mers up to k = 32 collide (identity)")
+                .default_value("fx")
+                .value_parser(HasherKind::VARIANTS),
+        )
+        .arg(
+            Arg::new("backend")
+                .long("backend")
+                .help("counts in a DashMap (dashmap, the default) or, for k up to 32 with --features compact, in a table of one 8-byte slot per k-mer taking about half the memory (compact)")
+                .conflicts_with_all(["strand", "bias-report", "filter-singletons", "singleton-spill", "max-memory", "distinct"])
+                .value_parser(Backend::VARIANTS),
+        )
+        .arg(
+            Arg::new("expected-kmers")
+                .long("expected-kmers")
+                .help("sizes the count map for this many distinct k-mers up front, avoiding repeated resizing as it grows")
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("mmap")
+                .long("mmap")
+                .help("memory-maps plain FASTA input and counts records in place instead of copying them; compressed or FASTQ input is read as without it")
+                .action(ArgAction::SetTrue),
+        )
 }
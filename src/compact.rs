@@ -0,0 +1,396 @@
+//! `--backend compact`: a count table for k-mers of up to 32 bases holding
+//! each in one `u64` slot, about half what the `DashMap` backend takes.
+//!
+//! K-mers are mixed by an invertible hash, whose top bits pick a shard and
+//! then a bucket of [`BUCKET_SLOTS`] slots. As in a quotient filter, a slot
+//! stores only the remainder of the mixed k-mer, the bits its shard and
+//! bucket don't already say, and the k-mer's count in the bits left over.
+//! Counts too large for those bits are kept in a small map beside the slots,
+//! as are k-mers whose bucket is full, and a shard doubles its buckets once
+//! too many of those pile up, each remainder giving up a bit to the count.
+
+use std::{
+    collections::hash_map::Entry,
+    mem,
+    sync::{Mutex, PoisonError},
+};
+
+use fxhash::FxHashMap;
+
+use super::kmer::Packed;
+
+/// Shards, each behind its own lock, as a power of two.
+const SHARD_BITS: u32 = 6;
+
+/// Slots per bucket, two cache lines of them.
+const BUCKET_SLOTS: usize = 16;
+
+/// Buckets per shard to start with, as a power of two, 2 MB of slots across
+/// all shards.
+const INITIAL_BUCKET_BITS: u32 = 8;
+
+/// A shard grows once it has stashed one k-mer, for want of room in its
+/// bucket, for every this many slots, with about four in five slots full.
+const STASH_RATIO: usize = 32;
+
+/// Odd multipliers of the invertible mix, from SplitMix64.
+const MIX_MULTIPLIERS: [u64; 2] = [0x9e37_79b9_7f4a_7c15, 0xbf58_476d_1ce4_e5b9];
+
+/// `bits` low bits set
+fn low_mask(bits: u32) -> u64 {
+    u64::MAX.checked_shr(64 - bits).unwrap_or(0)
+}
+
+/// The inverse of an odd `n` modulo `2^64`, by Newton's method, each step
+/// doubling the low bits that are right
+const fn inverse(n: u64) -> u64 {
+    // Odd numbers are their own inverses modulo 8
+    let mut inverse = n;
+    let mut i = 0;
+    while i < 5 {
+        inverse = inverse.wrapping_mul(2u64.wrapping_sub(n.wrapping_mul(inverse)));
+        i += 1;
+    }
+    inverse
+}
+
+/// A bijection on `bits`-bit integers spreading packed k-mers, whose high
+/// bits are otherwise the first bases of each, evenly over shards and
+/// buckets
+#[derive(Debug, Clone, Copy)]
+struct Mixer {
+    mask: u64,
+    shift: u32,
+}
+
+impl Mixer {
+    fn new(bits: u32) -> Self {
+        Self {
+            mask: low_mask(bits),
+            // At least half the bits, so the xorshift undoes itself
+            shift: bits.div_ceil(2),
+        }
+    }
+
+    fn mix(&self, packed_bits: u64) -> u64 {
+        let [a, b] = MIX_MULTIPLIERS;
+        let x = packed_bits.wrapping_mul(a) & self.mask;
+        (x ^ x >> self.shift).wrapping_mul(b) & self.mask
+    }
+
+    fn unmix(&self, mixed: u64) -> u64 {
+        let [a, b] = MIX_MULTIPLIERS.map(inverse);
+        let x = mixed.wrapping_mul(b) & self.mask;
+        (x ^ x >> self.shift).wrapping_mul(a) & self.mask
+    }
+}
+
+/// Counts k-mers of up to 32 bases, packed, in one slot each.
+#[derive(Debug)]
+pub struct CompactCounts {
+    mixer: Mixer,
+    /// Bits of a mixed k-mer below those picking its shard
+    rest_bits: u32,
+    shards: Vec<Mutex<Shard>>,
+}
+
+impl CompactCounts {
+    /// A table for `k`-mers with room for `capacity` distinct ones before
+    /// growing
+    ///
+    /// # Panics
+    /// If `k` is over 32.
+    pub fn new(k: usize, capacity: usize) -> Self {
+        assert!(
+            k <= u64::MAX_K,
+            "compact counts hold k-mers of at most 32 bases"
+        );
+
+        let key_bits = 2 * k as u32;
+        let shard_bits = SHARD_BITS.min(key_bits);
+        let rest_bits = key_bits - shard_bits;
+        // Filling buckets about halfway, where a shard is about to double
+        let buckets = capacity.div_ceil(BUCKET_SLOTS << shard_bits) * 2;
+        let bucket_bits = (buckets.next_power_of_two().trailing_zeros())
+            .max(INITIAL_BUCKET_BITS)
+            .min(rest_bits);
+
+        Self {
+            mixer: Mixer::new(key_bits),
+            rest_bits,
+            shards: (0..1 << shard_bits)
+                .map(|_| Mutex::new(Shard::new(rest_bits, bucket_bits)))
+                .collect(),
+        }
+    }
+
+    /// Distinct k-mers counted
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| lock(shard).len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Counts a batch of packed k-mers, ignoring whether each was found as
+    /// its reverse complement, leaving it empty
+    ///
+    /// # Notes
+    /// As `KmerMap::insert_batch` does, the batch is sorted by shard so each
+    /// shard's lock is taken once per batch.
+    pub(crate) fn increment_batch(&self, batch: &mut Vec<(u64, bool)>) {
+        let mut mixed = batch
+            .drain(..)
+            .map(|(packed_bits, _)| self.mixer.mix(packed_bits))
+            .collect::<Vec<_>>();
+        mixed.sort_unstable();
+
+        let shard_of = |mixed: &u64| mixed.checked_shr(self.rest_bits).unwrap_or(0) as usize;
+        for group in mixed.chunk_by(|a, b| shard_of(a) == shard_of(b)) {
+            let mut shard = lock(&self.shards[shard_of(&group[0])]);
+            for mixed in group {
+                shard.increment(mixed & low_mask(self.rest_bits))
+            }
+        }
+    }
+
+    /// Every packed k-mer counted, with its count, in no particular order
+    pub fn into_counts(self) -> impl Iterator<Item = (u64, i32)> {
+        let Self {
+            mixer,
+            rest_bits,
+            shards,
+        } = self;
+
+        shards.into_iter().enumerate().flat_map(move |(i, shard)| {
+            let shard = shard.into_inner().unwrap_or_else(PoisonError::into_inner);
+            let high = (i as u64).checked_shl(rest_bits).unwrap_or(0);
+            shard.into_entries().map(move |(rest, count)| {
+                (mixer.unmix(high | rest), count.min(i32::MAX as u32) as i32)
+            })
+        })
+    }
+}
+
+/// A shard's table, whose lock is only poisoned by a panic mid-count
+fn lock(shard: &Mutex<Shard>) -> std::sync::MutexGuard<'_, Shard> {
+    shard.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Buckets of slots for the k-mers of one shard, each slot either zero or a
+/// k-mer's remainder above its count, which is never zero.
+#[derive(Debug)]
+struct Shard {
+    /// Bits of a k-mer's rest, below its shard bits
+    rest_bits: u32,
+    /// Top bits of a k-mer's rest picking its bucket; the rest's other bits
+    /// are its remainder
+    bucket_bits: u32,
+    slots: Vec<u64>,
+    /// Counts of k-mers whose slot's count is saturated, by rest
+    overflow: FxHashMap<u64, u32>,
+    /// Counts of k-mers found when their bucket was full, by rest
+    stash: FxHashMap<u64, u32>,
+    len: usize,
+}
+
+impl Shard {
+    fn new(rest_bits: u32, bucket_bits: u32) -> Self {
+        Self {
+            rest_bits,
+            bucket_bits,
+            slots: vec![0; BUCKET_SLOTS << bucket_bits],
+            overflow: Default::default(),
+            stash: Default::default(),
+            len: 0,
+        }
+    }
+
+    fn remainder_bits(&self) -> u32 {
+        self.rest_bits - self.bucket_bits
+    }
+
+    fn count_bits(&self) -> u32 {
+        64 - self.remainder_bits()
+    }
+
+    /// The count a slot holds for a k-mer whose count is in `overflow`
+    fn saturated(&self) -> u64 {
+        low_mask(self.count_bits().min(32))
+    }
+
+    /// The bucket's slots and remainder of a k-mer's rest
+    fn locate(&self, rest: u64) -> (std::ops::Range<usize>, u64) {
+        let bucket = (rest >> self.remainder_bits()) as usize * BUCKET_SLOTS;
+        (
+            bucket..bucket + BUCKET_SLOTS,
+            rest & low_mask(self.remainder_bits()),
+        )
+    }
+
+    fn increment(&mut self, rest: u64) {
+        let (bucket, remainder) = self.locate(rest);
+        let count_bits = self.count_bits();
+        let saturated = self.saturated();
+
+        for slot in &mut self.slots[bucket] {
+            if *slot == 0 {
+                *slot = remainder.checked_shl(count_bits).unwrap_or(0) | 1;
+                self.len += 1;
+                return;
+            }
+            if slot.checked_shr(count_bits).unwrap_or(0) != remainder {
+                continue;
+            }
+            match (*slot & saturated) + 1 {
+                count if count < saturated => *slot += 1,
+                count if count == saturated => {
+                    *slot += 1;
+                    self.overflow.insert(rest, saturated as u32);
+                }
+                _ => {
+                    let count = self.overflow.get_mut(&rest).expect("saturated");
+                    *count = count.saturating_add(1)
+                }
+            }
+            return;
+        }
+
+        match self.stash.entry(rest) {
+            Entry::Occupied(mut count) => *count.get_mut() = count.get().saturating_add(1),
+            Entry::Vacant(count) => {
+                count.insert(1);
+                self.len += 1;
+                if self.remainder_bits() > 0 && self.stash.len() * STASH_RATIO > self.slots.len() {
+                    self.grow()
+                }
+            }
+        }
+    }
+
+    /// Puts a k-mer not yet in the shard in its bucket, or the stash
+    fn place(&mut self, rest: u64, count: u32) {
+        let (bucket, remainder) = self.locate(rest);
+        let count_bits = self.count_bits();
+        let saturated = self.saturated();
+        let Some(slot) = self.slots[bucket].iter_mut().find(|slot| **slot == 0) else {
+            self.stash.insert(rest, count);
+            return;
+        };
+
+        *slot = remainder.checked_shl(count_bits).unwrap_or(0) | saturated.min(count as u64);
+        if count as u64 >= saturated {
+            self.overflow.insert(rest, count);
+        }
+    }
+
+    /// Doubles the buckets, moving each k-mer to the one its rest now picks
+    fn grow(&mut self) {
+        let grown = Shard::new(self.rest_bits, self.bucket_bits + 1);
+        let old = mem::replace(self, grown);
+        self.len = old.len;
+        for (rest, count) in old.into_entries() {
+            self.place(rest, count)
+        }
+    }
+
+    /// Each k-mer's rest and count
+    fn into_entries(self) -> impl Iterator<Item = (u64, u32)> {
+        let remainder_bits = self.remainder_bits();
+        let count_bits = self.count_bits();
+        let saturated = self.saturated();
+        let Self {
+            slots,
+            overflow,
+            stash,
+            ..
+        } = self;
+
+        slots
+            .into_iter()
+            .enumerate()
+            .filter(|(_, slot)| *slot != 0)
+            .map(move |(i, slot)| {
+                let bucket = (i / BUCKET_SLOTS) as u64;
+                let rest = bucket << remainder_bits | slot.checked_shr(count_bits).unwrap_or(0);
+                let count = match slot & saturated {
+                    count if count == saturated => overflow[&rest],
+                    count => count as u32,
+                };
+                (rest, count)
+            })
+            .chain(stash)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    /// Counts `kmers` both compactly and in a `HashMap`, sorted for comparing
+    fn counted(k: usize, kmers: impl Iterator<Item = u64>) -> [Vec<(u64, i32)>; 2] {
+        let compact = CompactCounts::new(k, 0);
+        let mut expected = HashMap::<u64, i32>::new();
+        let mut batch = Vec::new();
+        for packed_bits in kmers {
+            batch.push((packed_bits, false));
+            *expected.entry(packed_bits).or_default() += 1;
+            if batch.len() == 1000 {
+                compact.increment_batch(&mut batch)
+            }
+        }
+        compact.increment_batch(&mut batch);
+        assert_eq!(compact.len(), expected.len());
+
+        [
+            compact.into_counts().collect(),
+            expected.into_iter().collect(),
+        ]
+        .map(|mut counts: Vec<_>| {
+            counts.sort_unstable();
+            counts
+        })
+    }
+
+    #[test]
+    fn mixing_is_undone() {
+        for bits in [2, 10, 42, 62, 64] {
+            let mixer = Mixer::new(bits);
+            for packed_bits in [0, 1, 12345, u64::MAX].map(|n| n & low_mask(bits)) {
+                assert_eq!(mixer.unmix(mixer.mix(packed_bits)), packed_bits);
+            }
+        }
+    }
+
+    #[test]
+    fn counts_as_a_hash_map_does_while_growing() {
+        let mut state = 1u64;
+        let mut random = move || {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            state
+        };
+
+        for k in [3usize, 21, 32] {
+            // Enough distinct k-mers, each counted about twice, to grow every
+            // shard at least once
+            let kmers =
+                (0..600_000).map(|_| (random() % 300_000 * 0x9e37_79b9) & low_mask(2 * k as u32));
+            let [compact, expected] = counted(k, kmers);
+            assert_eq!(compact, expected, "k = {k}");
+        }
+    }
+
+    #[test]
+    fn saturated_counts_overflow() {
+        // At k = 32 a slot starts with 14 bits of count
+        let kmers = [u64::MAX, 0, 7].into_iter().cycle().take(3 * 40_000);
+        let [compact, expected] = counted(32, kmers);
+        assert_eq!(compact, expected);
+        assert_eq!(compact[0], (0, 40_000));
+    }
+}
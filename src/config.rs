@@ -1,25 +1,152 @@
-use std::{error::Error, fs, path::PathBuf};
+use std::{collections::HashSet, error::Error, fs, path::PathBuf};
 
+#[cfg(feature = "cli")]
 use colored::Colorize;
 
+use crate::{
+    hasher::HasherKind,
+    kmer::Packed,
+    output::{Compression, OutputFormat},
+    progress::ProgressFormat,
+    reader,
+    run::Backend,
+    sort::SortOrder,
+};
+
+#[derive(Debug, Default)]
 pub struct Config {
     pub k: usize,
-    pub path: PathBuf,
+    /// Inputs whose counts are merged, in the order given
+    pub paths: Vec<PathBuf>,
+    /// Also count how often each canonical k-mer was seen on each strand
+    pub strand: bool,
+    /// Only output k-mers that are their own reverse complement
+    pub report_palindromes: bool,
+    /// Only output k-mers seen at least this many times, as `jellyfish dump -L`
+    pub lower_count: Option<u64>,
+    /// Only output k-mers seen at most this many times, as `jellyfish dump -U`
+    pub upper_count: Option<u64>,
+    /// Count only k-mers seen at least twice, screening out the rest with a
+    /// Bloom filter before they reach the count map
+    pub filter_singletons: bool,
+    /// Move k-mers counted once so far to disk every so often while
+    /// counting, adding them back when writing
+    pub singleton_spill: bool,
+    /// Count in buckets on disk, each counted in about this many bytes,
+    /// streaming the inputs through rather than reading them in; `paired`
+    /// and `dedup_exact`, which need every read at once, are ignored
+    pub max_memory: Option<usize>,
+    /// Output only which k-mers occur, from a set rather than a count map
+    pub distinct: bool,
+    /// Count only every this many-th k-mer window of each record
+    pub stride: Option<usize>,
+    /// Count k-mers as read, for strand-specific libraries, rather than as
+    /// the lesser of each and its reverse complement
+    pub no_canonical: bool,
+    /// Count only the canonical minimizers of each this many consecutive
+    /// k-mers
+    pub minimizer_window: Option<usize>,
+    pub format: OutputFormat,
+    /// Column separator for tabular formats, defaulting to the format's own
+    pub delimiter: Option<char>,
+    /// Add each k-mer's packed `u64` as a final column of tabular formats
+    pub with_packed: bool,
+    /// Write JSON as a bare array of records, without run metadata
+    pub json_flat: bool,
+    /// Start FASTA output with a `;` comment line of run statistics
+    pub fasta_header: bool,
+    /// Write counts here instead of `stdout`
+    pub output: Option<PathBuf>,
+    pub compression: Compression,
+    /// Report counting progress on `stderr`
+    pub progress: bool,
+    pub progress_format: ProgressFormat,
+    /// Make output byte-identical across runs: sorted, and without timings
+    pub deterministic: bool,
+    /// Sort output, spilling to temporary files for large results
+    pub sort: Option<SortOrder>,
+    /// Write one summary line per input record instead of k-mer counts
+    pub summary_per_record: bool,
+    /// Count each record's k-mers apart instead of across the whole input
+    pub per_record: bool,
+    /// Inputs are pairs of mate files, whose reads are checked to pair up
+    pub paired: bool,
+    /// Count each distinct read sequence once, dropping exact duplicates
+    pub dedup_exact: bool,
+    /// Trim each FASTQ read's 3' end at this Phred score before counting
+    pub trim_quality: Option<u8>,
+    /// Only count the FASTA records with these IDs
+    pub records: Option<HashSet<String>>,
+    /// Write a run summary, including memory use, to `stderr`
+    pub report: bool,
+    /// Write strand and GC bias by k-mer GC content here
+    pub bias_report: Option<PathBuf>,
+    pub hasher: HasherKind,
+    /// Count in a `DashMap`, or in a compact table with the `compact` feature
+    pub backend: Backend,
+    /// Distinct k-mers to size the count map for up front
+    pub expected_kmers: Option<usize>,
+    /// Memory-map the input and count records in place, without copying
+    pub mmap: bool,
 }
 
 impl Config {
-    pub fn new(k: &str, path: &str) -> Result<Config, Box<dyn Error>> {
+    pub fn new(k: &str, paths: &[String]) -> Result<Config, Box<dyn Error>> {
         let k: usize = match k.parse::<usize>() {
-            Ok(k) if k > 0 && k < 33 => k,
-            Ok(_) => return Err("k-mer length needs to be larger than zero and, for krust currently, no more than 32".into()),
-            Err(_) => return Err(format!("Issue with k-mer length argument \"{}\"", k.bold()).into()),
+            Ok(k) if k > 0 && k <= u128::MAX_K => k,
+            Ok(_) => return Err("k-mer length needs to be larger than zero and, for krust currently, no more than 64".into()),
+            Err(_) => return Err(format!("Issue with k-mer length argument \"{}\"", bold(k)).into()),
         };
 
-        let path = match fs::metadata(path) {
-            Ok(_) => path.into(),
-            Err(e) => return Err(format!("Issue with file path: {}", e.to_string().bold()).into()),
+        if paths.iter().filter(|path| *path == reader::STDIN).count() > 1 {
+            return Err(format!(
+                "Standard input, \"{}\", can only be read once",
+                reader::STDIN
+            )
+            .into());
+        }
+        let paths = paths
+            .iter()
+            .map(|path| match fs::metadata(path) {
+                _ if path == reader::STDIN => Ok(path.into()),
+                Ok(_) => Ok(path.into()),
+                Err(e) => Err(format!(
+                    "Issue with file path {}: {}",
+                    bold(path),
+                    bold(&e.to_string())
+                )),
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Config {
+            k,
+            paths,
+            ..Default::default()
+        })
+    }
+
+    /// Reads record IDs, one per line, ignoring blank lines and anything
+    /// after the first whitespace as FASTA headers do
+    pub fn read_record_ids(path: &str) -> Result<HashSet<String>, Box<dyn Error>> {
+        let ids = match fs::read_to_string(path) {
+            Ok(ids) => ids,
+            Err(e) => {
+                return Err(format!("Issue with records file: {}", bold(&e.to_string())).into())
+            }
         };
 
-        Ok(Config { k, path })
+        Ok(ids
+            .lines()
+            .filter_map(|line| line.split_whitespace().next())
+            .map(String::from)
+            .collect())
     }
 }
+
+/// Emphasizes part of an error message, in bold when built for the terminal
+fn bold(s: &str) -> String {
+    #[cfg(feature = "cli")]
+    return s.bold().to_string();
+    #[cfg(not(feature = "cli"))]
+    return s.to_string();
+}
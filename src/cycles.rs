@@ -0,0 +1,90 @@
+//! `krust cycles`: k-mer composition by read cycle, for spotting adapter
+//! read-through and chemistry artifacts.
+
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    io::{self, Write},
+    path::Path,
+};
+
+use bytes::Bytes;
+
+use super::kmer;
+
+/// Thirds of a read, by k-mer start position.
+pub const BINS: [&str; 3] = ["start", "middle", "end"];
+
+/// K-mer counts per bin of [`BINS`], as read rather than canonical, since
+/// which strand an artifact appears on matters.
+pub type CycleCounts = [BTreeMap<u64, u64>; 3];
+
+/// Tallies the k-mers of every read in `path` by the third of the read they
+/// start in
+pub fn cycle_counts<P: AsRef<Path>>(path: P, k: usize) -> Result<CycleCounts, Box<dyn Error>> {
+    let mut reader = needletail::parse_fastx_file(path)?;
+    let mut counts = CycleCounts::default();
+
+    while let Some(record) = reader.next() {
+        let seq = Bytes::from(record?.seq().to_ascii_uppercase());
+        tally(&mut counts, &seq, k);
+    }
+
+    Ok(counts)
+}
+
+fn tally(counts: &mut CycleCounts, seq: &Bytes, k: usize) {
+    let Some(positions) = (seq.len() + 1).checked_sub(k) else {
+        return;
+    };
+    for (start, packed_bits) in kmer::positioned_kmers(seq, k) {
+        *counts[BINS.len() * start / positions]
+            .entry(packed_bits)
+            .or_insert(0) += 1
+    }
+}
+
+/// Writes a tidy `bin`, `kmer`, `count`, `fraction` table, where `fraction`
+/// is of all k-mers in the bin; a k-mer whose fraction climbs towards the
+/// end of reads is a likely adapter
+pub fn write<W: Write>(out: &mut W, counts: &CycleCounts, k: usize) -> io::Result<()> {
+    writeln!(out, "bin\tkmer\tcount\tfraction")?;
+    for (bin, counts) in BINS.iter().zip(counts) {
+        let total = counts.values().sum::<u64>() as f64;
+        for (packed_bits, count) in counts {
+            writeln!(
+                out,
+                "{bin}\t{}\t{count}\t{:.6}",
+                kmer::unpack_to_string(*packed_bits, k),
+                *count as f64 / total
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn kmers_are_binned_by_start_position() {
+        let mut counts = CycleCounts::default();
+        tally(&mut counts, &Bytes::from_static(b"AAAAAACGTACG"), 4);
+        tally(&mut counts, &Bytes::from_static(b"ACG"), 4);
+
+        let mut out = Vec::new();
+        write(&mut out, &counts, 4).unwrap();
+        insta::assert_snapshot!(String::from_utf8(out).unwrap(), @r###"
+        bin	kmer	count	fraction
+        start	AAAA	3	1.000000
+        middle	AAAC	1	0.333333
+        middle	AACG	1	0.333333
+        middle	ACGT	1	0.333333
+        end	CGTA	1	0.333333
+        end	GTAC	1	0.333333
+        end	TACG	1	0.333333
+        "###);
+    }
+}
@@ -0,0 +1,109 @@
+//! `--dedup-exact`: counting each distinct read once, so PCR duplicates, as
+//! in amplicon data, don't inflate the k-mer spectrum.
+
+use std::{collections::HashSet, fmt};
+
+use bytes::Bytes;
+use fxhash::FxBuildHasher;
+
+use super::reader::{self, Sequence};
+
+/// Reads seen, and how many repeated an earlier read exactly.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Duplicates {
+    pub reads: usize,
+    pub duplicates: usize,
+}
+
+impl Duplicates {
+    pub(crate) fn fraction(&self) -> f64 {
+        match self.reads {
+            0 => 0.0,
+            reads => self.duplicates as f64 / reads as f64,
+        }
+    }
+}
+
+impl fmt::Display for Duplicates {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "reads: {}", self.reads)?;
+        write!(
+            f,
+            "duplicate reads: {} ({:.2}%)",
+            self.duplicates,
+            100.0 * self.fraction()
+        )
+    }
+}
+
+/// Drops every read whose sequence exactly matches an earlier one's, in any
+/// input, keeping the first
+///
+/// # Notes
+/// Sequences are compared whole, so a hash collision can't drop a read, and
+/// regardless of case, as `--mmap` leaves soft-masked bases lowercase where
+/// other readers don't. Reads in one uppercase piece are held by reference;
+/// only multi-line or soft-masked records are copied to be compared.
+pub(crate) fn dedup_exact(inputs: &mut [Vec<Sequence>]) -> Duplicates {
+    let mut seen = HashSet::<Bytes, FxBuildHasher>::default();
+    let mut duplicates = Duplicates::default();
+
+    for sequences in inputs {
+        duplicates.reads += sequences.len();
+        sequences.retain(|(_, pieces)| seen.insert(uppercase(reader::joined(pieces))));
+    }
+    duplicates.duplicates = duplicates.reads - seen.len();
+
+    duplicates
+}
+
+/// `seq`, copied to uppercase only if some of it is lowercase
+fn uppercase(seq: Bytes) -> Bytes {
+    match seq.iter().any(u8::is_ascii_lowercase) {
+        true => seq.to_ascii_uppercase().into(),
+        false => seq,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn keeps_the_first_of_each_sequence() {
+        let read = |id: &str, pieces: &[&'static [u8]]| {
+            let pieces = pieces.iter().map(|piece| Bytes::from_static(piece));
+            (id.to_string(), pieces.collect())
+        };
+        let mut inputs = vec![
+            vec![
+                read("a", &[b"ACGT"]),
+                read("b", &[b"AC", b"GT"]),
+                read("c", &[b"ACG"]),
+            ],
+            vec![
+                read("d", &[b"ACG"]),
+                read("e", &[b"TTT"]),
+                read("f", &[b"acGT"]),
+            ],
+        ];
+
+        let duplicates = dedup_exact(&mut inputs);
+        let ids = inputs
+            .iter()
+            .map(|sequences| sequences.iter().map(|(id, _)| id.as_str()).collect())
+            .collect::<Vec<Vec<_>>>();
+        assert_eq!(ids, [vec!["a", "c"], vec!["e"]]);
+        assert_eq!(
+            duplicates,
+            Duplicates {
+                reads: 6,
+                duplicates: 3
+            }
+        );
+        insta::assert_snapshot!(duplicates.to_string(), @r###"
+        reads: 6
+        duplicate reads: 3 (50.00%)
+        "###);
+    }
+}
@@ -0,0 +1,172 @@
+//! `krust minimizer-density`: minimizer density and gaps along an assembly,
+//! flagging stretches where minimizer-based mappers would find few anchors.
+
+use std::{
+    error::Error,
+    io::{self, Write},
+    path::Path,
+};
+
+use super::{
+    kmer::Packed,
+    minimizer::MinimizerIterator,
+    reader::{self, Sequence},
+};
+
+/// How minimizers are picked and windows judged.
+#[derive(Debug, Clone, Copy)]
+pub struct DensityParams {
+    pub k: usize,
+    /// K-mers per minimizer window
+    pub w: usize,
+    /// K-mer start positions per reported window
+    pub window: usize,
+    /// Longest stretch without a minimizer before a window is sparse
+    pub max_gap: usize,
+}
+
+impl DensityParams {
+    /// Minimizers per base expected of a random sequence, `2 / (w + 1)`
+    pub fn expected_density(&self) -> f64 {
+        2.0 / (self.w + 1) as f64
+    }
+}
+
+/// Minimizers among one window of a record's k-mer start positions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WindowDensity {
+    pub record: String,
+    pub start: usize,
+    pub end: usize,
+    pub minimizers: usize,
+    /// Longest distance between consecutive minimizers, or a record's ends
+    /// and its first or last minimizer, among those reaching into the window
+    pub max_gap: usize,
+    /// Under half the expected density, or with a gap over the maximum
+    pub sparse: bool,
+}
+
+impl WindowDensity {
+    pub fn density(&self) -> f64 {
+        self.minimizers as f64 / (self.end - self.start) as f64
+    }
+}
+
+/// Splits the k-mer start positions of each record of the assembly at `path`
+/// into windows and measures their minimizers
+pub fn densities<P: AsRef<Path> + std::fmt::Debug>(
+    path: P,
+    params: &DensityParams,
+) -> Result<Vec<WindowDensity>, Box<dyn Error>> {
+    Ok(reader::read(path, None, None)?
+        .iter()
+        .flat_map(|record| record_densities(record, params))
+        .collect())
+}
+
+fn record_densities((id, pieces): &Sequence, params: &DensityParams) -> Vec<WindowDensity> {
+    let seq = reader::joined(pieces);
+    let starts = match params.k <= u64::MAX_K {
+        true => minimizer_starts::<u64>(&seq, params),
+        false => minimizer_starts::<u128>(&seq, params),
+    };
+    let positions = (seq.len() + 1).saturating_sub(params.k);
+    // Gaps run between consecutive minimizers, and from each end of the
+    // record to the minimizer nearest it
+    let bounds = [0]
+        .into_iter()
+        .chain(starts.iter().copied())
+        .chain([positions])
+        .collect::<Vec<_>>();
+
+    (0..positions)
+        .step_by(params.window)
+        .map(|start| {
+            let end = (start + params.window).min(positions);
+            let minimizers =
+                starts.partition_point(|s| *s < end) - starts.partition_point(|s| *s < start);
+            let first_gap = bounds
+                .partition_point(|bound| *bound <= start)
+                .saturating_sub(1);
+            let max_gap = bounds[first_gap..]
+                .windows(2)
+                .take_while(|gap| gap[0] < end)
+                .map(|gap| gap[1] - gap[0])
+                .max()
+                .unwrap_or(0);
+            let mut window = WindowDensity {
+                record: id.clone(),
+                start,
+                end,
+                minimizers,
+                max_gap,
+                sparse: false,
+            };
+            window.sparse =
+                window.density() < params.expected_density() / 2.0 || max_gap > params.max_gap;
+            window
+        })
+        .collect()
+}
+
+fn minimizer_starts<P: Packed>(seq: &[u8], params: &DensityParams) -> Vec<usize> {
+    MinimizerIterator::<P>::new(seq, params.k, params.w)
+        .map(|(start, ..)| start)
+        .collect()
+}
+
+/// Writes a `record`, `start`, `end`, `minimizers`, `density`, `max_gap`,
+/// `sparse` row per window, its k-mer start positions 0-based and half-open
+/// as in BED
+pub fn write<W: Write>(out: &mut W, windows: &[WindowDensity]) -> io::Result<()> {
+    writeln!(
+        out,
+        "record\tstart\tend\tminimizers\tdensity\tmax_gap\tsparse"
+    )?;
+    for window in windows {
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}\t{:.4}\t{}\t{}",
+            window.record,
+            window.start,
+            window.end,
+            window.minimizers,
+            window.density(),
+            window.max_gap,
+            window.sparse
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use bytes::Bytes;
+
+    #[test]
+    fn flags_windows_without_anchors() {
+        let seq = b"ACGTTGCAACGGTACCATGGACGTACGGTAACCATGCATTGACCAGTTCAGAGCTAGGCTTACC";
+        let masked = [&seq[..], &[b'N'; 40], &seq[..]].concat();
+        let record = ("chr1".to_string(), vec![Bytes::from(masked)]);
+        let params = DensityParams {
+            k: 5,
+            w: 4,
+            window: 40,
+            max_gap: 20,
+        };
+
+        let mut out = Vec::new();
+        write(&mut out, &record_densities(&record, &params)).unwrap();
+        insta::assert_snapshot!(String::from_utf8(out).unwrap(), @r###"
+        record	start	end	minimizers	density	max_gap	sparse
+        chr1	0	40	17	0.4250	4	false
+        chr1	40	80	9	0.2250	50	true
+        chr1	80	120	8	0.2000	50	true
+        chr1	120	160	17	0.4250	4	false
+        chr1	160	164	1	0.2500	4	false
+        "###);
+    }
+}
@@ -0,0 +1,283 @@
+//! `krust estimate`: approximate distinct and total canonical k-mers of an
+//! input from one streaming pass in a few KB, to size a full count before
+//! committing memory to it.
+
+use std::{
+    error::Error,
+    fmt::{self, Display},
+    path::Path,
+};
+
+use fxhash::FxHashMap;
+use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+
+use super::{kmer, reader};
+
+/// HyperLogLog precision by default: `2^14` registers, for a standard error
+/// of about 0.8%.
+pub const DEFAULT_PRECISION: u8 = 14;
+
+/// K-mers whose hashes start with this many zero bits, one in 256, are
+/// counted exactly to estimate singletons.
+pub const SAMPLE_BITS: u32 = 8;
+
+/// Bases of reads gathered before their k-mers are hashed in parallel.
+const BATCH_BASES: usize = 16 << 20;
+
+/// Spreads a packed k-mer over all 64 bits, with SplitMix64's finalizer
+fn mix(packed_bits: u64) -> u64 {
+    let mut x = packed_bits;
+    x = (x ^ x >> 30).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ x >> 27).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ x >> 31
+}
+
+/// A HyperLogLog sketch of a set of hashes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HyperLogLog {
+    precision: u8,
+    /// Per register, the most leading zeros plus one of the hashes it saw
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    /// `2^precision` registers, taking `precision` from 4 to 18
+    pub fn new(precision: u8) -> Self {
+        assert!((4..=18).contains(&precision), "precision from 4 to 18");
+        Self {
+            precision,
+            registers: vec![0; 1 << precision],
+        }
+    }
+
+    /// Adds a hash, its top bits picking a register
+    pub fn insert(&mut self, hash: u64) {
+        let register = (hash >> (64 - self.precision)) as usize;
+        let rank = ((hash << self.precision).leading_zeros() + 1).min(65 - self.precision as u32);
+        self.registers[register] = self.registers[register].max(rank as u8);
+    }
+
+    /// Takes in the hashes seen by another sketch of the same precision
+    pub fn merge(&mut self, other: &Self) {
+        for (register, other) in self.registers.iter_mut().zip(&other.registers) {
+            *register = (*register).max(*other)
+        }
+    }
+
+    /// Estimated distinct hashes seen
+    ///
+    /// # Notes
+    /// Below `2.5` per register, while some registers are still empty, the
+    /// raw estimate is biased high, and linear counting of the empty ones is
+    /// used instead, as in the original paper. With 64-bit hashes no large
+    /// range correction is needed.
+    pub fn estimate(&self) -> f64 {
+        let m = self.registers.len() as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum = self
+            .registers
+            .iter()
+            .map(|rank| (-(*rank as f64)).exp2())
+            .sum::<f64>();
+        let raw = alpha * m * m / sum;
+
+        let empty = self.registers.iter().filter(|rank| **rank == 0).count();
+        match raw <= 2.5 * m && empty > 0 {
+            true => m * (m / empty as f64).ln(),
+            false => raw,
+        }
+    }
+
+    /// Relative standard error of [`HyperLogLog::estimate`]
+    pub fn standard_error(&self) -> f64 {
+        1.04 / (self.registers.len() as f64).sqrt()
+    }
+}
+
+/// What one pass gathers, per thread and then merged.
+struct Tally {
+    hll: HyperLogLog,
+    /// Counts of the k-mers sampled by hash, given `--singletons`
+    sampled: Option<FxHashMap<u64, u32>>,
+    total: u64,
+}
+
+impl Tally {
+    fn new(precision: u8, singletons: bool) -> Self {
+        Self {
+            hll: HyperLogLog::new(precision),
+            sampled: singletons.then(FxHashMap::default),
+            total: 0,
+        }
+    }
+
+    fn add(&mut self, seq: &[u8], k: usize) {
+        for (_, forward, reverse) in kmer::stranded_kmers::<u64>(seq, k) {
+            let hash = mix(forward.min(reverse));
+            self.hll.insert(hash);
+            self.total += 1;
+            if let Some(sampled) = &mut self.sampled {
+                if hash.leading_zeros() >= SAMPLE_BITS {
+                    *sampled.entry(hash).or_default() += 1
+                }
+            }
+        }
+    }
+
+    fn merge(mut self, other: Self) -> Self {
+        self.hll.merge(&other.hll);
+        self.total += other.total;
+        if let (Some(sampled), Some(other)) = (&mut self.sampled, other.sampled) {
+            for (hash, count) in other {
+                *sampled.entry(hash).or_default() += count
+            }
+        }
+        self
+    }
+}
+
+/// Approximate k-mer statistics of one input.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Estimate {
+    pub k: usize,
+    pub reads: u64,
+    /// Valid k-mers, counted exactly
+    pub total_kmers: u64,
+    pub distinct_kmers: f64,
+    /// Relative standard error of `distinct_kmers`
+    pub standard_error: f64,
+    /// Distinct k-mers seen once, scaled up from a sample, as KmerStream's
+    /// `f1`
+    pub singletons: Option<f64>,
+}
+
+/// Streams the canonical `k`-mers, `k` up to 32, of the FASTA or FASTQ input
+/// at `path`, optionally compressed, or `stdin` given `-`, through a
+/// HyperLogLog of `2^precision` registers, and, given `singletons`, counts
+/// one in `2^SAMPLE_BITS` of them by hash to estimate how many occur once
+pub fn estimate<P: AsRef<Path>>(
+    path: P,
+    k: usize,
+    precision: u8,
+    singletons: bool,
+) -> Result<Estimate, Box<dyn Error>> {
+    let mut records = match path.as_ref().to_str() == Some(reader::STDIN) {
+        true => needletail::parse_fastx_stdin()?,
+        false => needletail::parse_fastx_file(path)?,
+    };
+
+    let mut tally = Tally::new(precision, singletons);
+    let mut reads = 0;
+    let mut batch = Vec::new();
+    let mut batch_bases = 0;
+    loop {
+        let record = records.next().transpose()?;
+        if let Some(record) = &record {
+            let seq = record.seq().into_owned();
+            batch_bases += seq.len();
+            batch.push(seq);
+            reads += 1;
+        }
+        if batch_bases >= BATCH_BASES || record.is_none() {
+            let counted = batch
+                .par_iter()
+                .fold(
+                    || Tally::new(precision, singletons),
+                    |mut tally, seq| {
+                        tally.add(seq, k);
+                        tally
+                    },
+                )
+                .reduce(|| Tally::new(precision, singletons), Tally::merge);
+            tally = tally.merge(counted);
+            batch.clear();
+            batch_bases = 0;
+        }
+        if record.is_none() {
+            break;
+        }
+    }
+
+    Ok(Estimate {
+        k,
+        reads,
+        total_kmers: tally.total,
+        distinct_kmers: tally.hll.estimate(),
+        standard_error: tally.hll.standard_error(),
+        singletons: tally.sampled.map(|sampled| {
+            let once = sampled.values().filter(|count| **count == 1).count();
+            once as f64 * (1u64 << SAMPLE_BITS) as f64
+        }),
+    })
+}
+
+impl Display for Estimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "k: {}", self.k)?;
+        writeln!(f, "reads: {}", self.reads)?;
+        writeln!(f, "total k-mers: {}", self.total_kmers)?;
+        write!(
+            f,
+            "distinct k-mers: ~{:.0} (HyperLogLog, standard error {:.1}%)",
+            self.distinct_kmers,
+            100.0 * self.standard_error
+        )?;
+        if let Some(singletons) = self.singletons {
+            write!(
+                f,
+                "\nsingletons: ~{singletons:.0} (from 1 in {} k-mers)",
+                1u64 << SAMPLE_BITS
+            )?;
+        }
+        write!(
+            f,
+            "\nsize a full count with: --expected-kmers {:.0}",
+            self.distinct_kmers
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn estimates_are_within_a_few_standard_errors() {
+        let mut hll = HyperLogLog::new(DEFAULT_PRECISION);
+        for n in [100u64, 10_000, 1_000_000] {
+            for packed_bits in 0..n {
+                hll.insert(mix(packed_bits))
+            }
+            let error = (hll.estimate() - n as f64).abs() / n as f64;
+            assert!(
+                error < 3.0 * hll.standard_error(),
+                "{n}: {}",
+                hll.estimate()
+            );
+        }
+
+        let mut halves = [0..500_000, 500_000..1_000_000].map(|kmers| {
+            let mut hll = HyperLogLog::new(DEFAULT_PRECISION);
+            kmers.for_each(|packed_bits| hll.insert(mix(packed_bits)));
+            hll
+        });
+        let [first, second] = &mut halves;
+        first.merge(second);
+        assert_eq!(*first, hll);
+    }
+
+    #[test]
+    fn counts_reads_and_kmers_of_an_input() {
+        let fastq = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(
+            fastq.path(),
+            "@a\nACGTACGTAA\n+\nIIIIIIIIII\n@b\nACGTNACGT\n+\nIIIIIIIII\n",
+        )
+        .unwrap();
+
+        let estimate = estimate(fastq.path(), 4, DEFAULT_PRECISION, true).unwrap();
+        assert_eq!((estimate.reads, estimate.total_kmers), (2, 7 + 2));
+        // ACGT, CGTA (also as TACG), GTAC and GTAA
+        assert_eq!(estimate.distinct_kmers.round(), 4.0);
+    }
+}
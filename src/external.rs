@@ -0,0 +1,312 @@
+//! `--max-memory`: counting in bounded memory, for inputs with more distinct
+//! k-mers than fit in RAM.
+//!
+//! A first pass reads the inputs a batch of records at a time, scattering
+//! their canonical k-mers into temporary bucket files by hash, so every
+//! occurrence of a k-mer lands in the same bucket. A second counts one
+//! bucket at a time and streams its counts out before reading the next, so
+//! only one batch of records, then one bucket's count map, is held at once.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, ErrorKind, Read, Seek, Write},
+    mem,
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use bytes::Bytes;
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+
+use super::{
+    config::Config,
+    hasher,
+    kmer::Packed,
+    reader::{self, Sequence},
+    run::{self, KmerMap, ProcessError, Record, Tally, INSERT_BATCH_LEN},
+    spill,
+};
+
+/// Estimated bytes of count map per distinct k-mer, allowing for the slack
+/// of a hash table that has just grown.
+const MAP_BYTES_PER_KMER: usize = 64;
+
+/// Most bucket files written at once, well under common open file limits.
+const MAX_BUCKETS: usize = 512;
+
+/// Bytes a worker holds for one bucket before appending them to its file.
+const FLUSH_BYTES: usize = 1 << 14;
+
+/// Bases of records read before their k-mers are scattered in parallel.
+const BATCH_BASES: usize = 16 << 20;
+
+/// Bases taken per byte of a gzipped input in sizing buckets, about what
+/// gzip makes of sequence.
+const GZIP_RATIO: usize = 4;
+
+/// Buckets needed for each to be counted in `max_memory` bytes, supposing
+/// all of `kmers` k-mers are distinct
+///
+/// # Notes
+/// Capped at [`MAX_BUCKETS`], past which a bucket may take more than
+/// `max_memory`.
+pub(crate) fn bucket_count(kmers: usize, max_memory: usize) -> usize {
+    kmers
+        .saturating_mul(MAP_BYTES_PER_KMER)
+        .div_ceil(max_memory)
+        .clamp(1, MAX_BUCKETS)
+}
+
+/// Which of `buckets` a canonical k-mer goes to
+///
+/// # Notes
+/// Remixes the k-mer's FxHash, so k-mers sharing a bucket don't also share
+/// the low hash bits the count map places them by.
+fn bucket<P: Packed>(packed_bits: P, buckets: usize) -> usize {
+    (hasher::remix(fxhash::hash64(&packed_bits)) % buckets as u64) as usize
+}
+
+/// Bases in the inputs at `paths`, roughly, from their sizes on disk, or
+/// `None` if any isn't a regular file, such as a pipe
+fn input_bases<P: AsRef<Path>>(paths: &[P]) -> Option<usize> {
+    paths
+        .iter()
+        .map(|path| {
+            let len = std::fs::metadata(path).ok().filter(|m| m.is_file())?.len() as usize;
+            let mut magic = [0; 2];
+            let gzipped = File::open(path)
+                .and_then(|mut file| file.read_exact(&mut magic))
+                .is_ok_and(|_| magic == [0x1f, 0x8b]);
+            Some(match gzipped {
+                true => len.saturating_mul(GZIP_RATIO),
+                false => len,
+            })
+        })
+        .sum()
+}
+
+/// Counts the inputs of `config`, read a batch of records at a time, a
+/// bucket at a time, yielding each bucket's records in turn and adding its
+/// distinct k-mers to `distinct_kmers` once counted
+///
+/// # Notes
+/// Buckets are sized by the inputs' sizes on disk, supposing every base
+/// starts a distinct k-mer; where an input's size isn't known, as for a
+/// pipe, [`MAX_BUCKETS`] are used.
+pub(crate) fn count<'a, P: Packed, V: Tally>(
+    config: &'a Config,
+    max_memory: usize,
+    distinct_kmers: &'a AtomicUsize,
+) -> Result<impl Iterator<Item = io::Result<Record<P>>> + 'a, ProcessError> {
+    let buckets = match input_bases(&config.paths) {
+        Some(bases) => bucket_count(bases / config.stride.unwrap_or(1), max_memory),
+        None => MAX_BUCKETS,
+    };
+    let files = scatter::<P>(config, buckets)?;
+
+    Ok(files.into_iter().flat_map(move |file| {
+        let records: Box<dyn Iterator<Item = io::Result<Record<P>>>> =
+            match count_bucket::<P, V>(file, config) {
+                Ok(map) => {
+                    distinct_kmers.fetch_add(map.counts.len(), Ordering::Relaxed);
+                    Box::new(map.stream(config).map(Ok))
+                }
+                Err(e) => Box::new(std::iter::once(Err(e))),
+            };
+        records
+    }))
+}
+
+/// Writes every canonical k-mer of the inputs to its bucket's file, each as
+/// its packed bits, then, when tracking strands, a byte flagging a reverse
+/// complement
+fn scatter<P: Packed>(config: &Config, buckets: usize) -> Result<Vec<File>, ProcessError> {
+    let files = (0..buckets)
+        .map(|_| Ok(Mutex::new(BufWriter::new(spill::tempfile()?))))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let mut batch = Vec::new();
+    let mut batch_bases = 0;
+    let mut written = Ok(());
+    for path in &config.paths {
+        reader::read_each(
+            path,
+            config.records.as_ref(),
+            config.trim_quality,
+            |sequence| {
+                batch_bases += sequence.1.iter().map(Bytes::len).sum::<usize>();
+                batch.push(sequence);
+                if batch_bases >= BATCH_BASES && written.is_ok() {
+                    written = scatter_batch::<P>(mem::take(&mut batch), &files, config);
+                    batch_bases = 0;
+                }
+            },
+        )?;
+        mem::replace(&mut written, Ok(()))?;
+    }
+    scatter_batch::<P>(batch, &files, config)?;
+
+    files
+        .into_iter()
+        .map(|file| {
+            let mut file = file
+                .into_inner()
+                .expect("no writer panicked")
+                .into_inner()
+                .map_err(|e| e.into_error())?;
+            file.rewind()?;
+            Ok(file)
+        })
+        .collect()
+}
+
+/// Scatters a batch of records' k-mers in parallel
+fn scatter_batch<P: Packed>(
+    sequences: Vec<Sequence>,
+    files: &[Mutex<BufWriter<File>>],
+    config: &Config,
+) -> io::Result<()> {
+    let stride = config.stride.unwrap_or(1);
+
+    sequences
+        .into_par_iter()
+        .try_fold(
+            || Scatter::new(files, config.strand),
+            |mut scatter, (_, pieces)| {
+                let mut written = Ok(());
+                run::kmer_batches::<P>(
+                    &pieces,
+                    config.k,
+                    stride,
+                    config.minimizer_window,
+                    !config.no_canonical,
+                    |batch| {
+                        for (packed_bits, reversed) in batch.drain(..) {
+                            if written.is_ok() {
+                                written = scatter.push(packed_bits, reversed);
+                            }
+                        }
+                    },
+                );
+                written.map(|_| scatter)
+            },
+        )
+        .try_for_each(|scatter| scatter?.finish())
+}
+
+/// A worker's k-mers not yet appended to their buckets' files.
+struct Scatter<'a> {
+    files: &'a [Mutex<BufWriter<File>>],
+    pending: Vec<Vec<u8>>,
+    strand: bool,
+}
+
+impl<'a> Scatter<'a> {
+    fn new(files: &'a [Mutex<BufWriter<File>>], strand: bool) -> Self {
+        Self {
+            files,
+            pending: vec![Vec::new(); files.len()],
+            strand,
+        }
+    }
+
+    fn push<P: Packed>(&mut self, packed_bits: P, reversed: bool) -> io::Result<()> {
+        let i = bucket(packed_bits, self.files.len());
+        let pending = &mut self.pending[i];
+        packed_bits.write_le(pending)?;
+        if self.strand {
+            pending.push(reversed as u8);
+        }
+
+        match pending.len() >= FLUSH_BYTES {
+            true => self.flush(i),
+            false => Ok(()),
+        }
+    }
+
+    fn flush(&mut self, i: usize) -> io::Result<()> {
+        let mut file = self.files[i].lock().expect("no writer panicked");
+        file.write_all(&self.pending[i])?;
+        self.pending[i].clear();
+
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        (0..self.pending.len()).try_for_each(|i| self.flush(i))
+    }
+}
+
+/// Counts one bucket's k-mers
+fn count_bucket<P: Packed, V: Tally>(file: File, config: &Config) -> io::Result<KmerMap<P, V>> {
+    let map = KmerMap::with_capacity_and_hasher(config.strand, 0, config.hasher);
+    let mut file = BufReader::new(file);
+    let width = P::BYTES + config.strand as usize;
+    let mut record = [0; 17];
+    let mut batch = Vec::with_capacity(INSERT_BATCH_LEN);
+
+    loop {
+        match file.read_exact(&mut record[..width]) {
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            result => result?,
+        }
+        let reversed = config.strand && record[P::BYTES] == 1;
+        batch.push((P::from_le_slice(&record[..P::BYTES]), reversed));
+        if batch.len() == INSERT_BATCH_LEN {
+            map.insert_batch(&mut batch)
+        }
+    }
+    map.insert_batch(&mut batch);
+
+    Ok(map)
+}
+
+#[cfg(all(test, feature = "spill"))]
+mod test {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    use crate::kmer;
+
+    #[test]
+    fn buckets_count_as_one_map() {
+        let seq = b"ACGTTGCAnnACGGTACCATGNACGTACGGTAACACGTTGCAACGGTACC";
+        let sequences = || vec![("a".to_string(), vec![Bytes::from_static(seq)])];
+        let config = Config {
+            k: 5,
+            strand: true,
+            ..Default::default()
+        };
+        let unpacked = |records: Vec<Record>| {
+            records
+                .into_iter()
+                .map(|(packed_bits, count, strand)| {
+                    (kmer::unpack_to_string(packed_bits, 5), (count, strand))
+                })
+                .collect::<HashMap<_, _>>()
+        };
+
+        let map = KmerMap::<u64>::new(true);
+        map.process_pieces(&sequences()[0].1, &5);
+        let expected = unpacked(map.stream(&config).collect());
+
+        let fasta = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(fasta.path(), [&b">a\n"[..], seq, b"\n"].concat()).unwrap();
+        let config = Config {
+            paths: vec![fasta.path().into()],
+            ..config
+        };
+        let distinct_kmers = AtomicUsize::new(0);
+        // Small enough a budget for a bucket per k-mer or so
+        let records = count::<u64, i32>(&config, 64, &distinct_kmers)
+            .unwrap()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(unpacked(records), expected);
+        assert_eq!(distinct_kmers.into_inner(), expected.len());
+    }
+}
@@ -0,0 +1,208 @@
+//! `krust gather`: decomposes a sample into the references it contains.
+
+use std::{
+    error::Error,
+    io::{self, Write},
+    path::Path,
+};
+
+use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+
+use super::{
+    samples,
+    signature::{self, MinHash},
+    sketch::ScaledSketch,
+};
+
+/// A reference and its sketch.
+pub struct Reference {
+    pub name: String,
+    pub sketch: ScaledSketch,
+}
+
+/// Sketches a FASTA or FASTQ file, or reads the scaled sketches at `k` from a
+/// sourmash signature file
+pub fn load<P: AsRef<Path>>(
+    path: P,
+    k: usize,
+    scaled: u64,
+) -> Result<Vec<Reference>, Box<dyn Error>> {
+    if !signature::is_signature(&path) {
+        return Ok(vec![Reference {
+            name: samples::sample_name(&path),
+            sketch: ScaledSketch::from_path(&path, k, scaled)?,
+        }]);
+    }
+
+    let references = signature::read(&path)?
+        .into_iter()
+        .filter_map(|signature| match signature.minhash {
+            MinHash::Scaled(sketch) if sketch.k == k => Some(Reference {
+                name: signature.name,
+                sketch,
+            }),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    match references.is_empty() {
+        true => Err(format!("no scaled sketch with k = {k}").into()),
+        false => Ok(references),
+    }
+}
+
+/// Loads the sample to break down, which must be a single sketch
+pub fn query<P: AsRef<Path>>(
+    path: P,
+    k: usize,
+    scaled: u64,
+) -> Result<ScaledSketch, Box<dyn Error>> {
+    let mut query =
+        load(&path, k, scaled).map_err(|e| format!("{}: {e}", path.as_ref().display()))?;
+    match query.len() {
+        1 => Ok(query.remove(0).sketch),
+        n => Err(format!(
+            "{}: holds {n} sketches with k = {k}, but gather takes one sample",
+            path.as_ref().display()
+        )
+        .into()),
+    }
+}
+
+/// Loads every reference in parallel
+pub fn references<P: AsRef<Path> + Sync>(
+    paths: &[P],
+    k: usize,
+    scaled: u64,
+) -> Result<Vec<Reference>, Box<dyn Error>> {
+    let references = paths
+        .par_iter()
+        .map(|path| load(path, k, scaled).map_err(|e| format!("{}: {e}", path.as_ref().display())))
+        .collect::<Result<Vec<_>, String>>()?;
+
+    Ok(references.into_iter().flatten().collect())
+}
+
+/// A reference found in the query, in the order `gather` found them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match {
+    pub name: String,
+    /// Estimated bases of the query first explained by this reference
+    pub intersect_bp: u64,
+    /// Fraction of the query's hashes first explained by this reference
+    pub f_unique_to_query: f64,
+    /// Fraction of the query's hashes in this reference, explained earlier
+    /// or not
+    pub f_orig_query: f64,
+    /// Fraction of this reference's hashes in the query
+    pub f_match: f64,
+}
+
+/// Greedily explains the query's hashes by the references containing most of
+/// those still unexplained, until none explains `threshold_bp` more bases
+///
+/// # Notes
+/// Each hash in a scaled sketch stands for about `scaled` k-mers, so shared
+/// hashes times `scaled` estimates shared bases. Sketches made at different
+/// `scaled` are compared at the largest.
+pub fn gather(query: &ScaledSketch, references: &[Reference], threshold_bp: u64) -> Vec<Match> {
+    let scaled = references
+        .iter()
+        .map(|reference| reference.sketch.scaled)
+        .fold(query.scaled, u64::max);
+    let query = &query.downsample(scaled);
+    let references = references
+        .iter()
+        .map(|reference| Reference {
+            name: reference.name.clone(),
+            sketch: reference.sketch.downsample(scaled),
+        })
+        .collect::<Vec<_>>();
+
+    let mut remaining = query.clone();
+    let mut unused = references.iter().collect::<Vec<_>>();
+    let mut matches = Vec::new();
+
+    loop {
+        let best = unused
+            .iter()
+            .enumerate()
+            .map(|(i, reference)| (i, remaining.shared(&reference.sketch)))
+            .fold(None, |best: Option<(usize, usize)>, candidate| match best {
+                Some(best) if best.1 >= candidate.1 => Some(best),
+                _ => Some(candidate),
+            });
+        let Some((i, shared)) = best else {
+            break;
+        };
+        let intersect_bp = shared as u64 * query.scaled;
+        if shared == 0 || intersect_bp < threshold_bp {
+            break;
+        }
+
+        let reference = unused.remove(i);
+        let in_query = query.shared(&reference.sketch);
+        matches.push(Match {
+            name: reference.name.clone(),
+            intersect_bp,
+            f_unique_to_query: shared as f64 / query.hashes.len() as f64,
+            f_orig_query: in_query as f64 / query.hashes.len() as f64,
+            f_match: in_query as f64 / reference.sketch.hashes.len() as f64,
+        });
+        remaining
+            .hashes
+            .retain(|hash| reference.sketch.hashes.binary_search(hash).is_err());
+    }
+
+    matches
+}
+
+/// Writes one tab-separated line per match, with a header
+pub fn write<W: Write>(out: &mut W, matches: &[Match]) -> io::Result<()> {
+    writeln!(
+        out,
+        "name\tintersect_bp\tf_unique_to_query\tf_orig_query\tf_match"
+    )?;
+    for m in matches {
+        writeln!(
+            out,
+            "{}\t{}\t{:.4}\t{:.4}\t{:.4}",
+            m.name, m.intersect_bp, m.f_unique_to_query, m.f_orig_query, m.f_match
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+
+    use super::*;
+
+    fn reference(name: &str, seq: &[u8]) -> Reference {
+        Reference {
+            name: name.to_string(),
+            sketch: ScaledSketch::from_seqs(&[Bytes::copy_from_slice(seq)], 5, 1),
+        }
+    }
+
+    #[test]
+    fn gather_explains_a_mixture_by_its_parts() {
+        let a: &[u8] = b"ACGGTCAGTTCAGAGCTAGGCTTACC";
+        let b: &[u8] = b"TTGACCATGCATCGGTAAGCTAGCAA";
+        let mixture = [a, b].concat();
+        let query = ScaledSketch::from_seqs(&[Bytes::from(mixture)], 5, 1);
+        let references = [
+            reference("a", a),
+            reference("b", b),
+            reference("c", b"CACACACACACACACA"),
+        ];
+
+        let matches = gather(&query, &references, 1);
+        let names = matches.iter().map(|m| m.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["a", "b"]);
+        assert!(matches.iter().all(|m| m.f_match == 1.0));
+        let explained = matches.iter().map(|m| m.f_unique_to_query).sum::<f64>();
+        assert!(explained > 0.8 && explained <= 1.0);
+    }
+}
@@ -0,0 +1,166 @@
+//! Hashers for packed k-mer keys.
+
+use std::{
+    fmt::{self, Display},
+    hash::{BuildHasher, BuildHasherDefault, Hasher},
+    str::FromStr,
+};
+
+use fxhash::FxHasher;
+
+/// Hashes a `u64` key as itself, and a `u128` key as its two halves XORed,
+/// then remixes it so each bit of the hash depends on every bit of the key.
+///
+/// # Notes
+/// Packed k-mers are close to uniform in their low `2k` bits, but the high
+/// bits of short k-mers are all zero, and `hashbrown` keeps a hash's top 7
+/// bits in each entry's control byte while `dashmap` picks shards from the
+/// bits just below. Remixing spreads the key over every bit at the cost of a
+/// multiply, and as it's invertible no two `u64` keys share a hash.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct IdentityHasher(u64);
+
+impl Hasher for IdentityHasher {
+    fn finish(&self) -> u64 {
+        remix(self.0)
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        self.0 = n
+    }
+
+    fn write_u128(&mut self, n: u128) {
+        self.0 = n as u64 ^ (n >> 64) as u64
+    }
+
+    /// Only `u64` keys are expected, but anything else still hashes, by
+    /// folding its bytes in
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = self.0.rotate_left(8) ^ *byte as u64
+        }
+    }
+}
+
+/// [`IdentityHasher`] for `HashMap`s and `DashMap`s keyed by packed k-mers.
+pub type BuildIdentityHasher = BuildHasherDefault<IdentityHasher>;
+
+/// Which hasher the count map uses.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub enum HasherKind {
+    #[default]
+    Fx,
+    Identity,
+}
+
+impl HasherKind {
+    pub const VARIANTS: [&'static str; 2] = ["fx", "identity"];
+}
+
+impl FromStr for HasherKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fx" => Ok(Self::Fx),
+            "identity" => Ok(Self::Identity),
+            _ => Err(format!("Unknown hasher \"{s}\"")),
+        }
+    }
+}
+
+impl Display for HasherKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Fx => "fx",
+            Self::Identity => "identity",
+        })
+    }
+}
+
+/// Builds the hasher chosen at run time, so the count map has one type
+/// whichever it is.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct KmerBuildHasher(pub HasherKind);
+
+impl BuildHasher for KmerBuildHasher {
+    type Hasher = KmerHasher;
+
+    fn build_hasher(&self) -> KmerHasher {
+        match self.0 {
+            HasherKind::Fx => KmerHasher::Fx(FxHasher::default()),
+            HasherKind::Identity => KmerHasher::Identity(IdentityHasher::default()),
+        }
+    }
+}
+
+/// A [`KmerBuildHasher`]'s hasher.
+#[derive(Clone)]
+pub enum KmerHasher {
+    Fx(FxHasher),
+    Identity(IdentityHasher),
+}
+
+impl Hasher for KmerHasher {
+    fn finish(&self) -> u64 {
+        match self {
+            Self::Fx(hasher) => hasher.finish(),
+            Self::Identity(hasher) => hasher.finish(),
+        }
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        match self {
+            Self::Fx(hasher) => hasher.write_u64(n),
+            Self::Identity(hasher) => hasher.write_u64(n),
+        }
+    }
+
+    fn write_u128(&mut self, n: u128) {
+        match self {
+            Self::Fx(hasher) => hasher.write_u128(n),
+            Self::Identity(hasher) => hasher.write_u128(n),
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        match self {
+            Self::Fx(hasher) => hasher.write(bytes),
+            Self::Identity(hasher) => hasher.write(bytes),
+        }
+    }
+}
+
+/// Remixes a hash so each of its bits depends on every bit of the input, as
+/// FxHash's low bits don't, for picking buckets or filter bits from the low
+/// bits or ordering k-mers by hash
+pub(crate) fn remix(hash: u64) -> u64 {
+    let mixed = (hash ^ hash >> 33).wrapping_mul(0xff51_afd7_ed55_8ccd);
+    mixed ^ mixed >> 33
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn identity_hashes_u64_keys_remixed() {
+        let build = KmerBuildHasher(HasherKind::Identity);
+        assert_eq!(build.hash_one(0b1101_u64), remix(0b1101));
+        assert_ne!(
+            KmerBuildHasher::default().hash_one(0b1101_u64),
+            remix(0b1101)
+        );
+    }
+
+    #[test]
+    fn identity_spreads_short_kmers_over_the_top_bits() {
+        // The top 7 bits are hashbrown's control byte; dashmap's shard is
+        // picked from those just below
+        let build = KmerBuildHasher(HasherKind::Identity);
+        let top = (0..1u64 << 10)
+            .map(|packed_bits| build.hash_one(packed_bits) >> 50)
+            .collect::<std::collections::HashSet<_>>();
+        assert!(top.len() > 500, "{}", top.len());
+    }
+}
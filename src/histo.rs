@@ -0,0 +1,430 @@
+//! `krust histo`: k-mer abundance histograms at several k from one pass over
+//! the input.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io::{self, Write},
+    path::Path,
+    str::FromStr,
+    sync::{Mutex, MutexGuard},
+};
+
+use fxhash::FxHashMap;
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+#[cfg(feature = "json")]
+use serde::Serialize;
+
+#[cfg(not(feature = "json"))]
+use super::output::unsupported;
+use super::{reader::read, run::KmerMap};
+
+/// How many distinct k-mers occur each number of times, by k.
+pub type Histograms = BTreeMap<usize, BTreeMap<i32, u64>>;
+
+/// How many distinct k-mers have each count, kept up to date as k-mers are
+/// counted, in one part per count map shard.
+///
+/// # Notes
+/// Each part is only updated under its shard's write lock, so its own lock
+/// is never contended, and a k-mer's count only ever changes in one part.
+pub(crate) struct OnlineHistogram {
+    shards: Vec<Mutex<FxHashMap<i32, u64>>>,
+}
+
+impl OnlineHistogram {
+    pub(crate) fn new(shards: usize) -> Self {
+        Self {
+            shards: (0..shards).map(|_| Mutex::default()).collect(),
+        }
+    }
+
+    pub(crate) fn shard(&self, shard: usize) -> MutexGuard<'_, FxHashMap<i32, u64>> {
+        self.shards[shard].lock().expect("histogram lock poisoned")
+    }
+
+    /// Drops the k-mers with `count` once they're dropped from the map
+    pub(crate) fn forget(&self, count: i32) {
+        for shard in &self.shards {
+            shard
+                .lock()
+                .expect("histogram lock poisoned")
+                .remove(&count);
+        }
+    }
+
+    /// The parts summed, without the counts no k-mer has any longer
+    pub(crate) fn merged(&self) -> BTreeMap<i32, u64> {
+        let mut histogram = BTreeMap::new();
+        for shard in &self.shards {
+            for (count, kmers) in shard.lock().expect("histogram lock poisoned").iter() {
+                *histogram.entry(*count).or_insert(0) += kmers
+            }
+        }
+        histogram.retain(|_, kmers| *kmers > 0);
+        histogram
+    }
+}
+
+/// Moves a k-mer counted `count` times so far up to the next count
+pub(crate) fn increment(histogram: &mut FxHashMap<i32, u64>, count: i32) {
+    if count > 0 {
+        *histogram.get_mut(&count).expect("a k-mer with this count") -= 1
+    }
+    *histogram.entry(count + 1).or_insert(0) += 1
+}
+
+/// Counts canonical k-mers at every one of `ks`, reading each sequence once
+/// and updating one count map, and its histogram, per k
+pub fn histograms<P: AsRef<Path> + std::fmt::Debug>(
+    path: P,
+    ks: &[usize],
+) -> Result<Histograms, Box<dyn std::error::Error>> {
+    let maps = ks
+        .iter()
+        .map(|&k| (k, KmerMap::<u64>::new(false).with_histogram()))
+        .collect::<Vec<_>>();
+
+    read(path, None, None)?
+        .into_par_iter()
+        .for_each(|(_, pieces)| {
+            for (k, map) in &maps {
+                map.process_pieces(&pieces, k)
+            }
+        });
+
+    Ok(maps
+        .into_iter()
+        .map(|(k, map)| (k, map.histogram().expect("kept while counting")))
+        .collect())
+}
+
+/// Abundance bins, drawn as `jellyfish histo` draws them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bins {
+    pub low: u64,
+    pub high: u64,
+    pub increment: u64,
+}
+
+impl Default for Bins {
+    /// `jellyfish histo`'s defaults
+    fn default() -> Self {
+        Self {
+            low: 1,
+            high: 10000,
+            increment: 1,
+        }
+    }
+}
+
+impl Bins {
+    /// The bin `count` falls in, named by its smallest abundance
+    ///
+    /// # Notes
+    /// As in `jellyfish histo`, bins run `increment` apart from just below
+    /// `low`, abundances under the first bin are added to it, and those past
+    /// `high` go in one last bin.
+    pub fn bin(&self, count: u64) -> u64 {
+        let increment = self.increment.max(1);
+        let base = match self.low > 1 && increment < self.low {
+            true => self.low - increment,
+            false => 1,
+        };
+        let ceil = self.high + increment;
+        let last = (ceil + increment - base) / increment - 1;
+
+        let bucket = match count {
+            count if count < base => 0,
+            count if count > ceil => last,
+            count => (count - base) / increment,
+        };
+        base + bucket * increment
+    }
+
+    /// Adds up a histogram's abundances by bin
+    pub fn apply(&self, histogram: &BTreeMap<i32, u64>) -> BTreeMap<u64, u64> {
+        let mut binned = BTreeMap::new();
+        for (count, kmers) in histogram {
+            *binned.entry(self.bin(*count as u64)).or_insert(0) += kmers
+        }
+        binned
+    }
+}
+
+/// Summary statistics of one k's abundance histogram.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct HistogramStats {
+    /// Distinct k-mers
+    pub distinct: u64,
+    /// K-mer occurrences, distinct or not
+    pub total: u64,
+    /// Distinct k-mers occurring once, mostly sequencing errors in reads
+    pub singletons: u64,
+    pub max_count: i32,
+    /// Abundance of the highest point past the histogram's first trough,
+    /// roughly the k-mer coverage of a read set, or `None` if the histogram
+    /// never rises again
+    pub peak: Option<i32>,
+}
+
+impl HistogramStats {
+    pub fn new(histogram: &BTreeMap<i32, u64>) -> Self {
+        let kmers = |count| histogram.get(&count).copied().unwrap_or(0);
+        let max_count = histogram.keys().next_back().copied().unwrap_or(0);
+        // Abundances missing from the histogram have no k-mers, which can be
+        // a trough
+        let trough = (1..max_count).find(|count| kmers(count + 1) > kmers(*count));
+        let peak = trough.and_then(|trough| {
+            (trough + 1..=max_count)
+                .rev()
+                .max_by_key(|count| kmers(*count))
+        });
+
+        Self {
+            distinct: histogram.values().sum(),
+            total: histogram
+                .iter()
+                .map(|(count, kmers)| *count as u64 * kmers)
+                .sum(),
+            singletons: kmers(1),
+            max_count,
+            peak,
+        }
+    }
+
+    /// Mean occurrences per distinct k-mer
+    pub fn mean(&self) -> f64 {
+        match self.distinct {
+            0 => 0.0,
+            distinct => self.total as f64 / distinct as f64,
+        }
+    }
+}
+
+impl fmt::Display for HistogramStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "distinct k-mers: {}", self.distinct)?;
+        writeln!(f, "total k-mers: {}", self.total)?;
+        writeln!(f, "singletons: {}", self.singletons)?;
+        writeln!(f, "max count: {}", self.max_count)?;
+        writeln!(f, "mean count: {:.2}", self.mean())?;
+        match self.peak {
+            Some(peak) => write!(f, "peak: {peak}"),
+            None => write!(f, "peak: none"),
+        }
+    }
+}
+
+/// How `krust histo` lays out its output.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum HistoFormat {
+    /// A tab-separated `k`, `count`, `kmers` table covering every k
+    #[default]
+    Long,
+    /// `jellyfish histo`'s space-separated `count kmers` lines, for a
+    /// single k, as GenomeScope reads
+    Jellyfish,
+    /// One JSON object with each k's binned histogram and its
+    /// [`HistogramStats`]
+    Json,
+}
+
+impl HistoFormat {
+    pub const VARIANTS: [&'static str; 3] = ["long", "jellyfish", "json"];
+}
+
+impl FromStr for HistoFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "long" => Ok(Self::Long),
+            "jellyfish" => Ok(Self::Jellyfish),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("Unknown histogram format \"{s}\"")),
+        }
+    }
+}
+
+/// Writes binned histograms in `format`
+pub fn write<W: Write>(
+    out: &mut W,
+    histograms: &Histograms,
+    bins: &Bins,
+    format: HistoFormat,
+) -> io::Result<()> {
+    match format {
+        HistoFormat::Long => write_long(out, histograms, bins),
+        HistoFormat::Jellyfish => {
+            for histogram in histograms.values() {
+                for (bin, kmers) in bins.apply(histogram) {
+                    writeln!(out, "{bin} {kmers}")?
+                }
+            }
+            Ok(())
+        }
+        #[cfg(feature = "json")]
+        HistoFormat::Json => write_json(out, histograms, bins),
+        #[cfg(not(feature = "json"))]
+        HistoFormat::Json => Err(unsupported("JSON histograms", "json")),
+    }
+}
+
+/// Writes histograms as a tidy long-format table, one `k`, `count`,
+/// `kmers` row per abundance, ready for plotting spectra side by side
+fn write_long<W: Write>(out: &mut W, histograms: &Histograms, bins: &Bins) -> io::Result<()> {
+    writeln!(out, "k\tcount\tkmers")?;
+    for (k, histogram) in histograms {
+        for (count, kmers) in bins.apply(histogram) {
+            writeln!(out, "{k}\t{count}\t{kmers}")?
+        }
+    }
+
+    Ok(())
+}
+
+/// JSON output of every k
+#[cfg(feature = "json")]
+#[derive(Serialize)]
+struct JsonHistograms {
+    histograms: Vec<JsonHistogram>,
+}
+
+/// One k's entry in JSON output
+#[cfg(feature = "json")]
+#[derive(Serialize)]
+struct JsonHistogram {
+    k: usize,
+    stats: JsonStats,
+    /// Binned, as `[count, kmers]` pairs
+    histogram: Vec<(u64, u64)>,
+}
+
+/// [`HistogramStats`] with their mean, to four decimal places
+#[cfg(feature = "json")]
+#[derive(Serialize)]
+struct JsonStats {
+    #[serde(flatten)]
+    stats: HistogramStats,
+    mean: f64,
+}
+
+/// Writes `{"histograms":[...]}`, each k's entry holding its binned
+/// `histogram` and the `stats` of the unbinned one
+#[cfg(feature = "json")]
+fn write_json<W: Write>(out: &mut W, histograms: &Histograms, bins: &Bins) -> io::Result<()> {
+    let histograms = histograms
+        .iter()
+        .map(|(k, histogram)| {
+            let stats = HistogramStats::new(histogram);
+            JsonHistogram {
+                k: *k,
+                stats: JsonStats {
+                    stats,
+                    mean: (stats.mean() * 1e4).round() / 1e4,
+                },
+                histogram: bins.apply(histogram).into_iter().collect(),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    serde_json::to_writer(&mut *out, &JsonHistograms { histograms })?;
+    writeln!(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn one_row_per_k_and_abundance() {
+        let fasta = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(fasta.path(), ">a\nACGTNACG\n").unwrap();
+
+        let mut out = Vec::new();
+        let histograms = histograms(fasta.path(), &[2, 3]).unwrap();
+        write(&mut out, &histograms, &Bins::default(), HistoFormat::Long).unwrap();
+        insta::assert_snapshot!(String::from_utf8(out).unwrap(), @r###"
+        k	count	kmers
+        2	2	1
+        2	3	1
+        3	3	1
+        "###);
+    }
+
+    #[test]
+    fn bins_follow_jellyfish_histo() {
+        let bins = Bins::default();
+        assert_eq!(
+            [1, 2, 10000, 10001, 10002, 50000].map(|count| bins.bin(count)),
+            [1, 2, 10000, 10001, 10001, 10001]
+        );
+
+        let bins = Bins {
+            low: 5,
+            high: 20,
+            increment: 5,
+        };
+        assert_eq!(
+            [1, 4, 5, 9, 10, 25, 26, 100].map(|count| bins.bin(count)),
+            [1, 1, 1, 6, 6, 21, 21, 21]
+        );
+
+        let histogram = BTreeMap::from([(1, 7), (3, 2), (4, 1), (30, 5)]);
+        let mut out = Vec::new();
+        let bins = Bins {
+            low: 1,
+            high: 3,
+            increment: 1,
+        };
+        write(
+            &mut out,
+            &BTreeMap::from([(21, histogram)]),
+            &bins,
+            HistoFormat::Jellyfish,
+        )
+        .unwrap();
+        insta::assert_snapshot!(String::from_utf8(out).unwrap(), @r###"
+        1 7
+        3 2
+        4 6
+        "###);
+    }
+
+    #[test]
+    #[cfg(feature = "json")]
+    fn stats_find_the_peak_past_the_error_trough() {
+        let histogram =
+            BTreeMap::from([(1, 50), (2, 10), (3, 4), (5, 8), (6, 12), (7, 9), (40, 1)]);
+        let stats = HistogramStats::new(&histogram);
+        insta::assert_snapshot!(stats.to_string(), @r###"
+        distinct k-mers: 94
+        total k-mers: 297
+        singletons: 50
+        max count: 40
+        mean count: 3.16
+        peak: 6
+        "###);
+        assert_eq!(
+            HistogramStats::new(&BTreeMap::from([(1, 9), (2, 3)])).peak,
+            None
+        );
+
+        let mut out = Vec::new();
+        let bins = Bins {
+            low: 1,
+            high: 6,
+            increment: 1,
+        };
+        write(
+            &mut out,
+            &BTreeMap::from([(21, histogram)]),
+            &bins,
+            HistoFormat::Json,
+        )
+        .unwrap();
+        insta::assert_snapshot!(String::from_utf8(out).unwrap(), @r###"{"histograms":[{"k":21,"stats":{"distinct":94,"total":297,"singletons":50,"max_count":40,"peak":6,"mean":3.1596},"histogram":[[1,50],[2,10],[3,4],[5,8],[6,12],[7,10]]}]}"###);
+    }
+}
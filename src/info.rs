@@ -0,0 +1,84 @@
+//! `krust info --build`: which build of `krust` is running, for bug reports
+//! and the provenance of a pipeline's results.
+
+use std::io::Write;
+
+use serde::Serialize;
+
+/// How this `krust` was built, as recorded by `build.rs`.
+#[derive(Debug, Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    /// The commit built from, suffixed `-dirty` if tracked files had
+    /// changed, or `None` outside a git checkout
+    pub git_hash: Option<&'static str>,
+    pub target: &'static str,
+    /// `debug` or `release`
+    pub profile: &'static str,
+    pub rustc: &'static str,
+    /// Enabled cargo features
+    pub features: Vec<&'static str>,
+}
+
+impl BuildInfo {
+    pub fn new() -> Self {
+        let features = [
+            ("alloc-stats", cfg!(feature = "alloc-stats")),
+            ("cli", cfg!(feature = "cli")),
+            ("compact", cfg!(feature = "compact")),
+            ("compression", cfg!(feature = "compression")),
+            ("json", cfg!(feature = "json")),
+            ("mmap", cfg!(feature = "mmap")),
+            ("needletail", cfg!(feature = "needletail")),
+            ("rust-bio", cfg!(feature = "rust-bio")),
+            ("sketch", cfg!(feature = "sketch")),
+            ("spill", cfg!(feature = "spill")),
+        ];
+
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            git_hash: Some(env!("KRUST_GIT_HASH")).filter(|hash| !hash.is_empty()),
+            target: env!("KRUST_TARGET"),
+            profile: env!("KRUST_PROFILE"),
+            rustc: env!("KRUST_RUSTC"),
+            features: features
+                .into_iter()
+                .filter_map(|(feature, enabled)| enabled.then_some(feature))
+                .collect(),
+        }
+    }
+
+    /// Writes a single line of JSON
+    pub fn write<W: Write>(&self, out: &mut W) -> serde_json::Result<()> {
+        serde_json::to_writer(&mut *out, self)?;
+        writeln!(out).map_err(serde_json::Error::io)
+    }
+}
+
+impl Default for BuildInfo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn build_info_is_one_json_line() {
+        let mut out = Vec::new();
+        BuildInfo::new().write(&mut out).unwrap();
+
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(text.lines().count(), 1);
+        let json: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(json["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(json["target"], env!("KRUST_TARGET"));
+        let features = json["features"].as_array().unwrap();
+        assert_eq!(
+            features.contains(&"rust-bio".into()),
+            cfg!(feature = "rust-bio")
+        );
+    }
+}
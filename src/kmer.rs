@@ -1,6 +1,13 @@
-use std::cmp::Ordering;
+use std::{
+    cmp::Ordering,
+    fmt::{Debug, Display},
+    hash::Hash,
+    io::{self, Write},
+    ops::{BitAnd, BitOr, Not, Shl, Shr},
+};
 
 use bytes::Bytes;
+use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
 
 #[derive(Debug, Default, Eq, PartialEq, Hash)]
 pub struct Kmer {
@@ -11,12 +18,15 @@ pub struct Kmer {
 }
 
 impl Kmer {
+    /// Uppercases soft-masked bases, failing with the index of the first
+    /// byte that isn't a base
     pub fn from_sub(sub: Bytes) -> Result<Self, usize> {
         sub.into_iter()
             .enumerate()
             .map(|(i, byte)| {
                 Ok(match byte {
                     b'A' | b'C' | b'G' | b'T' => byte,
+                    b'a' | b'c' | b'g' | b't' => byte.to_ascii_uppercase(),
                     _ => return Err(i),
                 })
             })
@@ -59,6 +69,238 @@ impl Kmer {
     }
 }
 
+/// An integer holding a k-mer packed two bits a base, the last base in the
+/// lowest bits: a `u64` for k up to 32, or a `u128` for k up to 64.
+///
+/// # Notes
+/// Bases are encoded in alphabetical order, `A=0`, `C=1`, `G=2`, `T=3`, so
+/// packed k-mers of one length order as their strings do. The canonical
+/// k-mer, the lesser of a k-mer and its reverse complement, is then the
+/// same by either rule: lexicographically first, as most tools choose it,
+/// and first by 2-bit encoding, as `jellyfish -C` does. Tools encoding
+/// bases in another order, e.g. `T` before `G`, can canonicalize
+/// differently.
+pub trait Packed:
+    Copy
+    + Default
+    + Ord
+    + Hash
+    + Debug
+    + Display
+    + Send
+    + Sync
+    + 'static
+    + From<u8>
+    + Shl<usize, Output = Self>
+    + Shr<usize, Output = Self>
+    + BitAnd<Output = Self>
+    + BitOr<Output = Self>
+    + Not<Output = Self>
+{
+    /// The longest k-mer that fits
+    const MAX_K: usize;
+
+    /// Width in bytes, as written to raw output and sort spills
+    const BYTES: usize;
+
+    /// The lowest byte, whose low two bits are the last base
+    fn low_byte(self) -> u8;
+
+    fn write_le<W: Write>(self, out: &mut W) -> io::Result<()>;
+
+    /// Reads [`Packed::BYTES`] little-endian bytes
+    fn from_le_slice(bytes: &[u8]) -> Self;
+}
+
+macro_rules! impl_packed {
+    ($($int:ty),*) => {$(
+        impl Packed for $int {
+            const MAX_K: usize = <$int>::BITS as usize / 2;
+
+            const BYTES: usize = std::mem::size_of::<$int>();
+
+            fn low_byte(self) -> u8 {
+                self as u8
+            }
+
+            fn write_le<W: Write>(self, out: &mut W) -> io::Result<()> {
+                out.write_all(&self.to_le_bytes())
+            }
+
+            fn from_le_slice(bytes: &[u8]) -> Self {
+                <$int>::from_le_bytes(bytes.try_into().expect("Packed::BYTES long"))
+            }
+        }
+    )*};
+}
+
+impl_packed!(u64, u128);
+
+/// The base at position `i` of a packed k-mer of length `k`
+fn base_at<P: Packed>(packed_bits: P, k: usize, i: usize) -> KmerByte {
+    KmerByte::from((packed_bits >> (2 * (k - 1 - i))).low_byte() as u64 & 0b11)
+}
+
+/// Packs bases already checked by [`Kmer::from_sub`]
+fn pack<P: Packed>(bases: &[u8]) -> P {
+    bases.iter().fold(P::default(), |acc, base| {
+        let mask: u64 = KmerByte::from(base).into();
+        acc << 2 | P::from(mask as u8)
+    })
+}
+
+/// Reverse complement of a packed k-mer of length `k`
+pub fn reverse_complement_bits<P: Packed>(packed_bits: P, k: usize) -> P {
+    let mask = P::from(0b11);
+    (0..k).fold(P::default(), |acc, i| {
+        acc << 2 | (!packed_bits >> (2 * i) & mask)
+    })
+}
+
+/// Whether a packed k-mer equals its own reverse complement
+///
+/// # Notes
+/// Only possible for even `k`: the middle base of an odd k-mer would have to
+/// be its own complement.
+pub fn is_palindrome<P: Packed>(packed_bits: P, k: usize) -> bool {
+    reverse_complement_bits(packed_bits, k) == packed_bits
+}
+
+/// The four k-mers following a packed k-mer of length `k` in the de Bruijn
+/// graph, its last `k - 1` bases then each of `A`, `C`, `G` and `T`
+pub fn successors<P: Packed>(packed_bits: P, k: usize) -> [P; 4] {
+    let mask = !P::default() >> (2 * (P::MAX_K - k));
+    [0, 1, 2, 3].map(|base| (packed_bits << 2 | P::from(base)) & mask)
+}
+
+/// The four k-mers preceding a packed k-mer of length `k` in the de Bruijn
+/// graph, each of `A`, `C`, `G` and `T` then its first `k - 1` bases
+pub fn predecessors<P: Packed>(packed_bits: P, k: usize) -> [P; 4] {
+    [0, 1, 2, 3].map(|base| P::from(base) << (2 * (k - 1)) | packed_bits >> 2)
+}
+
+/// Unpacks a single packed k-mer into its bases
+pub fn unpack_to_string<P: Packed>(packed_bits: P, k: usize) -> String {
+    let mut kmer = String::with_capacity(k);
+    unpack_into(packed_bits, k, &mut kmer);
+    kmer
+}
+
+/// Unpacks a packed k-mer into `buf`, replacing its contents, so one buffer
+/// can be reused across many k-mers
+pub fn unpack_into<P: Packed>(packed_bits: P, k: usize, buf: &mut String) {
+    buf.clear();
+    buf.extend((0..k).map(|i| char::from(u8::from(base_at(packed_bits, k, i)))))
+}
+
+/// Marks bytes in [`CODES`] that aren't bases.
+const INVALID: u8 = 4;
+
+/// Each byte's 2-bit base, soft-masked bases included, or [`INVALID`]
+const CODES: [u8; 256] = {
+    let mut codes = [INVALID; 256];
+    let mut i = 0;
+    while i < 4 {
+        codes[b"ACGT"[i] as usize] = i as u8;
+        codes[b"acgt"[i] as usize] = i as u8;
+        i += 1;
+    }
+    codes
+};
+
+/// The packed k-mers of `seq` as read, with their start positions and
+/// reverse complements, skipping any containing an invalid byte
+///
+/// # Notes
+/// Both are rolled along a base at a time, the next base shifted into the
+/// bottom of the k-mer and its complement into the top of the reverse
+/// complement, rather than each k-mer being packed from scratch.
+pub fn stranded_kmers<P: Packed>(seq: &[u8], k: usize) -> StrandedKmers<'_, P> {
+    StrandedKmers {
+        bytes: seq.iter().enumerate(),
+        k,
+        mask: !P::default() >> (2 * (P::MAX_K - k)),
+        forward: P::default(),
+        reverse: P::default(),
+        run: 0,
+    }
+}
+
+/// The iterator [`stranded_kmers`] returns.
+pub struct StrandedKmers<'a, P: Packed> {
+    bytes: std::iter::Enumerate<std::slice::Iter<'a, u8>>,
+    k: usize,
+    /// The low `2k` bits
+    mask: P,
+    forward: P,
+    reverse: P,
+    /// Valid bases read since the last invalid one
+    run: usize,
+}
+
+impl<P: Packed> Iterator for StrandedKmers<'_, P> {
+    /// Start position, packed k-mer and packed reverse complement
+    type Item = (usize, P, P);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for (i, byte) in self.bytes.by_ref() {
+            let code = CODES[*byte as usize];
+            if code == INVALID {
+                self.run = 0;
+                continue;
+            }
+            self.forward = ((self.forward << 2) | P::from(code)) & self.mask;
+            self.reverse = (self.reverse >> 2) | (P::from(3 - code) << (2 * (self.k - 1)));
+            self.run += 1;
+
+            if self.run >= self.k {
+                return Some((i + 1 - self.k, self.forward, self.reverse));
+            }
+        }
+
+        None
+    }
+}
+
+/// The packed k-mers of `seq` as read, with their start positions, skipping
+/// any containing an invalid byte
+pub fn positioned_kmers<P: Packed>(seq: &Bytes, k: usize) -> impl Iterator<Item = (usize, P)> + '_ {
+    stranded_kmers::<P>(seq, k).map(|(start, packed_bits, _)| (start, packed_bits))
+}
+
+/// The packed canonical k-mers of `seq`, in order, skipping any containing
+/// an invalid byte
+pub fn canonical_kmers<P: Packed>(seq: &Bytes, k: usize) -> impl Iterator<Item = P> + '_ {
+    stranded_kmers::<P>(seq, k).map(|(_, packed_bits, reverse)| packed_bits.min(reverse))
+}
+
+/// Unpacks `(packed k-mer, count)` pairs in parallel using [`rayon`](https://docs.rs/rayon/1.5.1/rayon/)
+pub fn unpack_many<P: Packed, C: Copy + Send + Sync>(
+    packed: &[(P, C)],
+    k: usize,
+) -> Vec<(String, C)> {
+    packed
+        .par_iter()
+        .map(|&(packed_bits, count)| (unpack_to_string(packed_bits, k), count))
+        .collect()
+}
+
+/// Packs k-mer strings in parallel using [`rayon`](https://docs.rs/rayon/1.5.1/rayon/)
+///
+/// # Notes
+/// Each error holds the index of the first invalid byte, or, for a string
+/// that is not `k` long, the index at which the length stops matching.
+pub fn pack_many<P: Packed>(kmers: &[&str], k: usize) -> Vec<Result<P, usize>> {
+    kmers
+        .par_iter()
+        .map(|kmer| match kmer.len() {
+            len if len != k => Err(len.min(k)),
+            _ => Kmer::from_sub(Bytes::copy_from_slice(kmer.as_bytes()))
+                .map(|kmer| pack(&kmer.bytes)),
+        })
+        .collect()
+}
+
 impl FromIterator<u8> for Kmer {
     fn from_iter<I: IntoIterator<Item = u8>>(iter: I) -> Self {
         Self {
@@ -68,6 +310,89 @@ impl FromIterator<u8> for Kmer {
     }
 }
 
+/// A k-mer whose length `K` is known at compile time, packed into a `u64`.
+///
+/// # Notes
+/// [`Kmer`] remains the general path for a `k` chosen at runtime. Library
+/// consumers that fix `k` up front can use this type instead, letting the
+/// compiler unroll the per-base loops in packing, canonicalization and
+/// neighbor generation.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct FixedKmer<const K: usize>(u64);
+
+impl<const K: usize> FixedKmer<K> {
+    const VALID_K: () = assert!(K > 0 && K < 33, "k-mer length must be between 1 and 32");
+
+    const MASK: u64 = u64::MAX >> (64 - 2 * K);
+
+    /// Packs `K` bases, upper or lower case as [`Kmer::from_sub`] takes them,
+    /// returning the index of the first invalid byte on failure
+    pub fn from_bytes(bytes: &[u8; K]) -> Result<Self, usize> {
+        let () = Self::VALID_K;
+
+        let mut packed_bits = 0;
+        for (i, byte) in bytes.iter().enumerate() {
+            let mask: u64 = match byte.to_ascii_uppercase() {
+                byte @ (b'A' | b'C' | b'G' | b'T') => KmerByte::from(&byte).into(),
+                _ => return Err(i),
+            };
+            packed_bits = packed_bits << 2 | mask;
+        }
+
+        Ok(Self(packed_bits))
+    }
+
+    /// Takes the low `2 * K` bits of `packed_bits`
+    pub fn from_packed(packed_bits: u64) -> Self {
+        let () = Self::VALID_K;
+
+        Self(packed_bits & Self::MASK)
+    }
+
+    pub fn packed_bits(self) -> u64 {
+        self.0
+    }
+
+    pub fn reverse_complement(self) -> Self {
+        let complement = !self.0 & Self::MASK;
+        let mut packed_bits = 0;
+        for i in 0..K {
+            packed_bits = packed_bits << 2 | (complement >> (2 * i) & 0b11);
+        }
+
+        Self(packed_bits)
+    }
+
+    /// Lexicographically smaller of k-mer/reverse-complement
+    ///
+    /// # Notes
+    /// With `A < C < G < T` encoded as `0 < 1 < 2 < 3`, comparing packed bits
+    /// is the same as comparing the bases.
+    pub fn canonical(self) -> Self {
+        self.min(self.reverse_complement())
+    }
+
+    /// The `3 * K` k-mers at Hamming distance one
+    pub fn neighbors(self) -> impl Iterator<Item = Self> {
+        (0..K).flat_map(move |i| {
+            let shift = 2 * (K - 1 - i);
+            let base = self.0 >> shift & 0b11;
+            (0..4)
+                .filter(move |&other| other != base)
+                .map(move |other| Self(self.0 & !(0b11 << shift) | other << shift))
+        })
+    }
+
+    pub fn unpack(self) -> [u8; K] {
+        let mut bytes = [0; K];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = KmerByte::from(self.0 >> (2 * (K - 1 - i)) & 0b11).into();
+        }
+
+        bytes
+    }
+}
+
 pub enum KmerByte {
     A,
     C,
@@ -132,6 +457,8 @@ impl KmerByte {
     }
 }
 
+// Some tests spell sequences out byte by byte
+#[allow(clippy::byte_char_slices)]
 #[cfg(test)]
 pub mod test {
     use super::*;
@@ -172,4 +499,137 @@ pub mod test {
         let res = Kmer::from_sub(Bytes::copy_from_slice(dna));
         assert_eq!(Err(4), res);
     }
+
+    #[test]
+    fn fixed_kmer_matches_runtime_path() {
+        let mut kmer = Kmer::from_sub(Bytes::from_static(b"GATTACA")).unwrap();
+        kmer.pack_bits();
+        kmer.canonical();
+
+        let fixed = FixedKmer::<7>::from_bytes(b"GATTACA").unwrap();
+        assert_eq!(fixed.packed_bits(), kmer.packed_bits);
+        assert_eq!(&fixed.unpack(), b"GATTACA");
+        assert_eq!(&fixed.reverse_complement().unpack(), b"TGTAATC");
+        assert_eq!(&fixed.canonical().unpack(), kmer.bytes.as_ref());
+        assert_eq!(FixedKmer::<3>::from_bytes(b"ANC"), Err(1));
+    }
+
+    #[test]
+    fn fixed_kmer_takes_soft_masked_bases() {
+        let mut kmer = Kmer::from_sub(Bytes::from_static(b"gatTACA")).unwrap();
+        kmer.pack_bits();
+
+        let fixed = FixedKmer::<7>::from_bytes(b"gatTACA").unwrap();
+        assert_eq!(fixed.packed_bits(), kmer.packed_bits);
+        assert_eq!(fixed, FixedKmer::<7>::from_bytes(b"GATTACA").unwrap());
+        assert_eq!(FixedKmer::<3>::from_bytes(b"acn"), Err(2));
+    }
+
+    #[test]
+    fn fixed_kmer_neighbors() {
+        let kmer = FixedKmer::<2>::from_bytes(b"AC").unwrap();
+        let neighbors = kmer
+            .neighbors()
+            .map(|n| String::from_utf8(n.unpack().to_vec()).unwrap())
+            .collect::<Vec<_>>();
+        insta::assert_snapshot!(neighbors.join(","), @"CC,GC,TC,AA,AG,AT");
+
+        let kmer = FixedKmer::<32>::from_packed(u64::MAX);
+        assert_eq!(kmer.reverse_complement().packed_bits(), 0);
+        assert_eq!(kmer.neighbors().count(), 96);
+    }
+
+    #[test]
+    fn bulk_pack_and_unpack_round_trip() {
+        let packed = pack_many::<u64>(&["ACGT", "TTTT", "ACNT", "ACG"], 4);
+        assert_eq!(packed, vec![Ok(0b00011011), Ok(0b11111111), Err(2), Err(3)]);
+
+        let unpacked = unpack_many(&[(0b00011011u64, 3u64), (0b11111111, 1)], 4);
+        assert_eq!(
+            unpacked,
+            vec![("ACGT".to_string(), 3), ("TTTT".to_string(), 1)]
+        );
+    }
+
+    #[test]
+    fn wide_kmers_pack_beyond_k_32() {
+        let kmer = "ACGTTGCAACGGTACCATGGACGTACGGTAACCATGCATTGACCAGTTCAGAGCTAGGCTTACC";
+        assert_eq!(kmer.len(), 64);
+
+        let packed = pack_many::<u128>(&[kmer], 64)[0].unwrap();
+        assert_eq!(unpack_to_string(packed, 64), kmer);
+        assert_eq!(
+            unpack_to_string(reverse_complement_bits(packed, 64), 64),
+            "GGTAAGCCTAGCTCTGAACTGGTCAATGCATGGTTACCGTACGTCCATGGTACCGTTGCAACGT"
+        );
+
+        // Both widths agree wherever a k-mer fits in either
+        let seq = Bytes::from_static(kmer.as_bytes());
+        let narrow = canonical_kmers::<u64>(&seq, 31).map(u128::from);
+        assert!(narrow.eq(canonical_kmers::<u128>(&seq, 31)));
+    }
+
+    #[test]
+    fn rolled_kmers_match_packing_each_window() {
+        let seq = Bytes::from_static(
+            b"ACGTTGCAACGGTACCATGGNACGTACGGTAACCatgcattGACCAGTTCAGAGCTAGGCTTACCnnACGTTGCAACGGTACCATGGACGTACGGTAACCATGCATTGACCAGTT",
+        );
+
+        fn check<P: Packed>(seq: &Bytes, k: usize) {
+            let packed_each = (0..=seq.len() - k).filter_map(|start| {
+                let kmer = Kmer::from_sub(seq.slice(start..start + k)).ok()?;
+                let packed_bits = pack::<P>(&kmer.bytes);
+                Some((start, packed_bits, reverse_complement_bits(packed_bits, k)))
+            });
+            assert!(stranded_kmers::<P>(seq, k).eq(packed_each), "k = {k}");
+        }
+        for k in 1..=u64::MAX_K {
+            check::<u64>(&seq, k)
+        }
+        for k in [1, 31, 33, 47, u128::MAX_K] {
+            check::<u128>(&seq, k)
+        }
+    }
+
+    #[test]
+    fn packed_order_is_lexicographic_order() {
+        let kmers = [
+            "AAAT", "ACGT", "AGTA", "CAAA", "CGCG", "GTAC", "TAAA", "TTTG",
+        ];
+        let packed = pack_many::<u64>(&kmers, 4);
+        for (a, packed_a) in kmers.iter().zip(&packed) {
+            for (b, packed_b) in kmers.iter().zip(&packed) {
+                assert_eq!(packed_a.cmp(packed_b), a.cmp(b), "{a} against {b}");
+            }
+        }
+    }
+
+    #[test]
+    fn successors_and_predecessors_overlap_by_k_minus_1() {
+        let kmer = pack::<u64>(b"ACGT");
+        let unpacked = |kmers: [u64; 4]| kmers.map(|kmer| unpack_to_string(kmer, 4)).join(",");
+        insta::assert_snapshot!(unpacked(successors(kmer, 4)), @"CGTA,CGTC,CGTG,CGTT");
+        insta::assert_snapshot!(unpacked(predecessors(kmer, 4)), @"AACG,CACG,GACG,TACG");
+
+        let wide = pack::<u128>(&[b'T'; 64]);
+        assert_eq!(successors(wide, 64)[3], wide);
+        assert_eq!(predecessors(wide, 64)[3], wide);
+    }
+
+    #[test]
+    fn palindromes_need_even_k() {
+        let packed = pack_many::<u64>(&["ACGT", "GATC", "ACGA"], 4);
+        let palindromes = packed
+            .into_iter()
+            .map(|packed_bits| is_palindrome(packed_bits.unwrap(), 4))
+            .collect::<Vec<_>>();
+        assert_eq!(palindromes, vec![true, true, false]);
+
+        let packed = pack_many::<u64>(&["ACG"], 3)[0].unwrap();
+        assert!(!is_palindrome(packed, 3));
+        assert_eq!(
+            unpack_to_string(reverse_complement_bits(packed, 3), 3),
+            "CGT"
+        );
+    }
 }
@@ -20,13 +20,61 @@
 //! or, searching for kmers of length 21:
 //! ```$ cargo run --release 21 path/to/cerevisae.pan.fa > output.tsv```
 //!
-//! Future:
-//! - ```fn single_sequence_canonical_kmers(filepath: String, k: usize) {}```
-//! Returns k-mer counts for individual sequences in a fasta file.
-//! - Testing!
+//! Counting builds on `rayon`, `dashmap`, `bytes`, `fxhash` and `thiserror`
+//! alone, so embedding `krust` with `default-features = false` leaves out
+//! `clap`, `colored` and every other dependency, reading files with its own
+//! FASTA/FASTQ parser. `rust-bio` or `needletail` picks a reader, and
+//! `compression`, `json`, `mmap`, `sketch` and `spill` add back what they name
+//! in `Cargo.toml`; the subcommands only `cli` needs, such as `estimate` and
+//! `preview`, build with it.
+//!
+//! [`per_record::count_kmers_per_record`] returns k-mer counts for each
+//! record of a file on its own, as `--per-record` writes them.
 
+pub mod bias;
+pub mod bloom;
+#[cfg(feature = "sketch")]
+pub mod classify;
+#[cfg(feature = "cli")]
 pub mod cli;
+#[cfg(feature = "compact")]
+pub mod compact;
 pub mod config;
+#[cfg(feature = "cli")]
+pub mod cycles;
+pub mod dedup;
+pub mod density;
+pub mod diffkmers;
+#[cfg(feature = "cli")]
+pub mod estimate;
+pub mod external;
+#[cfg(feature = "sketch")]
+pub mod gather;
+pub mod hasher;
+pub mod histo;
+#[cfg(feature = "json")]
+pub mod info;
 pub mod kmer;
+pub mod memory;
+pub mod minimizer;
+pub mod output;
+pub mod paired;
+pub mod per_record;
+#[cfg(feature = "cli")]
+pub mod preview;
+pub mod progress;
+pub mod quality;
 pub mod reader;
+pub mod repeats;
 pub mod run;
+pub mod samples;
+pub mod set_ops;
+#[cfg(feature = "sketch")]
+pub mod signature;
+#[cfg(feature = "sketch")]
+pub mod sketch;
+pub mod sort;
+pub mod spill;
+#[cfg(feature = "cli")]
+pub mod suggest;
+pub mod summary;
@@ -1,54 +1,531 @@
-use std::process;
+use std::{
+    error::Error,
+    fmt::Display,
+    fs::{self, File},
+    io::{self, stdout, BufWriter, Write},
+    path::PathBuf,
+    process,
+};
 
+use clap::{parser::ValueSource, ArgMatches};
 use colored::Colorize;
-use krust::{cli, config::Config, run};
+use krust::{
+    classify, cli,
+    config::Config,
+    cycles, density,
+    diffkmers::{self, Groups, GroupsError},
+    estimate, gather, histo,
+    info::BuildInfo,
+    kmer::Packed,
+    output::{Compression, OutputFormat},
+    preview, repeats,
+    run::{self, Backend},
+    samples::SampleSheet,
+    set_ops::{self, SetOp},
+    signature, sketch,
+    sort::SortOrder,
+    suggest,
+};
 
 fn main() {
     let matches = cli::cli().get_matches();
 
-    let k = matches.get_one::<String>("k").expect("required");
-    let path = matches.get_one::<String>("path").expect("required");
-
-    let config = Config::new(k, path).unwrap_or_else(|e| {
-        println!();
-        println!(
-            "{}\n {}",
-            "Problem parsing arguments:".blue().bold(),
-            e.to_string().blue()
-        );
-        println!();
-        println!(
-            "{}\n {}\n  {}\n   {}",
-            "Help menu:".blue().bold(),
-            "$ cargo run -- --help".bold(),
-            "or".underline(),
-            "$ krust --help".bold()
+    if let Some(("classify", matches)) = matches.subcommand() {
+        let path = matches.get_one::<PathBuf>("path").expect("required");
+        let refs = matches.get_one::<PathBuf>("refs").expect("required");
+        let k = *matches.get_one::<u64>("k").expect("defaulted") as usize;
+        let size = *matches.get_one::<usize>("sketch").expect("defaulted");
+        let batch = *matches.get_one::<u64>("batch").expect("defaulted") as usize;
+
+        let written = classify::references(refs, k, size).and_then(|references| {
+            let mut out = BufWriter::new(stdout());
+            classify::classify(path, &references, batch, &mut out)?;
+            Ok(out.flush()?)
+        });
+        if let Err(e) = written {
+            application_error(e)
+        }
+        return;
+    }
+
+    if let Some(("cycles", matches)) = matches.subcommand() {
+        let path = matches.get_one::<PathBuf>("path").expect("required");
+        let k = *matches.get_one::<u64>("k").expect("defaulted") as usize;
+
+        let written = cycles::cycle_counts(path, k).and_then(|counts| {
+            let mut out = BufWriter::new(stdout());
+            cycles::write(&mut out, &counts, k)?;
+            Ok(out.flush()?)
+        });
+        if let Err(e) = written {
+            application_error(e)
+        }
+        return;
+    }
+
+    if let Some(("diffkmers", matches)) = matches.subcommand() {
+        let samples = sample_sheet(matches).expect("required");
+        let groups: Groups = matches
+            .get_one::<String>("groups")
+            .expect("required")
+            .parse()
+            .unwrap_or_else(|e: GroupsError| argument_error(e.into()));
+        let k = *matches.get_one::<u64>("k").expect("defaulted") as usize;
+        let min_count = *matches.get_one::<u64>("min-count").expect("defaulted");
+        let fdr = *matches.get_one::<f64>("fdr").expect("defaulted");
+
+        let written =
+            diffkmers::diffkmers(&samples, &groups, k, min_count, fdr).and_then(|kmers| {
+                let out: Box<dyn Write> = match matches.get_one::<PathBuf>("output") {
+                    Some(path) => Box::new(File::create(path)?),
+                    None => Box::new(stdout()),
+                };
+                let mut out = BufWriter::new(out);
+                diffkmers::write(&mut out, &kmers)?;
+                Ok(out.flush()?)
+            });
+        if let Err(e) = written {
+            application_error(e)
+        }
+        return;
+    }
+
+    if let Some(("dist", matches)) = matches.subcommand() {
+        let a = matches.get_one::<PathBuf>("a").expect("required");
+        let b = matches.get_one::<PathBuf>("b").expect("required");
+        let k = *matches.get_one::<u64>("k").expect("defaulted") as usize;
+        let size = *matches.get_one::<usize>("sketch").expect("defaulted");
+
+        let written = sketch::dist(a, b, k, size).and_then(|distances| {
+            let mut out = BufWriter::new(stdout());
+            sketch::write_distances(&mut out, &distances)?;
+            Ok(out.flush()?)
+        });
+        if let Err(e) = written {
+            application_error(e)
+        }
+        return;
+    }
+
+    if let Some(("dist-matrix", matches)) = matches.subcommand() {
+        let samples = sample_sheet(matches);
+        let paths = sample_paths(matches, samples.as_ref());
+        let k = *matches.get_one::<u64>("k").expect("defaulted") as usize;
+        let size = *matches.get_one::<usize>("sketch").expect("defaulted");
+        let output = matches.get_one::<PathBuf>("output");
+        let tree = matches
+            .get_one::<PathBuf>("tree")
+            .cloned()
+            .or_else(|| output.map(|output| output.with_extension("nwk")));
+
+        let written = sketch::distance_matrix(&paths, k, size, samples.as_ref()).and_then(
+            |(names, distances)| {
+                let out: Box<dyn Write> = match output {
+                    Some(path) => Box::new(File::create(path)?),
+                    None => Box::new(stdout()),
+                };
+                let mut out = BufWriter::new(out);
+                let groups = samples.as_ref().and_then(|samples| samples.groups(&names));
+                sketch::write_matrix(&mut out, &names, groups.as_deref(), &distances)?;
+                out.flush()?;
+                if let Some(tree) = tree {
+                    fs::write(tree, sketch::neighbor_joining(&names, &distances) + "\n")?;
+                }
+                Ok(())
+            },
         );
-        println!();
-        process::exit(1);
-    });
+        if let Err(e) = written {
+            application_error(e)
+        }
+        return;
+    }
 
-    println!("{}: {}", "k-length".bold(), k.blue().bold());
-    println!("{}: {}", "data".bold(), path.underline().bold().blue());
-    println!(
+    if let Some(("estimate", matches)) = matches.subcommand() {
+        let k = *matches.get_one::<u64>("k").expect("required") as usize;
+        let path = matches.get_one::<PathBuf>("path").expect("required");
+        let precision = *matches.get_one::<u8>("precision").expect("defaulted");
+        match estimate::estimate(path, k, precision, matches.get_flag("singletons")) {
+            Ok(estimate) => println!("{estimate}"),
+            Err(e) => application_error(e),
+        }
+        return;
+    }
+
+    if let Some(("gather", matches)) = matches.subcommand() {
+        let query = matches.get_one::<PathBuf>("query").expect("required");
+        let refs = matches
+            .get_many::<PathBuf>("refs")
+            .expect("required")
+            .collect::<Vec<_>>();
+        let k = *matches.get_one::<u64>("k").expect("defaulted") as usize;
+        let scaled = *matches.get_one::<u64>("scaled").expect("defaulted");
+        let threshold_bp = *matches.get_one::<u64>("threshold-bp").expect("defaulted");
+
+        let written = gather::references(&refs, k, scaled).and_then(|references| {
+            let query = gather::query(query, k, scaled)?;
+            let mut out = BufWriter::new(stdout());
+            gather::write(&mut out, &gather::gather(&query, &references, threshold_bp))?;
+            Ok(out.flush()?)
+        });
+        if let Err(e) = written {
+            application_error(e)
+        }
+        return;
+    }
+
+    if let Some(("histo", matches)) = matches.subcommand() {
+        let path = matches.get_one::<PathBuf>("path").expect("required");
+        let ks = matches
+            .get_many::<u64>("k")
+            .expect("required")
+            .map(|k| *k as usize)
+            .collect::<Vec<_>>();
+        let bins = histo::Bins {
+            low: *matches.get_one::<u64>("low").expect("defaulted"),
+            high: *matches.get_one::<u64>("high").expect("defaulted"),
+            increment: *matches.get_one::<u64>("increment").expect("defaulted"),
+        };
+        let format: histo::HistoFormat = match matches.get_flag("json") {
+            true => histo::HistoFormat::Json,
+            false => matches
+                .get_one::<String>("format")
+                .expect("defaulted")
+                .parse()
+                .expect("validated by clap"),
+        };
+        if format == histo::HistoFormat::Jellyfish && ks.len() > 1 {
+            argument_error("--format jellyfish writes a histogram for a single k".into())
+        }
+
+        let written = histo::histograms(path, &ks).and_then(|histograms| {
+            let mut out = BufWriter::new(stdout());
+            histo::write(&mut out, &histograms, &bins, format)?;
+            // JSON carries the statistics; tables keep them off stdout
+            if format != histo::HistoFormat::Json {
+                for (k, histogram) in &histograms {
+                    eprintln!("k: {k}\n{}", histo::HistogramStats::new(histogram))
+                }
+            }
+            Ok(out.flush()?)
+        });
+        if let Err(e) = written {
+            application_error(e)
+        }
+        return;
+    }
+
+    if let Some(("minimizer-density", matches)) = matches.subcommand() {
+        let path = matches.get_one::<PathBuf>("path").expect("required");
+        let params = density::DensityParams {
+            k: *matches.get_one::<u64>("k").expect("defaulted") as usize,
+            w: *matches.get_one::<u64>("w").expect("defaulted") as usize,
+            window: *matches.get_one::<u64>("window").expect("defaulted") as usize,
+            max_gap: *matches.get_one::<u64>("max-gap").expect("defaulted") as usize,
+        };
+
+        let written = density::densities(path, &params).and_then(|windows| {
+            let mut out = BufWriter::new(stdout());
+            density::write(&mut out, &windows)?;
+            Ok(out.flush()?)
+        });
+        if let Err(e) = written {
+            application_error(e)
+        }
+        return;
+    }
+
+    if let Some(("preview", matches)) = matches.subcommand() {
+        let path = matches.get_one::<PathBuf>("path").expect("required");
+        let k = *matches.get_one::<u64>("k").expect("defaulted") as usize;
+        let head_mb = *matches.get_one::<usize>("head-mb").expect("defaulted");
+        let sample = *matches.get_one::<usize>("sample").expect("defaulted");
+        match preview::preview(path, k, head_mb, sample) {
+            Ok(preview) => println!("{preview}"),
+            Err(e) => application_error(e),
+        }
+        return;
+    }
+
+    if let Some(("repeats", matches)) = matches.subcommand() {
+        let path = matches.get_one::<PathBuf>("path").expect("required");
+        let k = *matches.get_one::<u64>("k").expect("defaulted") as usize;
+        let min_count = *matches.get_one::<i32>("min-count").expect("defaulted");
+
+        let min_length = *matches.get_one::<usize>("min-length").expect("defaulted");
+
+        let out = || -> io::Result<BufWriter<Box<dyn Write>>> {
+            Ok(BufWriter::new(match matches.get_one::<PathBuf>("output") {
+                Some(path) => Box::new(File::create(path)?),
+                None => Box::new(stdout()),
+            }))
+        };
+
+        let written = match matches.get_flag("seeds") {
+            true => repeats::seeds(path, k, min_count, min_length).and_then(|seeds| {
+                let mut out = out()?;
+                repeats::write_seeds(&mut out, &seeds)?;
+                Ok(out.flush()?)
+            }),
+            false => repeats::repeats(path, k, min_count).and_then(|intervals| {
+                let mut out = out()?;
+                repeats::write(&mut out, &intervals)?;
+                Ok(out.flush()?)
+            }),
+        };
+        if let Err(e) = written {
+            application_error(e)
+        }
+        return;
+    }
+
+    if let Some(("set-op", matches)) = matches.subcommand() {
+        let op: SetOp = matches
+            .get_one::<String>("op")
+            .expect("required")
+            .parse()
+            .expect("validated by clap");
+        let a = matches.get_one::<PathBuf>("a").expect("required");
+        let b = matches.get_one::<PathBuf>("b").expect("required");
+
+        let written = match matches.get_one::<PathBuf>("output") {
+            Some(path) => File::create(path)
+                .map_err(Into::into)
+                .and_then(|file| set_ops::combine(a, b, op, file)),
+            None => set_ops::combine(a, b, op, stdout()),
+        };
+        if let Err(e) = written {
+            application_error(e)
+        }
+        return;
+    }
+
+    if let Some(("sketch", matches)) = matches.subcommand() {
+        let samples = sample_sheet(matches);
+        let paths = sample_paths(matches, samples.as_ref());
+        let k = *matches.get_one::<u64>("k").expect("defaulted") as usize;
+        let scaled = *matches.get_one::<u64>("scaled").expect("defaulted");
+        let num = matches.get_one::<usize>("num").copied();
+        let output = matches.get_one::<PathBuf>("output");
+
+        let written =
+            signature::sketch(&paths, k, num, scaled, samples.as_ref()).and_then(|signatures| {
+                let out: Box<dyn Write> = match output {
+                    Some(path) => Box::new(File::create(path)?),
+                    None => Box::new(stdout()),
+                };
+                let mut out = BufWriter::new(out);
+                match output.is_some_and(signature::is_binary) {
+                    true => signature::write_binary(&mut out, &signatures)?,
+                    false => signature::write(&mut out, &signatures)?,
+                }
+                Ok(out.flush()?)
+            });
+        if let Err(e) = written {
+            application_error(e)
+        }
+        return;
+    }
+
+    if let Some(("info", _)) = matches.subcommand() {
+        if let Err(e) = BuildInfo::new().write(&mut stdout()) {
+            application_error(e)
+        }
+        return;
+    }
+
+    if let Some(("suggest-k", matches)) = matches.subcommand() {
+        let genome_size = *matches.get_one::<u64>("genome-size").expect("required");
+        let path = matches.get_one::<PathBuf>("path").expect("required");
+        let head_mb = *matches.get_one::<usize>("head-mb").expect("defaulted");
+        let sample = *matches.get_one::<usize>("sample").expect("defaulted");
+        match suggest::suggest_k(path, genome_size, head_mb, sample) {
+            Ok(suggestion) => println!("{suggestion}"),
+            Err(e) => application_error(e),
+        }
+        return;
+    }
+
+    let k = matches.get_one::<String>("k").expect("required");
+    let paths = matches
+        .get_many::<String>("path")
+        .expect("required")
+        .cloned()
+        .collect::<Vec<_>>();
+
+    let mut config = Config::new(k, &paths).unwrap_or_else(|e| argument_error(e));
+    config.strand = matches.get_flag("strand");
+    config.report_palindromes = matches.get_flag("report-palindromes");
+    if config.report_palindromes && config.k % 2 == 1 {
+        argument_error("--report-palindromes needs an even k, as no k-mer of odd length is its own reverse complement".into())
+    }
+    config.lower_count = matches.get_one::<u64>("lower-count").copied();
+    config.upper_count = matches.get_one::<u64>("upper-count").copied();
+    config.paired = matches.get_flag("paired");
+    config.dedup_exact = matches.get_flag("dedup-exact");
+    config.trim_quality = matches.get_one::<u8>("trim-quality").copied();
+    config.filter_singletons = matches.get_flag("filter-singletons");
+    config.singleton_spill = matches.get_flag("singleton-spill");
+    config.max_memory = matches
+        .get_one::<u64>("max-memory")
+        .map(|mb| (*mb as usize) << 20);
+    config.distinct = matches.get_flag("distinct");
+    config.stride = matches
+        .get_one::<u64>("stride")
+        .map(|stride| *stride as usize);
+    config.no_canonical = matches.get_flag("no-canonical");
+    config.minimizer_window = matches
+        .get_one::<u64>("minimizer-window")
+        .map(|w| *w as usize);
+    config.format = matches
+        .get_one::<String>("format")
+        .expect("defaulted")
+        .parse()
+        .expect("validated by clap");
+    config.delimiter = matches.get_one::<char>("delimiter").copied();
+    config.with_packed = matches.get_flag("with-packed");
+    config.json_flat = matches.get_flag("json-flat");
+    config.fasta_header = matches.get_flag("fasta-header");
+    config.output = matches.get_one::<PathBuf>("output").cloned();
+    config.progress = matches.get_flag("progress") || matches.contains_id("progress-format");
+    if let Some(format) = matches.get_one::<String>("progress-format") {
+        config.progress_format = format.parse().expect("validated by clap");
+    }
+    config.deterministic = matches.get_flag("deterministic");
+    config.report = matches.get_flag("report");
+    config.bias_report = matches.get_one::<PathBuf>("bias-report").cloned();
+    config.mmap = matches.get_flag("mmap");
+    config.expected_kmers = matches.get_one::<usize>("expected-kmers").copied();
+    config.hasher = matches
+        .get_one::<String>("hasher")
+        .expect("defaulted")
+        .parse()
+        .expect("validated by clap");
+    if let Some(backend) = matches.get_one::<String>("backend") {
+        config.backend = backend.parse().expect("validated by clap");
+    }
+    if config.backend == Backend::Compact {
+        if !cfg!(feature = "compact") {
+            argument_error("--backend compact needs krust built with --features compact".into())
+        }
+        if config.k > u64::MAX_K {
+            argument_error("--backend compact counts k-mers of at most 32 bases".into())
+        }
+    }
+    config.sort = matches
+        .get_one::<String>("sort")
+        .map(|sort| sort.parse().expect("validated by clap"))
+        .or(config.deterministic.then_some(SortOrder::Kmer));
+    config.summary_per_record = matches.get_flag("summary-per-record");
+    config.per_record = matches.get_flag("per-record");
+    let only_records = matches.get_many::<String>("only-records");
+    let records_file = matches.get_one::<String>("records-file");
+    if only_records.is_some() || records_file.is_some() {
+        let mut records = match records_file {
+            Some(path) => Config::read_record_ids(path).unwrap_or_else(|e| argument_error(e)),
+            None => Default::default(),
+        };
+        records.extend(only_records.into_iter().flatten().cloned());
+        config.records = Some(records);
+    }
+    config.compression = match matches.get_one::<String>("compress") {
+        Some(compression) => compression.parse().expect("validated by clap"),
+        None => config
+            .output
+            .as_ref()
+            .map(Compression::from_extension)
+            .unwrap_or_default(),
+    };
+    if config.format == OutputFormat::Histogram {
+        // Options that shape k-mer records, or count other than into the
+        // map that keeps the histogram
+        let records_only = [
+            "distinct",
+            "singleton-spill",
+            "max-memory",
+            "backend",
+            "strand",
+            "with-packed",
+            "lower-count",
+            "upper-count",
+            "report-palindromes",
+            "sort",
+            "json-flat",
+            "fasta-header",
+            "delimiter",
+        ];
+        if let Some(arg) = records_only
+            .iter()
+            .find(|arg| matches.value_source(arg) == Some(ValueSource::CommandLine))
+        {
+            argument_error(format!("--format histogram can't be combined with --{arg}").into())
+        }
+    }
+
+    // Keep stdout clean for the counts themselves
+    eprintln!("{}: {}", "k-length".bold(), k.blue().bold());
+    eprintln!(
+        "{}: {}",
+        "data".bold(),
+        paths.join(", ").underline().bold().blue()
+    );
+    eprintln!(
         "{}: {}",
         "reader".bold(),
-        match cfg!(feature = "needletail") {
-            true => "needletail",
-            _ => "rust-bio",
+        match (cfg!(feature = "needletail"), cfg!(feature = "rust-bio")) {
+            (true, _) => "needletail",
+            (_, true) => "rust-bio",
+            _ => "krust",
         }
         .blue()
         .bold()
     );
-    println!();
+    eprintln!();
 
-    if let Err(e) = run::run(config.path, config.k) {
-        eprintln!(
-            "{}\n {}",
-            "Application error:".blue().bold(),
-            e.to_string().blue()
-        );
-        drop(e);
-        process::exit(1);
+    if let Err(e) = run::run(config) {
+        application_error(e)
     }
 }
+
+/// The `--samples` sheet, if given
+fn sample_sheet(matches: &ArgMatches) -> Option<SampleSheet> {
+    let path = matches.get_one::<PathBuf>("samples")?;
+    Some(SampleSheet::read(path).unwrap_or_else(|e| argument_error(e.into())))
+}
+
+/// The input paths given, or else those of the `--samples` sheet
+fn sample_paths(matches: &ArgMatches, samples: Option<&SampleSheet>) -> Vec<PathBuf> {
+    match matches.get_many::<PathBuf>("paths") {
+        Some(paths) => paths.cloned().collect(),
+        None => samples.expect("required unless paths are").paths(),
+    }
+}
+
+/// Reports an error met while running, and exits
+fn application_error(e: impl Display) -> ! {
+    eprintln!(
+        "{}\n {}",
+        "Application error:".blue().bold(),
+        e.to_string().blue()
+    );
+    process::exit(1);
+}
+
+/// Reports a bad argument, with where to find help, and exits
+fn argument_error(e: Box<dyn Error>) -> ! {
+    println!();
+    println!(
+        "{}\n {}",
+        "Problem parsing arguments:".blue().bold(),
+        e.to_string().blue()
+    );
+    println!();
+    println!(
+        "{}\n {}\n  {}\n   {}",
+        "Help menu:".blue().bold(),
+        "$ cargo run -- --help".bold(),
+        "or".underline(),
+        "$ krust --help".bold()
+    );
+    println!();
+    process::exit(1);
+}
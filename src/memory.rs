@@ -0,0 +1,187 @@
+//! Memory use for `--report`.
+//!
+//! Peak RSS comes from the OS. Heap figures need the `alloc-stats` feature,
+//! which installs [`CountingAllocator`] as the global allocator; without it
+//! they're `None`.
+
+use std::fmt::{self, Display};
+
+#[cfg(feature = "alloc-stats")]
+pub use counting::CountingAllocator;
+
+#[cfg(feature = "alloc-stats")]
+mod counting {
+    use std::{
+        alloc::{GlobalAlloc, Layout, System},
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    pub(super) static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+    pub(super) static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+    /// [`System`], keeping a running total and high-water mark of live heap
+    /// bytes.
+    pub struct CountingAllocator;
+
+    impl CountingAllocator {
+        fn grew(size: usize) {
+            let allocated = ALLOCATED.fetch_add(size, Ordering::Relaxed) + size;
+            PEAK.fetch_max(allocated, Ordering::Relaxed);
+        }
+
+        fn shrank(size: usize) {
+            ALLOCATED.fetch_sub(size, Ordering::Relaxed);
+        }
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let ptr = System.alloc(layout);
+            if !ptr.is_null() {
+                Self::grew(layout.size())
+            }
+            ptr
+        }
+
+        unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+            let ptr = System.alloc_zeroed(layout);
+            if !ptr.is_null() {
+                Self::grew(layout.size())
+            }
+            ptr
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout);
+            Self::shrank(layout.size())
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            let new_ptr = System.realloc(ptr, layout, new_size);
+            if !new_ptr.is_null() {
+                Self::shrank(layout.size());
+                Self::grew(new_size)
+            }
+            new_ptr
+        }
+    }
+
+    #[global_allocator]
+    static GLOBAL: CountingAllocator = CountingAllocator;
+}
+
+/// Live heap bytes, with `alloc-stats`
+pub fn allocated() -> Option<usize> {
+    #[cfg(feature = "alloc-stats")]
+    return Some(counting::ALLOCATED.load(std::sync::atomic::Ordering::Relaxed));
+    #[cfg(not(feature = "alloc-stats"))]
+    None
+}
+
+/// Resets the heap high-water mark to the bytes live now, with `alloc-stats`
+pub fn reset_peak() {
+    #[cfg(feature = "alloc-stats")]
+    counting::PEAK.store(
+        counting::ALLOCATED.load(std::sync::atomic::Ordering::Relaxed),
+        std::sync::atomic::Ordering::Relaxed,
+    );
+}
+
+/// Most heap bytes live at once since the last [`reset_peak`], with
+/// `alloc-stats`
+pub fn peak_allocated() -> Option<usize> {
+    #[cfg(feature = "alloc-stats")]
+    return Some(counting::PEAK.load(std::sync::atomic::Ordering::Relaxed));
+    #[cfg(not(feature = "alloc-stats"))]
+    None
+}
+
+/// The process's peak resident set size in bytes, where the OS reports it
+///
+/// # Notes
+/// Read from `VmHWM` in `/proc/self/status`, so Linux only.
+pub fn peak_rss() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kb = line.split_whitespace().nth(1)?.parse::<u64>().ok()?;
+
+    Some(kb * 1024)
+}
+
+/// Run summary written by `--report`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Report {
+    pub distinct_kmers: usize,
+    /// Most heap the count map took at once while counting, rehashes
+    /// included
+    pub peak_map_bytes: Option<usize>,
+    /// Heap the finished count map holds
+    pub map_bytes: Option<usize>,
+    pub peak_rss: Option<u64>,
+}
+
+impl Report {
+    pub fn bytes_per_kmer(&self) -> Option<f64> {
+        let map_bytes = self.map_bytes?;
+        (self.distinct_kmers > 0).then(|| map_bytes as f64 / self.distinct_kmers as f64)
+    }
+}
+
+fn mib(bytes: u64) -> String {
+    format!("{:.1} MiB", bytes as f64 / (1 << 20) as f64)
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "distinct k-mers: {}", self.distinct_kmers)?;
+        match (self.peak_map_bytes, self.map_bytes) {
+            (Some(peak), Some(map_bytes)) => {
+                writeln!(f, "peak map memory: {}", mib(peak as u64))?;
+                write!(f, "map memory: {}", mib(map_bytes as u64))?;
+                if let Some(bytes_per_kmer) = self.bytes_per_kmer() {
+                    write!(f, " ({bytes_per_kmer:.1} bytes per distinct k-mer)")?;
+                }
+                writeln!(f)?;
+            }
+            _ => writeln!(f, "map memory: unknown, build with --features alloc-stats")?,
+        }
+        match self.peak_rss {
+            Some(peak_rss) => write!(f, "peak RSS: {}", mib(peak_rss)),
+            None => write!(f, "peak RSS: unknown"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn report_shows_bytes_per_kmer() {
+        let report = Report {
+            distinct_kmers: 1 << 20,
+            peak_map_bytes: Some(96 << 20),
+            map_bytes: Some(48 << 20),
+            peak_rss: Some(128 << 20),
+        };
+        insta::assert_snapshot!(report.to_string(), @r###"
+        distinct k-mers: 1048576
+        peak map memory: 96.0 MiB
+        map memory: 48.0 MiB (48.0 bytes per distinct k-mer)
+        peak RSS: 128.0 MiB
+        "###);
+    }
+
+    #[test]
+    fn report_without_alloc_stats() {
+        let report = Report {
+            distinct_kmers: 3,
+            ..Default::default()
+        };
+        insta::assert_snapshot!(report.to_string(), @r###"
+        distinct k-mers: 3
+        map memory: unknown, build with --features alloc-stats
+        peak RSS: unknown
+        "###);
+    }
+}
@@ -0,0 +1,148 @@
+//! `--minimizer-window`: `(k, w)` minimizers, the canonical k-mer of least
+//! hash among each `w` consecutive k-mers of a sequence, a sample of its
+//! k-mers that overlapping sequences share.
+
+use std::collections::VecDeque;
+
+use super::{
+    hasher,
+    kmer::{self, Packed, StrandedKmers},
+};
+
+/// A k-mer that may yet be a window's minimizer.
+#[derive(Clone, Copy)]
+struct Candidate<P> {
+    order: u64,
+    start: usize,
+    packed_bits: P,
+    reversed: bool,
+}
+
+/// The minimizers of a sequence, each once however many windows pick it
+///
+/// # Notes
+/// K-mers are ranked by a hash of their canonical form rather than by the
+/// form itself, which would favour runs of `A`. Ties go to the leftmost.
+/// Windows only span consecutive k-mers, so none crosses an invalid byte.
+pub struct MinimizerIterator<'a, P: Packed> {
+    kmers: StrandedKmers<'a, P>,
+    w: usize,
+    /// Candidates of the current window, their orders increasing from front
+    /// to back
+    candidates: VecDeque<Candidate<P>>,
+    /// Consecutive k-mers read since the last invalid byte
+    run: usize,
+    /// Start of the k-mer read last
+    previous: Option<usize>,
+    /// Start of the minimizer yielded last
+    yielded: Option<usize>,
+}
+
+impl<'a, P: Packed> MinimizerIterator<'a, P> {
+    /// Minimizers of each `w` consecutive `k`-mers of `seq`
+    pub fn new(seq: &'a [u8], k: usize, w: usize) -> Self {
+        Self {
+            kmers: kmer::stranded_kmers(seq, k),
+            w,
+            candidates: VecDeque::with_capacity(w),
+            run: 0,
+            previous: None,
+            yielded: None,
+        }
+    }
+}
+
+impl<P: Packed> Iterator for MinimizerIterator<'_, P> {
+    /// Start position, packed canonical k-mer and whether it was found as
+    /// its reverse complement
+    type Item = (usize, P, bool);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for (start, forward, reverse) in self.kmers.by_ref() {
+            if self.previous.is_none_or(|previous| previous + 1 != start) {
+                self.candidates.clear();
+                self.run = 0;
+            }
+            self.previous = Some(start);
+            self.run += 1;
+
+            let (packed_bits, reversed) = match reverse < forward {
+                true => (reverse, true),
+                false => (forward, false),
+            };
+            let order = hasher::remix(fxhash::hash64(&packed_bits));
+            while self
+                .candidates
+                .back()
+                .is_some_and(|last| last.order > order)
+            {
+                self.candidates.pop_back();
+            }
+            self.candidates.push_back(Candidate {
+                order,
+                start,
+                packed_bits,
+                reversed,
+            });
+            while self.candidates[0].start + self.w <= start {
+                self.candidates.pop_front();
+            }
+
+            let minimizer = self.candidates[0];
+            if self.run >= self.w && self.yielded != Some(minimizer.start) {
+                self.yielded = Some(minimizer.start);
+                return Some((minimizer.start, minimizer.packed_bits, minimizer.reversed));
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Minimizers found by ranking every window's k-mers afresh
+    fn naive(seq: &[u8], k: usize, w: usize) -> Vec<(usize, u64, bool)> {
+        let kmers = kmer::stranded_kmers::<u64>(seq, k)
+            .map(|(start, forward, reverse)| (start, forward.min(reverse), reverse < forward))
+            .collect::<Vec<_>>();
+        let mut minimizers = kmers
+            .windows(w)
+            .filter(|window| window[w - 1].0 - window[0].0 == w - 1)
+            .map(|window| {
+                *window
+                    .iter()
+                    .min_by_key(|(start, packed_bits, _)| {
+                        (hasher::remix(fxhash::hash64(packed_bits)), *start)
+                    })
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        minimizers.dedup();
+        minimizers
+    }
+
+    #[test]
+    fn matches_ranking_each_window() {
+        let seq =
+            b"ACGTTGCAACGGTACCATGGNACGTACGGTAACCATGCATTGACCAGTTCAGAGCTAGGCTTACCnnACGTTGCAACGGTAC";
+        for (k, w) in [(3, 1), (5, 4), (7, 10), (11, 30), (31, 3)] {
+            assert_eq!(
+                MinimizerIterator::<u64>::new(seq, k, w).collect::<Vec<_>>(),
+                naive(seq, k, w),
+                "k = {k}, w = {w}"
+            );
+        }
+    }
+
+    #[test]
+    fn a_window_of_one_keeps_every_kmer() {
+        let seq = bytes::Bytes::from_static(b"ACGTNACGGT");
+        let kmers = kmer::canonical_kmers::<u64>(&seq, 3);
+        assert!(MinimizerIterator::<u64>::new(&seq, 3, 1)
+            .map(|(_, packed_bits, _)| packed_bits)
+            .eq(kmers));
+    }
+}
@@ -0,0 +1,679 @@
+use std::{
+    fmt::{self, Display},
+    io::{self, Write},
+    path::Path,
+    str::FromStr,
+    time::Duration,
+};
+
+#[cfg(feature = "compression")]
+use flate2::{write::GzEncoder, Compression as GzLevel};
+#[cfg(feature = "json")]
+use serde::Serialize;
+
+use super::{
+    config::Config,
+    kmer::{self, Packed},
+    run::StrandCounts,
+};
+
+/// How counted k-mers are written.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub enum OutputFormat {
+    /// `>{count}` and `{k-mer}` on alternate lines, like `jellyfish dump`
+    #[default]
+    Fasta,
+    /// `{k-mer}\t{count}` per line, without a header row
+    Tsv,
+    /// A `kmer,count` header row followed by one k-mer per line
+    Csv,
+    /// A [`RAW_MAGIC`] header then little-endian (packed k-mer, `u64` count)
+    /// pairs, the k-mer a `u64` up to k = 32 and a `u128` beyond
+    Raw,
+    /// An object describing the run, with the records under `"counts"`
+    Json,
+    /// No k-mers, only how many distinct k-mers have each count, as
+    /// `jellyfish histo` writes it, kept up to date while counting
+    Histogram,
+}
+
+/// Starts [`OutputFormat::Raw`] output, followed by a version byte, `k` and two
+/// reserved zero bytes.
+pub const RAW_MAGIC: &[u8; 4] = b"KRAW";
+
+pub const RAW_VERSION: u8 = 1;
+
+impl OutputFormat {
+    pub const VARIANTS: [&'static str; 6] = ["fasta", "tsv", "csv", "raw", "json", "histogram"];
+
+    fn default_delimiter(&self) -> char {
+        match self {
+            Self::Csv => ',',
+            _ => '\t',
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fasta" => Ok(Self::Fasta),
+            "tsv" => Ok(Self::Tsv),
+            "csv" => Ok(Self::Csv),
+            "raw" => Ok(Self::Raw),
+            "json" => Ok(Self::Json),
+            "histogram" => Ok(Self::Histogram),
+            _ => Err(format!("Unknown output format \"{s}\"")),
+        }
+    }
+}
+
+impl Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Fasta => "fasta",
+            Self::Tsv => "tsv",
+            Self::Csv => "csv",
+            Self::Raw => "raw",
+            Self::Json => "json",
+            Self::Histogram => "histogram",
+        })
+    }
+}
+
+/// How output is compressed, whether it goes to a file or `stdout`.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub const VARIANTS: [&'static str; 3] = ["none", "gzip", "zstd"];
+
+    /// Infers compression from a `.gz` or `.zst` extension
+    pub fn from_extension<P: AsRef<Path>>(path: P) -> Self {
+        match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+            Some("gz") => Self::Gzip,
+            Some("zst") => Self::Zstd,
+            _ => Self::None,
+        }
+    }
+
+    pub(crate) fn encoder<W: Write>(&self, inner: W) -> io::Result<Encoder<W>> {
+        Ok(match self {
+            Self::None => Encoder::Plain(inner),
+            #[cfg(feature = "compression")]
+            Self::Gzip => Encoder::Gzip(GzEncoder::new(inner, GzLevel::default())),
+            #[cfg(feature = "compression")]
+            Self::Zstd => Encoder::Zstd(zstd::Encoder::new(inner, 0)?),
+            #[cfg(not(feature = "compression"))]
+            Self::Gzip | Self::Zstd => return Err(unsupported("compressed output", "compression")),
+        })
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "gzip" => Ok(Self::Gzip),
+            "zstd" => Ok(Self::Zstd),
+            _ => Err(format!("Unknown compression \"{s}\"")),
+        }
+    }
+}
+
+/// A [`Write`] that compresses according to [`Compression`].
+///
+/// # Notes
+/// Call [`Encoder::finish`] to write the compressed stream's trailer rather
+/// than relying on drop, which swallows errors.
+pub(crate) enum Encoder<W: Write> {
+    Plain(W),
+    #[cfg(feature = "compression")]
+    Gzip(GzEncoder<W>),
+    #[cfg(feature = "compression")]
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+impl<W: Write> Encoder<W> {
+    pub(crate) fn finish(self) -> io::Result<W> {
+        let mut inner = match self {
+            Self::Plain(inner) => inner,
+            #[cfg(feature = "compression")]
+            Self::Gzip(encoder) => encoder.finish()?,
+            #[cfg(feature = "compression")]
+            Self::Zstd(encoder) => encoder.finish()?,
+        };
+        inner.flush()?;
+
+        Ok(inner)
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(inner) => inner.write(buf),
+            #[cfg(feature = "compression")]
+            Self::Gzip(encoder) => encoder.write(buf),
+            #[cfg(feature = "compression")]
+            Self::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Plain(inner) => inner.flush(),
+            #[cfg(feature = "compression")]
+            Self::Gzip(encoder) => encoder.flush(),
+            #[cfg(feature = "compression")]
+            Self::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Why output a build of `krust` left out can't be written: `what` needs
+/// the cargo `feature`
+#[cfg(not(all(feature = "compression", feature = "json", feature = "spill")))]
+pub(crate) fn unsupported(what: &str, feature: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{what} needs krust built with the `{feature}` feature"),
+    )
+}
+
+/// Writes k-mer records in the configured [`OutputFormat`].
+// What only the JSON envelope says goes unread in builds without `json`
+#[cfg_attr(not(feature = "json"), allow(dead_code))]
+pub(crate) struct RecordWriter<W> {
+    inner: W,
+    format: OutputFormat,
+    delimiter: char,
+    strand: bool,
+    with_packed: bool,
+    /// Write k-mers alone, without counts
+    distinct: bool,
+    k: usize,
+    /// Reused across records so unpacking allocates once
+    kmer: String,
+    json_flat: bool,
+    fasta_header: bool,
+    /// Distinct k-mers counted, before any output filter, if known
+    distinct_kmers: Option<usize>,
+    inputs: Vec<String>,
+    report_palindromes: bool,
+    lower_count: Option<u64>,
+    upper_count: Option<u64>,
+    stride: usize,
+    minimizer_window: Option<usize>,
+    canonical: bool,
+    written: usize,
+}
+
+impl<W: Write> RecordWriter<W> {
+    pub(crate) fn new(inner: W, config: &Config) -> Self {
+        Self {
+            inner,
+            format: config.format,
+            delimiter: config
+                .delimiter
+                .unwrap_or_else(|| config.format.default_delimiter()),
+            strand: config.strand,
+            with_packed: config.with_packed,
+            distinct: config.distinct,
+            k: config.k,
+            kmer: String::with_capacity(config.k),
+            json_flat: config.json_flat,
+            fasta_header: config.fasta_header,
+            distinct_kmers: None,
+            inputs: config
+                .paths
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect(),
+            report_palindromes: config.report_palindromes,
+            lower_count: config.lower_count,
+            upper_count: config.upper_count,
+            stride: config.stride.unwrap_or(1),
+            minimizer_window: config.minimizer_window,
+            canonical: !config.no_canonical,
+            written: 0,
+        }
+    }
+
+    /// Reports how many distinct k-mers were counted, for headers that say
+    pub(crate) fn with_distinct_kmers(self, distinct_kmers: usize) -> Self {
+        Self {
+            distinct_kmers: Some(distinct_kmers),
+            ..self
+        }
+    }
+
+    /// Writes the header row, if the format has one, given how long counting
+    /// took unless that is being left out for reproducibility
+    #[cfg_attr(not(feature = "json"), allow(unused_variables))]
+    pub(crate) fn header(&mut self, elapsed: Option<Duration>) -> io::Result<()> {
+        if self.distinct {
+            return Ok(());
+        }
+
+        match self.format {
+            OutputFormat::Csv => (),
+            OutputFormat::Fasta if self.fasta_header => {
+                write!(self.inner, ";k={}", self.k)?;
+                if let Some(distinct_kmers) = self.distinct_kmers {
+                    write!(self.inner, ";distinct={distinct_kmers}")?;
+                }
+                return writeln!(
+                    self.inner,
+                    ";canonical={};version={}",
+                    self.canonical,
+                    env!("CARGO_PKG_VERSION")
+                );
+            }
+            OutputFormat::Raw => {
+                self.inner.write_all(RAW_MAGIC)?;
+                return self.inner.write_all(&[RAW_VERSION, self.k as u8, 0, 0]);
+            }
+            #[cfg(not(feature = "json"))]
+            OutputFormat::Json => return Err(unsupported("JSON output", "json")),
+            #[cfg(feature = "json")]
+            OutputFormat::Json if self.json_flat => return write!(self.inner, "["),
+            #[cfg(feature = "json")]
+            OutputFormat::Json => {
+                let envelope = Envelope {
+                    k: self.k,
+                    input: match &self.inputs[..] {
+                        [input] => Inputs::One(input),
+                        inputs => Inputs::Many(inputs),
+                    },
+                    version: env!("CARGO_PKG_VERSION"),
+                    canonical: self.canonical,
+                    canonical_rule: self.canonical.then_some("lexicographic"),
+                    stride: self.stride,
+                    minimizer_window: self.minimizer_window,
+                    filters: Filters {
+                        report_palindromes: self.report_palindromes,
+                        lower_count: self.lower_count,
+                        upper_count: self.upper_count,
+                    },
+                    timing: elapsed.map(|elapsed| Timing {
+                        counting_seconds: elapsed.as_secs_f64(),
+                    }),
+                };
+                // Records are streamed into `"counts"` after the envelope, so
+                // it's left open where it would close
+                let mut envelope = serde_json::to_vec(&envelope)?;
+                envelope.pop();
+                self.inner.write_all(&envelope)?;
+                return write!(self.inner, ",\"counts\":[");
+            }
+            _ => return Ok(()),
+        }
+
+        let d = self.delimiter;
+
+        write!(self.inner, "kmer{d}count")?;
+        if self.strand {
+            write!(self.inner, "{d}forward{d}reverse")?;
+        }
+        if self.with_packed {
+            write!(self.inner, "{d}packed")?;
+        }
+        writeln!(self.inner)
+    }
+
+    pub(crate) fn record<P: Packed>(
+        &mut self,
+        packed_bits: P,
+        count: i32,
+        strand: Option<StrandCounts>,
+    ) -> io::Result<()> {
+        if self.format == OutputFormat::Raw {
+            packed_bits.write_le(&mut self.inner)?;
+            return self.inner.write_all(&(count as u64).to_le_bytes());
+        }
+
+        kmer::unpack_into(packed_bits, self.k, &mut self.kmer);
+        let kmer = &self.kmer;
+        let d = self.delimiter;
+
+        if self.distinct {
+            return writeln!(self.inner, "{kmer}");
+        }
+
+        #[cfg(feature = "json")]
+        if self.format == OutputFormat::Json {
+            if self.written > 0 {
+                write!(self.inner, ",")?;
+            }
+            self.written += 1;
+
+            let record = JsonRecord {
+                kmer,
+                count,
+                forward: strand.map(|strand| strand.forward),
+                reverse: strand.map(|strand| strand.reverse),
+                // As a string, since JSON readers parsing numbers as doubles
+                // would round any packed k-mer past 2^53, from k = 27
+                packed: self.with_packed.then(|| packed_bits.to_string()),
+            };
+            return Ok(serde_json::to_writer(&mut self.inner, &record)?);
+        }
+
+        match self.format {
+            OutputFormat::Fasta => write!(self.inner, ">{count}")?,
+            _ => write!(self.inner, "{kmer}{d}{count}")?,
+        }
+        if let Some(StrandCounts { forward, reverse }) = strand {
+            write!(self.inner, "{d}{forward}{d}{reverse}")?;
+        }
+        match self.format {
+            OutputFormat::Fasta => writeln!(self.inner, "\n{kmer}"),
+            _ if self.with_packed => writeln!(self.inner, "{d}{packed_bits}"),
+            _ => writeln!(self.inner),
+        }
+    }
+
+    /// Closes anything [`RecordWriter::header`] opened
+    pub(crate) fn footer(&mut self) -> io::Result<()> {
+        if self.distinct {
+            return Ok(());
+        }
+
+        match self.format {
+            OutputFormat::Json if self.json_flat => writeln!(self.inner, "]"),
+            OutputFormat::Json => writeln!(self.inner, "]}}"),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+/// What JSON output says about the run, ahead of its `"counts"`
+#[cfg(feature = "json")]
+#[derive(Serialize)]
+struct Envelope<'a> {
+    k: usize,
+    input: Inputs<'a>,
+    version: &'static str,
+    canonical: bool,
+    canonical_rule: Option<&'static str>,
+    stride: usize,
+    minimizer_window: Option<usize>,
+    filters: Filters,
+    timing: Option<Timing>,
+}
+
+/// One input's path as a string, or several as an array
+#[cfg(feature = "json")]
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Inputs<'a> {
+    One(&'a str),
+    Many(&'a [String]),
+}
+
+#[cfg(feature = "json")]
+#[derive(Serialize)]
+struct Filters {
+    report_palindromes: bool,
+    lower_count: Option<u64>,
+    upper_count: Option<u64>,
+}
+
+#[cfg(feature = "json")]
+#[derive(Serialize)]
+struct Timing {
+    counting_seconds: f64,
+}
+
+/// One k-mer of JSON output
+#[cfg(feature = "json")]
+#[derive(Serialize)]
+struct JsonRecord<'a> {
+    kmer: &'a str,
+    count: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    forward: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reverse: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    packed: Option<String>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn written_bytes(config: &Config, records: &[(&str, i32, Option<StrandCounts>)]) -> Vec<u8> {
+        let mut writer = RecordWriter::new(Vec::new(), config);
+        let elapsed = (!config.deterministic).then(|| Duration::from_millis(1500));
+        writer.header(elapsed).unwrap();
+        for (kmer, count, strand) in records {
+            let packed_bits = kmer::pack_many::<u64>(&[kmer], config.k)[0].unwrap();
+            writer.record(packed_bits, *count, *strand).unwrap();
+        }
+        writer.footer().unwrap();
+        writer.inner
+    }
+
+    fn written(config: &Config, records: &[(&str, i32, Option<StrandCounts>)]) -> String {
+        String::from_utf8(written_bytes(config, records)).unwrap()
+    }
+
+    #[test]
+    fn csv_has_header_and_custom_delimiter() {
+        let config = Config {
+            k: 3,
+            format: OutputFormat::Csv,
+            ..Default::default()
+        };
+        insta::assert_snapshot!(written(&config, &[("ACG", 4, None)]), @r###"
+        kmer,count
+        ACG,4
+        "###);
+
+        let config = Config {
+            k: 3,
+            format: OutputFormat::Csv,
+            delimiter: Some(';'),
+            strand: true,
+            ..Default::default()
+        };
+        let strand = Some(StrandCounts {
+            forward: 3,
+            reverse: 1,
+        });
+        insta::assert_snapshot!(written(&config, &[("ACG", 4, strand)]), @r###"
+        kmer;count;forward;reverse
+        ACG;4;3;1
+        "###);
+    }
+
+    #[test]
+    fn fasta_and_tsv_have_no_header() {
+        let config = Config {
+            k: 3,
+            ..Default::default()
+        };
+        insta::assert_snapshot!(written(&config, &[("ACG", 4, None)]), @r###"
+        >4
+        ACG
+        "###);
+
+        let config = Config {
+            k: 3,
+            format: OutputFormat::Tsv,
+            ..Default::default()
+        };
+        assert_eq!(written(&config, &[("ACG", 4, None)]), "ACG\t4\n");
+    }
+
+    #[test]
+    fn fasta_header_describes_the_run() {
+        let config = Config {
+            k: 3,
+            fasta_header: true,
+            ..Default::default()
+        };
+        let mut writer = RecordWriter::new(Vec::new(), &config).with_distinct_kmers(7);
+        writer.header(None).unwrap();
+        writer.record(6u64, 4, None).unwrap();
+        insta::assert_snapshot!(String::from_utf8(writer.inner).unwrap(), @r###"
+        ;k=3;distinct=7;canonical=true;version=0.1.0
+        >4
+        ACG
+        "###);
+    }
+
+    #[test]
+    fn distinct_writes_bare_kmers() {
+        let config = Config {
+            k: 3,
+            distinct: true,
+            ..Default::default()
+        };
+        insta::assert_snapshot!(written(&config, &[("ACG", 0, None), ("AAA", 0, None)]), @r###"
+        ACG
+        AAA
+        "###);
+    }
+
+    #[test]
+    fn with_packed_appends_packed_column_to_tabular_formats() {
+        let config = Config {
+            k: 3,
+            format: OutputFormat::Csv,
+            with_packed: true,
+            ..Default::default()
+        };
+        insta::assert_snapshot!(written(&config, &[("ACG", 4, None)]), @r###"
+        kmer,count,packed
+        ACG,4,6
+        "###);
+
+        let config = Config {
+            k: 3,
+            with_packed: true,
+            ..Default::default()
+        };
+        assert_eq!(written(&config, &[("ACG", 4, None)]), ">4\nACG\n");
+    }
+
+    #[test]
+    fn raw_writes_header_and_little_endian_pairs() {
+        let config = Config {
+            k: 3,
+            format: OutputFormat::Raw,
+            ..Default::default()
+        };
+        let bytes = written_bytes(&config, &[("ACG", 4, None)]);
+
+        assert_eq!(&bytes[..8], b"KRAW\x01\x03\x00\x00");
+        assert_eq!(bytes[8..16], 6u64.to_le_bytes());
+        assert_eq!(bytes[16..], 4u64.to_le_bytes());
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn compression_round_trips() {
+        use std::io::Read;
+
+        let config = Config {
+            k: 3,
+            ..Default::default()
+        };
+        let plain = written(&config, &[("ACG", 4, None)]);
+
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            let mut writer = RecordWriter::new(compression.encoder(Vec::new()).unwrap(), &config);
+            writer.record(6u64, 4, None).unwrap();
+            let compressed = writer.into_inner().finish().unwrap();
+
+            let mut decompressed = String::new();
+            match compression {
+                Compression::Gzip => flate2::read::GzDecoder::new(&compressed[..])
+                    .read_to_string(&mut decompressed)
+                    .unwrap(),
+                _ => zstd::Decoder::new(&compressed[..])
+                    .unwrap()
+                    .read_to_string(&mut decompressed)
+                    .unwrap(),
+            };
+            assert_eq!(decompressed, plain);
+        }
+
+        assert_eq!(Compression::from_extension("out.tsv.gz"), Compression::Gzip);
+        assert_eq!(Compression::from_extension("out.tsv"), Compression::None);
+    }
+
+    #[test]
+    #[cfg(not(feature = "compression"))]
+    fn compressed_output_needs_the_compression_feature() {
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            let e = compression.encoder(Vec::new()).err().unwrap();
+            assert_eq!(e.kind(), io::ErrorKind::Unsupported);
+        }
+        assert!(Compression::None.encoder(Vec::new()).is_ok());
+    }
+
+    #[test]
+    #[cfg(feature = "json")]
+    fn json_wraps_counts_in_run_metadata() {
+        let config = Config {
+            k: 3,
+            paths: vec!["data/\"odd\".fa".into()],
+            format: OutputFormat::Json,
+            with_packed: true,
+            ..Default::default()
+        };
+        insta::assert_snapshot!(written(&config, &[("ACG", 4, None), ("AAA", 1, None)]), @r###"{"k":3,"input":"data/\"odd\".fa","version":"0.1.0","canonical":true,"canonical_rule":"lexicographic","stride":1,"minimizer_window":null,"filters":{"report_palindromes":false,"lower_count":null,"upper_count":null},"timing":{"counting_seconds":1.5},"counts":[{"kmer":"ACG","count":4,"packed":"6"},{"kmer":"AAA","count":1,"packed":"0"}]}"###);
+
+        let config = Config {
+            k: 3,
+            format: OutputFormat::Json,
+            json_flat: true,
+            ..Default::default()
+        };
+        insta::assert_snapshot!(written(&config, &[("ACG", 4, None)]), @r###"[{"kmer":"ACG","count":4}]"###);
+    }
+
+    #[test]
+    #[cfg(feature = "json")]
+    fn json_packed_kmers_are_exact_past_2_to_the_53() {
+        let config = Config {
+            k: 32,
+            format: OutputFormat::Json,
+            json_flat: true,
+            with_packed: true,
+            ..Default::default()
+        };
+        let json = written(&config, &[(&"T".repeat(32), 1, None)]);
+        let records: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(records[0]["packed"], u64::MAX.to_string());
+    }
+
+    #[test]
+    #[cfg(feature = "json")]
+    fn deterministic_json_leaves_out_timing() {
+        let config = Config {
+            k: 3,
+            format: OutputFormat::Json,
+            deterministic: true,
+            ..Default::default()
+        };
+        assert!(written(&config, &[]).contains(r#""timing":null,"#));
+    }
+}
@@ -0,0 +1,151 @@
+//! `--paired`: inputs given as pairs of mate files, R1 then R2, whose reads
+//! are checked to pair up, name for name, before both mates are counted.
+
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use super::{
+    kmer::{self, Packed},
+    reader::{self, Sequence},
+    run::{KmerMap, ProcessError},
+};
+
+/// Why two mate files don't pair up.
+#[derive(Debug, Error)]
+pub enum PairError {
+    #[error("--paired takes inputs in pairs, an R1 and an R2 file each, not {0} inputs")]
+    OddInputs(usize),
+
+    #[error("{r1} has {r1_reads} reads but its mate file {r2} has {r2_reads}")]
+    ReadCounts {
+        r1: String,
+        r1_reads: usize,
+        r2: String,
+        r2_reads: usize,
+    },
+
+    /// `read` counts reads from 1
+    #[error("read {read} is {r1_id} in {r1} but {r2_id} in its mate file {r2}")]
+    Names {
+        read: usize,
+        r1: String,
+        r1_id: String,
+        r2: String,
+        r2_id: String,
+    },
+}
+
+/// A read's name without the `/1` or `/2` that older Illumina names end in
+fn mate_name(id: &str) -> &str {
+    id.strip_suffix("/1")
+        .or_else(|| id.strip_suffix("/2"))
+        .unwrap_or(id)
+}
+
+/// Checks that each pair of `inputs`, read from `paths`, holds the same
+/// reads in the same order
+pub(crate) fn check_pairs(paths: &[PathBuf], inputs: &[Vec<Sequence>]) -> Result<(), PairError> {
+    if !inputs.len().is_multiple_of(2) {
+        return Err(PairError::OddInputs(inputs.len()));
+    }
+
+    for (paths, mates) in paths.chunks(2).zip(inputs.chunks(2)) {
+        let [r1, r2] = [0, 1].map(|i| paths[i].display().to_string());
+        if mates[0].len() != mates[1].len() {
+            return Err(PairError::ReadCounts {
+                r1,
+                r1_reads: mates[0].len(),
+                r2,
+                r2_reads: mates[1].len(),
+            });
+        }
+
+        let unpaired = mates[0]
+            .iter()
+            .zip(&mates[1])
+            .position(|((r1_id, _), (r2_id, _))| mate_name(r1_id) != mate_name(r2_id));
+        if let Some(i) = unpaired {
+            return Err(PairError::Names {
+                read: i + 1,
+                r1,
+                r1_id: mates[0][i].0.clone(),
+                r2,
+                r2_id: mates[1][i].0.clone(),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Counts canonical k-mers across both mates of paired-end reads in `r1` and
+/// `r2`, once their reads are checked to pair up
+pub fn count_kmers_paired<P: AsRef<Path>>(
+    r1: P,
+    r2: P,
+    k: usize,
+) -> Result<Vec<(String, i32)>, ProcessError> {
+    let paths = [r1.as_ref().to_path_buf(), r2.as_ref().to_path_buf()];
+    let mates = paths
+        .iter()
+        .map(|path| reader::read(path, None, None))
+        .collect::<Result<Vec<_>, _>>()?;
+    check_pairs(&paths, &mates)?;
+
+    Ok(match k <= u64::MAX_K {
+        true => count_mates::<u64>(&mates, k),
+        false => count_mates::<u128>(&mates, k),
+    })
+}
+
+fn count_mates<P: Packed>(mates: &[Vec<Sequence>], k: usize) -> Vec<(String, i32)> {
+    let map = KmerMap::<P>::new(false);
+    for reads in mates {
+        map.count(reads, k, None)
+    }
+
+    map.counts
+        .into_iter()
+        .map(|(packed_bits, count)| (kmer::unpack_to_string(packed_bits, k), count))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fastq(reads: &[(&str, &str)]) -> tempfile::NamedTempFile {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let records = reads
+            .iter()
+            .map(|(id, seq)| format!("@{id}\n{seq}\n+\n{}\n", "I".repeat(seq.len())))
+            .collect::<String>();
+        std::fs::write(file.path(), records).unwrap();
+        file
+    }
+
+    #[test]
+    fn counts_both_mates_once_they_pair() {
+        let r1 = fastq(&[("a/1", "ACGA"), ("b 1:N:0", "AAAA")]);
+        let r2 = fastq(&[("a/2", "TCGT"), ("b 2:N:0", "CCCC")]);
+        let mut counts = count_kmers_paired(r1.path(), r2.path(), 3).unwrap();
+        counts.sort();
+        assert_eq!(
+            counts,
+            [("AAA", 2), ("ACG", 2), ("CCC", 2), ("CGA", 2)]
+                .map(|(kmer, count)| (kmer.into(), count))
+        );
+
+        let swapped = fastq(&[("b", "CCCC"), ("a", "TCGT")]);
+        let e = count_kmers_paired(r1.path(), swapped.path(), 3).unwrap_err();
+        assert!(e.to_string().contains("read 1 is a/1 in"), "{e}");
+
+        let short = fastq(&[("a", "TCGT")]);
+        let e = count_kmers_paired(r1.path(), short.path(), 3).unwrap_err();
+        assert!(
+            e.to_string().contains("has 2 reads but its mate file"),
+            "{e}"
+        );
+    }
+}
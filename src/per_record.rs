@@ -0,0 +1,114 @@
+//! `--per-record`: k-mer counts for each input record on its own, to compare
+//! the spectra of contigs or chromosomes in one pass.
+
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    path::Path,
+};
+
+use bytes::Bytes;
+use fxhash::FxHashMap;
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+
+use super::{
+    kmer::{self, Packed},
+    reader::{self, ReadError, Sequence},
+};
+
+/// Canonical k-mer counts, by record ID.
+pub type RecordCounts = HashMap<String, HashMap<String, u64>>;
+
+/// Counts canonical k-mers in each record of a FASTA or FASTQ file
+/// separately, keyed by record ID
+///
+/// # Notes
+/// Records sharing an ID are counted together.
+pub fn count_kmers_per_record<P: AsRef<Path> + std::fmt::Debug>(
+    path: P,
+    k: usize,
+) -> Result<RecordCounts, ReadError> {
+    let mut counts = RecordCounts::new();
+    for (id, record) in per_record(reader::read(path, None, None)?, k) {
+        let merged = counts.entry(id).or_default();
+        for (kmer, count) in record {
+            *merged.entry(kmer).or_insert(0) += count
+        }
+    }
+
+    Ok(counts)
+}
+
+/// Counts each record in parallel, keeping them in input order, with each
+/// record's k-mers sorted
+pub(crate) fn per_record(sequences: Vec<Sequence>, k: usize) -> Vec<(String, Vec<(String, u64)>)> {
+    sequences
+        .into_par_iter()
+        .map(|(id, pieces)| {
+            let seq = reader::joined(&pieces);
+            let mut counts = match k <= u64::MAX_K {
+                true => tally::<u64>(&seq, k),
+                false => tally::<u128>(&seq, k),
+            };
+            counts.sort_unstable();
+            (id, counts)
+        })
+        .collect()
+}
+
+/// Canonical k-mers in `seq` with their counts
+fn tally<P: Packed>(seq: &Bytes, k: usize) -> Vec<(String, u64)> {
+    let mut counts = FxHashMap::<P, u64>::default();
+    for packed_bits in kmer::canonical_kmers::<P>(seq, k) {
+        *counts.entry(packed_bits).or_insert(0) += 1
+    }
+
+    counts
+        .into_iter()
+        .map(|(packed_bits, count)| (kmer::unpack_to_string(packed_bits, k), count))
+        .collect()
+}
+
+/// Writes a header line, then one `record`, `kmer`, `count` line per k-mer of
+/// each record
+pub(crate) fn write<W: Write>(
+    out: &mut W,
+    records: &[(String, Vec<(String, u64)>)],
+    delimiter: char,
+) -> io::Result<()> {
+    let d = delimiter;
+    writeln!(out, "record{d}kmer{d}count")?;
+    for (id, counts) in records {
+        for (kmer, count) in counts {
+            writeln!(out, "{id}{d}{kmer}{d}{count}")?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn counts_each_record_apart() {
+        let fasta = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(fasta.path(), ">a\nACGT\n>b\nAAAA\n>a\nACG\n").unwrap();
+
+        let counts = count_kmers_per_record(fasta.path(), 3).unwrap();
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts["a"], HashMap::from([("ACG".to_string(), 3)]));
+        assert_eq!(counts["b"], HashMap::from([("AAA".to_string(), 2)]));
+
+        let records = per_record(reader::read(fasta.path(), None, None).unwrap(), 3);
+        let mut out = Vec::new();
+        write(&mut out, &records, '\t').unwrap();
+        insta::assert_snapshot!(String::from_utf8(out).unwrap(), @r###"
+        record	kmer	count
+        a	ACG	2
+        b	AAA	2
+        a	ACG	1
+        "###);
+    }
+}
@@ -0,0 +1,269 @@
+//! `krust preview`: a quick look at a FASTA/FASTQ file before counting it.
+
+use std::{
+    error::Error,
+    fmt::{self, Display},
+    path::Path,
+};
+
+use bytes::Bytes;
+use fxhash::FxHashSet;
+
+use super::kmer;
+
+/// `xorshift64*`, so samples come out the same from run to run.
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, n: u64) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) % n
+    }
+}
+
+/// A read kept for the preview: its uppercased sequence and, from FASTQ, its
+/// qualities.
+pub(crate) struct Read {
+    pub seq: Bytes,
+    pub qual: Option<Vec<u8>>,
+}
+
+/// Phred quality summary of the previewed bases.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QualitySummary {
+    pub mean: f64,
+    /// Fraction of bases at Q30 or better
+    pub q30: f64,
+}
+
+/// Statistics over the first reads of an input plus a reservoir sample of
+/// the rest.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Preview {
+    pub k: usize,
+    /// Reads read from the start of the input
+    pub head: usize,
+    /// Reads sampled from the rest
+    pub sampled: usize,
+    /// Reads in the whole input
+    pub total: u64,
+    /// Previewed read lengths, sorted
+    pub lengths: Vec<usize>,
+    /// `G` and `C` as a fraction of `ACGT` bases
+    pub gc: f64,
+    /// `None` for FASTA input
+    pub quality: Option<QualitySummary>,
+    /// Distinct canonical k-mers in the previewed reads, a lower bound for
+    /// the whole input
+    pub distinct_kmers: usize,
+}
+
+/// Reads `head_mb` megabytes of sequence from the start of `path`, then
+/// reservoir samples `sample_len` reads from the rest
+///
+/// # Notes
+/// Unlike counting, previewing takes FASTQ and compressed input, since that's
+/// what comes off a sequencer. The whole input is still scanned for the
+/// sample, but nothing is counted beyond the reads kept.
+pub fn preview<P: AsRef<Path>>(
+    path: P,
+    k: usize,
+    head_mb: usize,
+    sample_len: usize,
+) -> Result<Preview, Box<dyn Error>> {
+    let sample = Sample::read(path, head_mb, sample_len)?;
+
+    Ok(Preview {
+        head: sample.head,
+        sampled: sample.reads.len() - sample.head,
+        total: sample.total,
+        ..Preview::of(&sample.reads, k)
+    })
+}
+
+/// The first reads of an input followed by a reservoir sample of the rest.
+pub(crate) struct Sample {
+    pub reads: Vec<Read>,
+    /// Reads taken from the start of the input, before the sampled ones
+    pub head: usize,
+    /// Reads in the whole input
+    pub total: u64,
+}
+
+impl Sample {
+    /// Takes reads until `head_mb` megabytes of sequence, then reservoir
+    /// samples `sample_len` of the remaining reads
+    pub(crate) fn read<P: AsRef<Path>>(
+        path: P,
+        head_mb: usize,
+        sample_len: usize,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut reader = needletail::parse_fastx_file(path)?;
+        let mut rng = Rng(0x5eed_0004);
+
+        let mut reads = Vec::new();
+        let mut head_bases = 0;
+        let mut sample = Vec::with_capacity(sample_len);
+        let mut rest = 0;
+
+        while let Some(record) = reader.next() {
+            let record = record?;
+            let read = Read {
+                seq: Bytes::from(record.seq().to_ascii_uppercase()),
+                qual: record.qual().map(<[u8]>::to_vec),
+            };
+
+            if head_bases < head_mb << 20 {
+                head_bases += read.seq.len();
+                reads.push(read);
+                continue;
+            }
+
+            rest += 1;
+            if sample.len() < sample_len {
+                sample.push(read)
+            } else if let Some(slot) = sample.get_mut(rng.below(rest) as usize) {
+                *slot = read
+            }
+        }
+
+        let head = reads.len();
+        reads.append(&mut sample);
+        Ok(Self {
+            reads,
+            head,
+            total: head as u64 + rest,
+        })
+    }
+}
+
+impl Preview {
+    fn of(reads: &[Read], k: usize) -> Self {
+        let mut lengths = reads.iter().map(|read| read.seq.len()).collect::<Vec<_>>();
+        lengths.sort_unstable();
+
+        let (mut gc, mut acgt) = (0u64, 0u64);
+        for base in reads.iter().flat_map(|read| read.seq.iter()) {
+            match base {
+                b'G' | b'C' => {
+                    gc += 1;
+                    acgt += 1
+                }
+                b'A' | b'T' => acgt += 1,
+                _ => (),
+            }
+        }
+
+        let qualities = reads
+            .iter()
+            .filter_map(|read| read.qual.as_deref())
+            .flatten()
+            .map(|qual| qual.saturating_sub(b'!'));
+        let (mut sum, mut q30, mut bases) = (0u64, 0u64, 0u64);
+        for qual in qualities {
+            sum += qual as u64;
+            q30 += (qual >= 30) as u64;
+            bases += 1;
+        }
+
+        let distinct_kmers = reads
+            .iter()
+            .flat_map(|read| kmer::canonical_kmers::<u64>(&read.seq, k))
+            .collect::<FxHashSet<_>>()
+            .len();
+
+        Self {
+            k,
+            head: reads.len(),
+            sampled: 0,
+            total: reads.len() as u64,
+            lengths,
+            gc: ratio(gc, acgt),
+            quality: (bases > 0).then(|| QualitySummary {
+                mean: ratio(sum, bases),
+                q30: ratio(q30, bases),
+            }),
+            distinct_kmers,
+        }
+    }
+}
+
+fn ratio(numerator: u64, denominator: u64) -> f64 {
+    match denominator {
+        0 => 0.0,
+        _ => numerator as f64 / denominator as f64,
+    }
+}
+
+impl Display for Preview {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "reads: {} previewed of {} ({} from the start, {} sampled)",
+            self.lengths.len(),
+            self.total,
+            self.head,
+            self.sampled
+        )?;
+        match (self.lengths.first(), self.lengths.last()) {
+            (Some(min), Some(max)) => writeln!(
+                f,
+                "read length: min {min}, median {}, mean {:.1}, max {max}",
+                self.lengths[self.lengths.len() / 2],
+                self.lengths.iter().sum::<usize>() as f64 / self.lengths.len() as f64
+            )?,
+            _ => writeln!(f, "read length: no reads")?,
+        }
+        writeln!(f, "GC: {:.1}%", 100.0 * self.gc)?;
+        match self.quality {
+            Some(quality) => writeln!(
+                f,
+                "quality: mean Q{:.1}, {:.1}% of bases Q30 or better",
+                quality.mean,
+                100.0 * quality.q30
+            )?,
+            None => writeln!(f, "quality: none (FASTA)")?,
+        }
+        write!(
+            f,
+            "distinct {}-mers: {} in the previewed reads",
+            self.k, self.distinct_kmers
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn read(seq: &'static [u8], qual: Option<&[u8]>) -> Read {
+        Read {
+            seq: Bytes::from_static(seq),
+            qual: qual.map(<[u8]>::to_vec),
+        }
+    }
+
+    #[test]
+    fn summarizes_lengths_gc_quality_and_kmers() {
+        let reads = [
+            read(b"ACGTNACG", Some(b"IIII!III")),
+            read(b"GGCC", Some(b"5555")),
+        ];
+
+        insta::assert_snapshot!(Preview::of(&reads, 3).to_string(), @r###"
+        reads: 2 previewed of 2 (2 from the start, 0 sampled)
+        read length: min 4, median 8, mean 6.0, max 8
+        GC: 72.7%
+        quality: mean Q30.0, 58.3% of bases Q30 or better
+        distinct 3-mers: 2 in the previewed reads
+        "###);
+    }
+
+    #[test]
+    fn fasta_has_no_quality() {
+        let preview = Preview::of(&[read(b"ACGT", None)], 3);
+        assert_eq!(preview.quality, None);
+    }
+}
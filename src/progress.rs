@@ -0,0 +1,216 @@
+use std::{
+    fmt::{self, Display},
+    io::{stderr, Write},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, RecvTimeoutError},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How often [`ProgressTracker::report`] writes to `stderr`.
+pub const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How [`ProgressTracker::report`] writes progress.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub enum ProgressFormat {
+    /// A single line, redrawn in place, for people watching a terminal
+    #[default]
+    Human,
+    /// One JSON object per line, for workflow engines and dashboards
+    Json,
+}
+
+impl ProgressFormat {
+    pub const VARIANTS: [&'static str; 2] = ["human", "json"];
+}
+
+impl FromStr for ProgressFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(Self::Human),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("Unknown progress format \"{s}\"")),
+        }
+    }
+}
+
+/// A snapshot of counting progress.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Progress {
+    /// Inputs counted in full
+    pub inputs: u64,
+    pub sequences: u64,
+    pub bases: u64,
+    /// Distinct k-mers as of the last report, sampled from the count map
+    pub distinct_kmers_so_far: u64,
+    pub elapsed: Duration,
+}
+
+/// Counts sequences and bases as worker threads finish them.
+///
+/// # Notes
+/// Updates are relaxed atomic adds, cheap enough to make once per sequence
+/// from every `rayon` worker.
+#[derive(Debug)]
+pub struct ProgressTracker {
+    inputs: AtomicU64,
+    sequences: AtomicU64,
+    bases: AtomicU64,
+    distinct_kmers: AtomicU64,
+    start: Instant,
+    format: ProgressFormat,
+}
+
+impl Default for ProgressTracker {
+    fn default() -> Self {
+        Self {
+            inputs: AtomicU64::new(0),
+            sequences: AtomicU64::new(0),
+            bases: AtomicU64::new(0),
+            distinct_kmers: AtomicU64::new(0),
+            start: Instant::now(),
+            format: ProgressFormat::default(),
+        }
+    }
+}
+
+impl ProgressTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_format(format: ProgressFormat) -> Self {
+        Self {
+            format,
+            ..Default::default()
+        }
+    }
+
+    /// Records one finished sequence of `len` bases
+    pub fn record(&self, len: usize) {
+        self.sequences.fetch_add(1, Ordering::Relaxed);
+        self.bases.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    /// Records that every sequence of one input has been counted
+    pub fn finish_input(&self) {
+        self.inputs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn progress(&self) -> Progress {
+        Progress {
+            inputs: self.inputs.load(Ordering::Relaxed),
+            sequences: self.sequences.load(Ordering::Relaxed),
+            bases: self.bases.load(Ordering::Relaxed),
+            distinct_kmers_so_far: self.distinct_kmers.load(Ordering::Relaxed),
+            elapsed: self.start.elapsed(),
+        }
+    }
+
+    /// Runs `f`, writing progress to `stderr` every [`REPORT_INTERVAL`] until
+    /// it returns
+    ///
+    /// # Notes
+    /// `distinct_kmers` is sampled once per report, not per k-mer, so it can
+    /// afford to briefly lock the count map to read its length.
+    pub fn report<T, D, F>(&self, distinct_kmers: D, f: F) -> T
+    where
+        D: Fn() -> usize + Send,
+        F: FnOnce() -> T,
+    {
+        let (done, stopped) = mpsc::channel::<()>();
+
+        thread::scope(|scope| {
+            scope.spawn(move || loop {
+                let finished = !matches!(
+                    stopped.recv_timeout(REPORT_INTERVAL),
+                    Err(RecvTimeoutError::Timeout)
+                );
+                self.distinct_kmers
+                    .store(distinct_kmers() as u64, Ordering::Relaxed);
+                let progress = self.progress();
+                let _ = match self.format {
+                    ProgressFormat::Human => write!(stderr(), "\r{progress}"),
+                    ProgressFormat::Json => writeln!(stderr(), "{}", progress.json()),
+                };
+                if finished {
+                    if self.format == ProgressFormat::Human {
+                        let _ = writeln!(stderr());
+                    }
+                    break;
+                }
+            });
+
+            let result = f();
+            drop(done);
+            result
+        })
+    }
+}
+
+impl Progress {
+    /// A single-line JSON object, e.g.
+    /// `{"inputs":1,"sequences":2,"bases":22,"distinct":6,"elapsed":0.5}`
+    pub fn json(&self) -> String {
+        format!(
+            "{{\"inputs\":{},\"sequences\":{},\"bases\":{},\"distinct\":{},\"elapsed\":{}}}",
+            self.inputs,
+            self.sequences,
+            self.bases,
+            self.distinct_kmers_so_far,
+            self.elapsed.as_secs_f64()
+        )
+    }
+}
+
+impl Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} inputs done, {} sequences, {} bases, {} distinct k-mers, {:.1}s",
+            self.inputs,
+            self.sequences,
+            self.bases,
+            self.distinct_kmers_so_far,
+            self.elapsed.as_secs_f64()
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn tracker_sums_sequences_and_bases() {
+        let tracker = ProgressTracker::new();
+        tracker.report(
+            || 7,
+            || {
+                tracker.record(10);
+                tracker.record(5);
+            },
+        );
+
+        let progress = tracker.progress();
+        assert_eq!((progress.sequences, progress.bases), (2, 15));
+        assert_eq!(progress.distinct_kmers_so_far, 7);
+    }
+
+    #[test]
+    fn json_progress_is_one_object_per_line() {
+        let progress = Progress {
+            inputs: 1,
+            sequences: 2,
+            bases: 22,
+            distinct_kmers_so_far: 6,
+            elapsed: Duration::from_millis(500),
+        };
+        insta::assert_snapshot!(progress.json(), @r###"{"inputs":1,"sequences":2,"bases":22,"distinct":6,"elapsed":0.5}"###);
+    }
+}
@@ -0,0 +1,55 @@
+//! `--trim-quality`: 3'-end quality trimming of FASTQ reads before their
+//! k-mers are counted, so counts reflect the bases an assembler would keep.
+
+/// Added to a Phred score to write it as a FASTQ quality character.
+pub const PHRED_OFFSET: u8 = 33;
+
+/// Length of `quality`'s read once its 3' end is trimmed at Phred score
+/// `threshold`, as `bwa aln -q` trims
+///
+/// # Notes
+/// Walking in from the 3' end, each base adds `threshold` less its score to
+/// a running sum, and the read is cut where that sum peaks, stopping once it
+/// goes negative. A single good base among bad ones is trimmed with them,
+/// while a single bad one among good ones is kept. Unlike `bwa`, reads may
+/// be trimmed to nothing.
+pub fn trim_end(quality: &[u8], threshold: u8) -> usize {
+    let mut sum = 0i64;
+    let mut max = 0;
+    let mut len = quality.len();
+
+    for (i, q) in quality.iter().enumerate().rev() {
+        sum += threshold as i64 - q.saturating_sub(PHRED_OFFSET) as i64;
+        if sum < 0 {
+            break;
+        }
+        if sum > max {
+            max = sum;
+            len = i;
+        }
+    }
+
+    len
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// FASTQ quality characters for Phred scores
+    fn phred(scores: &[u8]) -> Vec<u8> {
+        scores.iter().map(|score| score + PHRED_OFFSET).collect()
+    }
+
+    #[test]
+    fn trims_the_low_quality_tail() {
+        assert_eq!(trim_end(&phred(&[30, 30, 30, 30, 2, 2, 2]), 20), 4);
+        // A good base among bad ones goes with them...
+        assert_eq!(trim_end(&phred(&[30, 30, 30, 2, 25, 2, 2]), 20), 3);
+        // ...and a bad one among good ones stays
+        assert_eq!(trim_end(&phred(&[30, 30, 2, 30, 30, 30, 30]), 20), 7);
+        assert_eq!(trim_end(&phred(&[2, 2, 2]), 20), 0);
+        assert_eq!(trim_end(&phred(&[40, 40]), 20), 2);
+        assert_eq!(trim_end(b"", 20), 0);
+    }
+}
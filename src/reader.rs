@@ -1,26 +1,673 @@
-use std::{error::Error, fmt::Debug, path::Path};
+use std::{
+    collections::HashSet,
+    error::Error,
+    fmt::Debug,
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::Path,
+};
+#[cfg(any(feature = "rust-bio", feature = "needletail"))]
+use std::{
+    io::Read,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
 
 use bytes::Bytes;
-use rayon::{prelude::IntoParallelIterator, vec::IntoIter};
+#[cfg(feature = "compression")]
+use flate2::read::MultiGzDecoder;
+use thiserror::Error;
 
-#[cfg(not(feature = "needletail"))]
-pub(crate) fn read<P: AsRef<Path> + Debug>(path: P) -> Result<IntoIter<Bytes>, Box<dyn Error>> {
-    Ok(bio::io::fasta::Reader::from_file(path)?
-        .records()
-        .map(|read| read.expect("Error reading FASTA record."))
-        .map(|record| Bytes::copy_from_slice(record.seq()))
-        .collect::<Vec<Bytes>>()
-        .into_par_iter())
+#[cfg(not(feature = "compression"))]
+use super::output::unsupported;
+use super::quality;
+
+/// A FASTA record's ID, the header up to the first whitespace, and its
+/// sequence, in one or more pieces to be read as if joined
+pub(crate) type Sequence = (String, Vec<Bytes>);
+
+/// A sequence's pieces as one `Bytes`, copying only if there's more than one
+pub(crate) fn joined(pieces: &[Bytes]) -> Bytes {
+    match pieces {
+        [piece] => piece.clone(),
+        _ => pieces.concat().into(),
+    }
+}
+
+/// The path that reads standard input.
+pub const STDIN: &str = "-";
+
+/// Why input couldn't be read, and where.
+#[derive(Debug, Error)]
+pub enum ReadError {
+    #[error("{input}: {source}")]
+    Open {
+        input: String,
+        #[source]
+        source: Box<dyn Error + Send + Sync>,
+    },
+
+    /// `record` counts every record from 1, kept or not
+    #[error("{input}, record {record}{}: {source}", provenance(.header, .offset))]
+    Record {
+        input: String,
+        record: usize,
+        /// The record's header line, where the reader exposes it
+        header: Option<String>,
+        /// Roughly where in the (decompressed) input the record starts
+        offset: Option<u64>,
+        #[source]
+        source: Box<dyn Error + Send + Sync>,
+    },
+}
+
+/// A `Record` error's header and offset, as they appear in its message
+fn provenance(header: &Option<String>, offset: &Option<u64>) -> String {
+    let mut provenance = String::new();
+    if let Some(header) = header {
+        provenance.push_str(&format!(" ({header})"))
+    }
+    if let Some(offset) = offset {
+        provenance.push_str(&format!(" near byte {offset}"))
+    }
+    provenance
+}
+
+impl ReadError {
+    fn open<E: Into<Box<dyn Error + Send + Sync>>>(input: &str, source: E) -> Self {
+        Self::Open {
+            input: input.to_string(),
+            source: source.into(),
+        }
+    }
+
+    fn record<E: Into<Box<dyn Error + Send + Sync>>>(
+        input: &str,
+        record: usize,
+        source: E,
+    ) -> Self {
+        Self::Record {
+            input: input.to_string(),
+            record,
+            header: None,
+            offset: None,
+            source: source.into(),
+        }
+    }
+
+    /// Adds the failing record's header line, if known, and offset
+    fn at(mut self, at_header: Option<&[u8]>, at_offset: u64) -> Self {
+        if let Self::Record { header, offset, .. } = &mut self {
+            *header = at_header.map(|line| String::from_utf8_lossy(line).into_owned());
+            *offset = Some(at_offset);
+        }
+        self
+    }
+}
+
+/// Counts the bytes read through it, so errors can say roughly where they
+/// happened even when the parser doesn't
+///
+/// # Notes
+/// Parsers read ahead into their own buffers, so the count runs up to a
+/// buffer's length past the record being parsed.
+#[cfg(any(feature = "rust-bio", feature = "needletail"))]
+struct Counting<R> {
+    inner: R,
+    read: Arc<AtomicU64>,
+}
+
+#[cfg(any(feature = "rust-bio", feature = "needletail"))]
+impl<R: Read> Read for Counting<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.read.fetch_add(read as u64, Ordering::Relaxed);
+        Ok(read)
+    }
+}
+
+#[cfg(any(feature = "rust-bio", feature = "needletail"))]
+impl<R> Counting<R> {
+    fn new(inner: R) -> (Self, Arc<AtomicU64>) {
+        let read = Arc::new(AtomicU64::new(0));
+        let counting = Self {
+            inner,
+            read: read.clone(),
+        };
+        (counting, read)
+    }
+}
+
+/// How errors name an input
+fn input_name<P: AsRef<Path>>(path: P) -> String {
+    match path.as_ref() == Path::new(STDIN) {
+        true => "stdin".to_string(),
+        false => path.as_ref().display().to_string(),
+    }
+}
+
+/// Reads every FASTA or FASTQ record of `path`, or of stdin given [`STDIN`]
+///
+/// # Notes
+/// Sequences are uppercased so soft-masked (lowercase) bases count like any
+/// others, as they do in `jellyfish`. Given `records`, only records with those
+/// IDs are kept. Given `trim_quality`, FASTQ reads are trimmed at their 3' end
+/// by [`quality::trim_end`].
+pub(crate) fn read<P: AsRef<Path> + Debug>(
+    path: P,
+    records: Option<&HashSet<String>>,
+    trim_quality: Option<u8>,
+) -> Result<Vec<Sequence>, ReadError> {
+    let mut sequences = Vec::new();
+    read_each(path, records, trim_quality, |sequence| {
+        sequences.push(sequence)
+    })?;
+
+    Ok(sequences)
+}
+
+/// Reads as [`read`] does, handing each record to `each` as it's read rather
+/// than holding them all
+pub(crate) fn read_each<P: AsRef<Path> + Debug, F: FnMut(Sequence)>(
+    path: P,
+    records: Option<&HashSet<String>>,
+    trim_quality: Option<u8>,
+    each: F,
+) -> Result<(), ReadError> {
+    let input = input_name(&path);
+    if path.as_ref() == Path::new(STDIN) {
+        return read_stream(
+            BufReader::new(std::io::stdin()),
+            &input,
+            records,
+            trim_quality,
+            each,
+        );
+    }
+
+    // Named pipes and process substitutions such as `<(zcat reads.fq.gz)`
+    // may switch formats as stdin may, so are read the same way
+    let regular = std::fs::metadata(&path).map_or(true, |metadata| metadata.is_file());
+    match regular {
+        true => read_file(path, &input, records, trim_quality, each),
+        false => {
+            let file = File::open(&path).map_err(|e| ReadError::open(&input, e))?;
+            read_stream(BufReader::new(file), &input, records, trim_quality, each)
+        }
+    }
+}
+
+/// `seq` with its 3' end trimmed at `trim_quality`, if given, by its FASTQ
+/// `quality`
+fn trimmed<'a>(seq: &'a [u8], quality: &[u8], trim_quality: Option<u8>) -> &'a [u8] {
+    match trim_quality {
+        Some(threshold) => &seq[..quality::trim_end(quality, threshold).min(seq.len())],
+        None => seq,
+    }
+}
+
+#[cfg(all(feature = "rust-bio", not(feature = "needletail")))]
+fn read_file<P: AsRef<Path> + Debug, F: FnMut(Sequence)>(
+    path: P,
+    input: &str,
+    records: Option<&HashSet<String>>,
+    trim_quality: Option<u8>,
+    mut each: F,
+) -> Result<(), ReadError> {
+    let mut keep = |id: &str, seq: &[u8]| {
+        if records.is_none_or(|records| records.contains(id)) {
+            each((id.to_string(), vec![Bytes::from(seq.to_ascii_uppercase())]))
+        }
+    };
+
+    let (reader, first) = open(path).map_err(|e| ReadError::open(input, e))?;
+    let (reader, read) = Counting::new(reader);
+    let error = |i: usize, e: Box<dyn Error + Send + Sync>| {
+        ReadError::record(input, i + 1, e).at(None, read.load(Ordering::Relaxed))
+    };
+    match first {
+        Some(b'@') => {
+            for (i, record) in bio::io::fastq::Reader::new(reader).records().enumerate() {
+                let record = record.map_err(|e| error(i, e.into()))?;
+                keep(
+                    record.id(),
+                    trimmed(record.seq(), record.qual(), trim_quality),
+                )
+            }
+        }
+        _ => {
+            for (i, record) in bio::io::fasta::Reader::new(reader).records().enumerate() {
+                let record = record.map_err(|e| error(i, e.into()))?;
+                keep(record.id(), record.seq())
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Opens `path` as a stream, decompressing gzip, and peeks at its first byte
+/// to tell FASTA from FASTQ
+///
+/// # Notes
+/// Formats are told apart by content, never by file name.
+#[cfg(all(feature = "rust-bio", not(feature = "needletail")))]
+fn open<P: AsRef<Path>>(path: P) -> std::io::Result<(Box<dyn BufRead + Send>, Option<u8>)> {
+    let mut reader = gunzipped(BufReader::new(File::open(path)?))?;
+    let first = reader.fill_buf()?.first().copied();
+
+    Ok((reader, first))
 }
 
 #[cfg(feature = "needletail")]
-pub(crate) fn read<P: AsRef<Path> + Debug>(path: P) -> Result<IntoIter<Bytes>, Box<dyn Error>> {
-    let mut reader = needletail::parse_fastx_file(path)?;
-    let mut v = Vec::new();
+fn read_file<P: AsRef<Path> + Debug, F: FnMut(Sequence)>(
+    path: P,
+    input: &str,
+    records: Option<&HashSet<String>>,
+    trim_quality: Option<u8>,
+    mut each: F,
+) -> Result<(), ReadError> {
+    let file = File::open(path).map_err(|e| ReadError::open(input, e))?;
+    let (file, read) = Counting::new(file);
+    let mut reader = needletail::parse_fastx_reader(file).map_err(|e| ReadError::open(input, e))?;
+    let mut ordinal = 0;
     while let Some(record) = reader.next() {
-        let record = record.expect("invalid record");
-        let seq = Bytes::copy_from_slice(&record.seq());
-        v.push(seq);
+        ordinal += 1;
+        let record = record.map_err(|e| {
+            ReadError::record(input, ordinal, e).at(None, read.load(Ordering::Relaxed))
+        })?;
+        let id = record_id(record.id());
+        if records.is_some_and(|records| !records.contains(&id)) {
+            continue;
+        }
+        let seq = record.seq();
+        let seq = match record.qual() {
+            Some(quality) => trimmed(&seq, quality, trim_quality),
+            None => &seq,
+        };
+        let seq = Bytes::from(seq.to_ascii_uppercase());
+        each((id, vec![seq]));
+    }
+
+    Ok(())
+}
+
+/// Reads a file with [`read_stream`], when built with neither `rust-bio` nor
+/// `needletail`
+#[cfg(not(any(feature = "rust-bio", feature = "needletail")))]
+fn read_file<P: AsRef<Path> + Debug, F: FnMut(Sequence)>(
+    path: P,
+    input: &str,
+    records: Option<&HashSet<String>>,
+    trim_quality: Option<u8>,
+    each: F,
+) -> Result<(), ReadError> {
+    let file = File::open(path).map_err(|e| ReadError::open(input, e))?;
+    read_stream(BufReader::new(file), input, records, trim_quality, each)
+}
+
+/// Reads FASTA and FASTQ records, gzipped or not, from a stream that may
+/// switch between the two at any record boundary, as `cat a.fa b.fq` does
+///
+/// # Notes
+/// A line starting with `>` opens a FASTA record and one starting with `@`
+/// a FASTQ record, which runs until its quality is as long as its sequence.
+/// No base is written `@`, so neither can be mistaken for sequence. Each
+/// record is handed to `each` as it's read.
+fn read_stream<R: BufRead + Send + 'static, F: FnMut(Sequence)>(
+    reader: R,
+    input: &str,
+    records: Option<&HashSet<String>>,
+    trim_quality: Option<u8>,
+    mut each: F,
+) -> Result<(), ReadError> {
+    let reader = gunzipped(reader).map_err(|e| ReadError::open(input, e))?;
+    let mut lines = Lines::new(reader);
+    // Records begun so far
+    let mut ordinal = 0;
+
+    let mut fasta: Option<(String, Vec<u8>)> = None;
+    let mut keep = |id: String, seq: Vec<u8>| {
+        if records.is_none_or(|records| records.contains(&id)) {
+            each((id, vec![Bytes::from(seq.to_ascii_uppercase())]))
+        }
+    };
+
+    while lines
+        .next()
+        .map_err(|e| ReadError::record(input, ordinal.max(1), e).at(None, lines.offset))?
+    {
+        match lines.line.first() {
+            Some(b'>') => {
+                ordinal += 1;
+                let id = record_id(&lines.line[1..]);
+                if let Some((id, seq)) = fasta.replace((id, Vec::new())) {
+                    keep(id, seq)
+                }
+            }
+            Some(b'@') => {
+                ordinal += 1;
+                if let Some((id, seq)) = fasta.take() {
+                    keep(id, seq)
+                }
+                let (header, offset) = (lines.line.clone(), lines.offset);
+                let id = record_id(&header[1..]);
+                let next = |lines: &mut Lines| {
+                    let error = match lines.next() {
+                        Ok(true) => return Ok(()),
+                        Ok(false) => ReadError::record(input, ordinal, "FASTQ record ends early"),
+                        Err(e) => ReadError::record(input, ordinal, e),
+                    };
+                    Err(error.at(Some(&header), offset))
+                };
+
+                let mut seq = Vec::new();
+                loop {
+                    next(&mut lines)?;
+                    if lines.line.starts_with(b"+") {
+                        break;
+                    }
+                    seq.extend_from_slice(&lines.line);
+                }
+                let mut quality = Vec::with_capacity(seq.len());
+                while quality.len() < seq.len() {
+                    next(&mut lines)?;
+                    quality.extend_from_slice(&lines.line);
+                }
+                let len = trimmed(&seq, &quality, trim_quality).len();
+                seq.truncate(len);
+
+                keep(id, seq)
+            }
+            _ => {
+                if let Some((_, seq)) = &mut fasta {
+                    seq.extend_from_slice(&lines.line)
+                }
+            }
+        }
+    }
+    if let Some((id, seq)) = fasta {
+        keep(id, seq)
+    }
+
+    Ok(())
+}
+
+/// A stream's lines, read one at a time into a reused buffer, with where
+/// each starts.
+struct Lines {
+    reader: Box<dyn BufRead + Send>,
+    /// The current line, without its line ending
+    line: Vec<u8>,
+    /// Byte offset of the current line
+    offset: u64,
+    /// Byte offset of the next line
+    end: u64,
+}
+
+impl Lines {
+    fn new(reader: Box<dyn BufRead + Send>) -> Self {
+        Self {
+            reader,
+            line: Vec::new(),
+            offset: 0,
+            end: 0,
+        }
+    }
+
+    /// Moves on to the next line, returning whether there was one
+    fn next(&mut self) -> io::Result<bool> {
+        self.line.clear();
+        self.offset = self.end;
+        let read = self.reader.read_until(b'\n', &mut self.line)?;
+        self.end += read as u64;
+        while self
+            .line
+            .last()
+            .is_some_and(|byte| *byte == b'\n' || *byte == b'\r')
+        {
+            self.line.pop();
+        }
+
+        Ok(read > 0)
+    }
+}
+
+/// `reader`, decompressed if it starts with gzip's magic bytes
+fn gunzipped<R: BufRead + Send + 'static>(
+    mut reader: R,
+) -> std::io::Result<Box<dyn BufRead + Send>> {
+    Ok(match reader.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        #[cfg(feature = "compression")]
+        true => Box::new(BufReader::new(MultiGzDecoder::new(reader))),
+        #[cfg(not(feature = "compression"))]
+        true => return Err(unsupported("gzipped input", "compression")),
+        false => Box::new(reader),
+    })
+}
+
+/// A header's ID, up to its first whitespace
+fn record_id(header: &[u8]) -> String {
+    String::from_utf8_lossy(header)
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Reads a memory-mapped FASTA file without copying sequence: each record
+/// is the slices of the map holding its lines, and soft-masked bases are left
+/// lowercase for [`Kmer::from_sub`](crate::kmer::Kmer::from_sub) to accept.
+/// `None` if the file isn't plain FASTA, e.g. compressed or FASTQ, to be
+/// read with [`read`] instead
+///
+/// # Notes
+/// The map is shared by every slice, so it stays alive until the last
+/// record is dropped. Changing the file while it's mapped is undefined
+/// behaviour, as with any `mmap`. Only the start of the file is checked, so
+/// FASTQ records after FASTA ones, which [`read`] takes, aren't counted.
+#[cfg(feature = "mmap")]
+pub(crate) fn read_mapped<P: AsRef<Path> + Debug>(
+    path: P,
+    records: Option<&HashSet<String>>,
+) -> Result<Option<Vec<Sequence>>, ReadError> {
+    let input = input_name(&path);
+    let file = std::fs::File::open(path).map_err(|e| ReadError::open(&input, e))?;
+    // SAFETY: see the notes above; krust never writes to its input
+    let map = unsafe { memmap2::Mmap::map(&file) }.map_err(|e| ReadError::open(&input, e))?;
+
+    Ok(is_plain_fasta(&map).then(|| parse_fasta(Bytes::from_owner(map), records)))
+}
+
+/// Whether `text` starts, after any whitespace, with a FASTA header, or is
+/// empty
+#[cfg(feature = "mmap")]
+fn is_plain_fasta(text: &[u8]) -> bool {
+    text.iter()
+        .find(|byte| !byte.is_ascii_whitespace())
+        .is_none_or(|byte| *byte == b'>')
+}
+
+/// Splits in-memory FASTA text into records of line slices
+#[cfg(feature = "mmap")]
+pub(crate) fn parse_fasta(fasta: Bytes, records: Option<&HashSet<String>>) -> Vec<Sequence> {
+    let mut sequences = Vec::new();
+    let mut current: Option<Sequence> = None;
+    let mut start = 0;
+
+    while start < fasta.len() {
+        let end = fasta[start..]
+            .iter()
+            .position(|byte| *byte == b'\n')
+            .map_or(fasta.len(), |i| start + i);
+        let line = fasta[start..end]
+            .strip_suffix(b"\r")
+            .unwrap_or(&fasta[start..end]);
+
+        if let Some(header) = line.strip_prefix(b">") {
+            sequences.extend(current.take());
+            let id = String::from_utf8_lossy(header)
+                .split_whitespace()
+                .next()
+                .unwrap_or_default()
+                .to_string();
+            if records.is_none_or(|records| records.contains(&id)) {
+                current = Some((id, Vec::new()))
+            }
+        } else if let Some((_, pieces)) = &mut current {
+            if !line.is_empty() {
+                pieces.push(fasta.slice_ref(line))
+            }
+        }
+
+        start = end + 1;
+    }
+    sequences.extend(current);
+
+    sequences
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn streamed(
+        stream: &[u8],
+        records: Option<&HashSet<String>>,
+        trim_quality: Option<u8>,
+    ) -> Result<Vec<Sequence>, ReadError> {
+        let mut sequences = Vec::new();
+        let stream = Cursor::new(stream.to_vec());
+        read_stream(stream, "stdin", records, trim_quality, |sequence| {
+            sequences.push(sequence)
+        })?;
+        Ok(sequences)
+    }
+
+    fn ids_and_seqs(sequences: &[Sequence]) -> Vec<(&str, Bytes)> {
+        sequences
+            .iter()
+            .map(|(id, pieces)| (id.as_str(), joined(pieces)))
+            .collect()
+    }
+
+    #[test]
+    fn streams_switch_format_at_record_boundaries() {
+        let stream = b">a desc\nACGT\nacgt\n@b\nGGCC\n+\n@>II\n>c\r\nTT\r\n@d x\nAA\n+d\nII\n";
+        let sequences = streamed(&stream[..], None, None).unwrap();
+        assert_eq!(
+            ids_and_seqs(&sequences),
+            [
+                ("a", Bytes::from("ACGTACGT")),
+                ("b", Bytes::from("GGCC")),
+                ("c", Bytes::from("TT")),
+                ("d", Bytes::from("AA")),
+            ]
+        );
+
+        let records = HashSet::from(["b".to_string()]);
+        let sequences = streamed(&stream[..], Some(&records), None).unwrap();
+        assert_eq!(ids_and_seqs(&sequences), [("b", Bytes::from("GGCC"))]);
+    }
+
+    /// Writes `contents` into a new named pipe from another thread, as a
+    /// shell's process substitution would
+    #[cfg(all(unix, feature = "compression"))]
+    fn pipe(dir: &Path, name: &str, contents: Vec<u8>) -> std::path::PathBuf {
+        let path = dir.join(name);
+        let made = std::process::Command::new("mkfifo")
+            .arg(&path)
+            .status()
+            .unwrap();
+        assert!(made.success());
+
+        let writer = path.clone();
+        std::thread::spawn(move || std::fs::write(writer, contents).unwrap());
+
+        path
+    }
+
+    #[cfg(unix)]
+    #[test]
+    #[cfg(feature = "compression")]
+    fn named_pipes_read_as_stdin_does() {
+        let dir = tempfile::tempdir().unwrap();
+        let stream = b">a\nACGT\nacgt\n@b\nGGCC\n+\nIIII\n>c\r\nTT\r\n";
+        let expected = streamed(stream, None, None).unwrap();
+
+        let fifo = pipe(dir.path(), "mixed", stream.to_vec());
+        let sequences = read(&fifo, None, None).unwrap();
+        assert_eq!(ids_and_seqs(&sequences), ids_and_seqs(&expected));
+
+        let mut gzipped = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        io::Write::write_all(&mut gzipped, stream).unwrap();
+        let fifo = pipe(dir.path(), "gzipped", gzipped.finish().unwrap());
+        let sequences = read(&fifo, None, None).unwrap();
+        assert_eq!(ids_and_seqs(&sequences), ids_and_seqs(&expected));
+
+        let fastq = b"@a\nACGTACGT\n+\nIIIII###\n";
+        let fifo = pipe(dir.path(), "fastq", fastq.to_vec());
+        let sequences = read(&fifo, None, Some(20)).unwrap();
+        assert_eq!(ids_and_seqs(&sequences), [("a", Bytes::from("ACGTA"))]);
+    }
+
+    #[test]
+    fn fastq_reads_are_trimmed_at_their_3_prime_end() {
+        // `#` is Phred 2, `I` Phred 40
+        let fastq = b"@a\nACGTACGT\n+\nIIIII###\n@b\nACGT\n+\n####\n>c\nACGT\n";
+        let sequences = streamed(&fastq[..], None, Some(20)).unwrap();
+        assert_eq!(
+            ids_and_seqs(&sequences),
+            [
+                ("a", Bytes::from("ACGTA")),
+                ("b", Bytes::new()),
+                ("c", Bytes::from("ACGT"))
+            ]
+        );
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), &fastq[..38]).unwrap();
+        let sequences = read(file.path(), None, Some(20)).unwrap();
+        assert_eq!(
+            ids_and_seqs(&sequences),
+            [("a", Bytes::from("ACGTA")), ("b", Bytes::new())]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "mmap")]
+    fn only_plain_fasta_is_mapped() {
+        let fasta = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(fasta.path(), ">a\nACGT\nacgt\n").unwrap();
+        let sequences = read_mapped(fasta.path(), None).unwrap().unwrap();
+        assert_eq!(ids_and_seqs(&sequences), [("a", Bytes::from("ACGTacgt"))]);
+
+        for text in [&b"@a\nACGT\n+\nIIII\n"[..], &[0x1f, 0x8b, 8, 0]] {
+            std::fs::write(fasta.path(), text).unwrap();
+            assert!(read_mapped(fasta.path(), None).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn truncated_fastq_records_are_errors() {
+        let stream = b">a\nACGT\n@b\nGGCC\n+\nII";
+        let e = streamed(&stream[..], None, None).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "stdin, record 2 (@b) near byte 8: FASTQ record ends early"
+        );
+    }
+
+    #[test]
+    fn bad_records_are_reported_by_ordinal() {
+        let fastq = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(fastq.path(), "@a\nACGT\n+\nIIII\n@b\nACGT\nIIII\n").unwrap();
+
+        let e = read(fastq.path(), None, None).unwrap_err();
+        assert!(matches!(e, ReadError::Record { record: 2, .. }), "{e}");
     }
-    Ok(v.into_par_iter())
 }
@@ -0,0 +1,289 @@
+//! `krust repeats`: BED intervals of a genome covered by high-copy k-mers, a
+//! quick repeat annotation from k-mer counts alone, or, with `--seeds`,
+//! candidate repeat elements assembled from those k-mers.
+
+use std::{
+    error::Error,
+    io::{self, Write},
+    path::Path,
+};
+
+use fxhash::FxHashSet;
+use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+
+use super::{
+    kmer::{self, Packed},
+    reader::{self, Sequence},
+    run::{CountMap, KmerMap},
+};
+
+/// A stretch of a record covered by k-mers occurring at least the minimum
+/// number of times across the genome.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepeatInterval {
+    pub record: String,
+    pub start: usize,
+    pub end: usize,
+    /// Highest genome-wide count of the interval's k-mers
+    pub max_count: i32,
+}
+
+/// Counts the canonical `k`-mers of the genome at `path`, then merges the
+/// spans of those counted at least `min_count` times, within each record,
+/// wherever they overlap or touch
+pub fn repeats<P: AsRef<Path> + std::fmt::Debug>(
+    path: P,
+    k: usize,
+    min_count: i32,
+) -> Result<Vec<RepeatInterval>, Box<dyn Error>> {
+    let records = reader::read(path, None, None)?;
+
+    Ok(match k <= u64::MAX_K {
+        true => intervals::<u64>(&records, k, min_count),
+        false => intervals::<u128>(&records, k, min_count),
+    })
+}
+
+fn intervals<P: Packed>(records: &[Sequence], k: usize, min_count: i32) -> Vec<RepeatInterval> {
+    let map = KmerMap::<P>::new(false);
+    map.count(records, k, None);
+
+    records
+        .par_iter()
+        .flat_map_iter(|(id, pieces)| {
+            let seq = reader::joined(pieces);
+            let mut intervals = Vec::<RepeatInterval>::new();
+            for (start, forward, reverse) in kmer::stranded_kmers::<P>(&seq, k) {
+                let count = map
+                    .counts
+                    .get(&forward.min(reverse))
+                    .map_or(0, |count| *count);
+                if count < min_count {
+                    continue;
+                }
+                match intervals.last_mut() {
+                    Some(last) if start <= last.end => {
+                        last.end = start + k;
+                        last.max_count = last.max_count.max(count);
+                    }
+                    _ => intervals.push(RepeatInterval {
+                        record: id.clone(),
+                        start,
+                        end: start + k,
+                        max_count: count,
+                    }),
+                }
+            }
+            intervals
+        })
+        .collect()
+}
+
+/// A candidate repeat element, the sequence spelled by a path of high-copy
+/// k-mers through the genome's de Bruijn graph.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RepeatSeed {
+    pub sequence: String,
+    /// Genome-wide count of the k-mer the seed was grown from, the highest
+    /// of its k-mers
+    pub max_count: i32,
+    /// Mean genome-wide count of its k-mers, about how many copies of the
+    /// element the genome holds
+    pub mean_count: f64,
+}
+
+/// Counts the canonical `k`-mers of the genome at `path`, then grows
+/// candidate repeat elements from those counted at least `min_count` times,
+/// keeping those of at least `min_length` bases, most abundant first
+///
+/// # Notes
+/// Starting from the most abundant k-mer not yet used, a seed is extended
+/// greedily one base at a time, right through its successors and then left
+/// through its predecessors, always to the most abundant high-copy k-mer not
+/// yet used, until none is left. Each k-mer joins at most one seed, so
+/// copies of one family collapse into a single consensus-like sequence, while
+/// a branch into another family starts a seed of its own.
+pub fn seeds<P: AsRef<Path> + std::fmt::Debug>(
+    path: P,
+    k: usize,
+    min_count: i32,
+    min_length: usize,
+) -> Result<Vec<RepeatSeed>, Box<dyn Error>> {
+    let records = reader::read(path, None, None)?;
+
+    Ok(match k <= u64::MAX_K {
+        true => grow_seeds::<u64>(&records, k, min_count, min_length),
+        false => grow_seeds::<u128>(&records, k, min_count, min_length),
+    })
+}
+
+fn grow_seeds<P: Packed>(
+    records: &[Sequence],
+    k: usize,
+    min_count: i32,
+    min_length: usize,
+) -> Vec<RepeatSeed> {
+    let map = KmerMap::<P>::new(false);
+    map.count(records, k, None);
+
+    let mut abundant = map
+        .counts
+        .iter()
+        .map(|entry| (*entry.key(), *entry.value()))
+        .filter(|(_, count)| *count >= min_count)
+        .collect::<Vec<_>>();
+    // Most abundant first, ties broken by k-mer so seeds are the same run to run
+    abundant.sort_unstable_by_key(|(packed_bits, count)| (std::cmp::Reverse(*count), *packed_bits));
+
+    let mut used = FxHashSet::default();
+    let mut seeds = Vec::new();
+    for (seed, max_count) in abundant {
+        if !used.insert(seed) {
+            continue;
+        }
+
+        let mut counts = vec![max_count];
+        let mut right = Vec::new();
+        let mut kmer = seed;
+        while let Some((next, count)) = next_kmer(
+            &map.counts,
+            kmer::successors(kmer, k),
+            k,
+            min_count,
+            &mut used,
+        ) {
+            right.push(kmer::unpack_to_string(next, k).pop().expect("k > 0"));
+            counts.push(count);
+            kmer = next;
+        }
+        let mut left = Vec::new();
+        let mut kmer = seed;
+        while let Some((next, count)) = next_kmer(
+            &map.counts,
+            kmer::predecessors(kmer, k),
+            k,
+            min_count,
+            &mut used,
+        ) {
+            left.push(kmer::unpack_to_string(next, k).remove(0));
+            counts.push(count);
+            kmer = next;
+        }
+
+        let sequence = left
+            .into_iter()
+            .rev()
+            .chain(kmer::unpack_to_string(seed, k).chars())
+            .chain(right)
+            .collect::<String>();
+        if sequence.len() >= min_length {
+            seeds.push(RepeatSeed {
+                sequence,
+                max_count,
+                mean_count: counts.iter().map(|count| *count as f64).sum::<f64>()
+                    / counts.len() as f64,
+            })
+        }
+    }
+
+    seeds
+}
+
+/// The most abundant of `candidates`, as read, whose canonical form is
+/// counted at least `min_count` times and not yet `used`, marking it used
+fn next_kmer<P: Packed>(
+    counts: &CountMap<P>,
+    candidates: [P; 4],
+    k: usize,
+    min_count: i32,
+    used: &mut FxHashSet<P>,
+) -> Option<(P, i32)> {
+    let (next, canonical, count) = candidates
+        .into_iter()
+        .filter_map(|candidate| {
+            let canonical = candidate.min(kmer::reverse_complement_bits(candidate, k));
+            let count = *counts.get(&canonical)?;
+            (count >= min_count && !used.contains(&canonical))
+                .then_some((candidate, canonical, count))
+        })
+        // The first base wins ties
+        .min_by_key(|(candidate, _, count)| (std::cmp::Reverse(*count), *candidate))?;
+    used.insert(canonical);
+
+    Some((next, count))
+}
+
+/// Writes seeds as FASTA, each named `repeat_` and its rank by abundance,
+/// with its length and k-mer counts
+pub fn write_seeds<W: Write>(out: &mut W, seeds: &[RepeatSeed]) -> io::Result<()> {
+    for (i, seed) in seeds.iter().enumerate() {
+        writeln!(
+            out,
+            ">repeat_{} length={} max_count={} mean_count={:.1}\n{}",
+            i + 1,
+            seed.sequence.len(),
+            seed.max_count,
+            seed.mean_count,
+            seed.sequence
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Writes intervals as BED, 0-based and half-open, with each interval's
+/// highest k-mer count in the name column
+pub fn write<W: Write>(out: &mut W, intervals: &[RepeatInterval]) -> io::Result<()> {
+    for interval in intervals {
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}",
+            interval.record, interval.start, interval.end, interval.max_count
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn merges_spans_of_high_copy_kmers() {
+        let repeat = "ACGGTCATTGCA";
+        let genome = format!(
+            ">chr1\nTTTAGC{repeat}GATCCAAGTG{repeat}{repeat}CTTACG\n>chr2\nGAACGT{repeat}\n"
+        );
+        let fasta = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(fasta.path(), genome).unwrap();
+
+        let mut out = Vec::new();
+        write(&mut out, &repeats(fasta.path(), 5, 4).unwrap()).unwrap();
+        insta::assert_snapshot!(String::from_utf8(out).unwrap(), @r###"
+        chr1	6	18	5
+        chr1	28	52	5
+        chr2	6	18	5
+        "###);
+    }
+
+    #[test]
+    fn seeds_assemble_each_repeat_family_once() {
+        let family = "GGATCACAGTCTACACTGCTCACT";
+        let other = "CCAACCCCGGCCCCTGAGTCCGAG";
+        let genome = format!(
+            ">chr1\nTAAGCC{family}GATCCAAGTG{family}CTTACG{other}\n>chr2\nGAACGT{other}CCGCAT{family}AGT\n"
+        );
+        let fasta = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(fasta.path(), genome).unwrap();
+
+        let mut out = Vec::new();
+        write_seeds(&mut out, &seeds(fasta.path(), 7, 2, 20).unwrap()).unwrap();
+        insta::assert_snapshot!(String::from_utf8(out).unwrap(), @r###"
+        >repeat_1 length=25 max_count=3 mean_count=2.9
+        TGGATCACAGTCTACACTGCTCACT
+        >repeat_2 length=24 max_count=2 mean_count=2.0
+        CCAACCCCGGCCCCTGAGTCCGAG
+        "###);
+    }
+}
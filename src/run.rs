@@ -1,136 +1,1561 @@
-use super::{kmer::Kmer, reader::read};
+#[cfg(feature = "compact")]
+use super::compact::CompactCounts;
+use super::{
+    bias,
+    bloom::Bloom,
+    config::Config,
+    dedup, external,
+    hasher::{HasherKind, KmerBuildHasher},
+    histo::{self, Bins, HistoFormat, OnlineHistogram},
+    kmer::{self, Packed},
+    memory::{self, Report},
+    minimizer::MinimizerIterator,
+    output::{Encoder, OutputFormat, RecordWriter},
+    paired, per_record,
+    progress::ProgressTracker,
+    reader::{self, read, Sequence},
+    sort::{self, SortOrder},
+    spill::{SingletonSpill, SPILL_CHECK_BASES},
+    summary::{self, RecordSummary},
+};
 use bytes::Bytes;
-use dashmap::DashMap;
-use fxhash::FxHasher;
-use rayon::prelude::{ParallelBridge, ParallelIterator};
+use dashmap::{DashMap, SharedValue};
+use rayon::prelude::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
 use std::{
-    collections::{hash_map::IntoIter, HashMap},
-    error::Error,
-    fmt::Debug,
-    hash::BuildHasherDefault,
+    collections::BTreeMap,
+    fmt::{self, Display},
+    fs::File,
     io::{stdout, BufWriter, Error as IoError, Write},
+    mem,
     path::Path,
+    str::FromStr,
+    sync::{
+        atomic::AtomicUsize,
+        mpsc::{self, Receiver, SyncSender},
+    },
+    thread,
+    time::{Duration, Instant},
 };
 use thiserror::Error;
 
 #[derive(Debug, Error)]
 pub enum ProcessError {
     #[error("Unable to read input: {0}")]
-    ReadError(#[from] Box<dyn Error>),
+    ReadError(#[from] reader::ReadError),
 
     #[error("Unable to write output: {0}")]
     WriteError(#[from] IoError),
+
+    #[error("Unable to pair reads: {0}")]
+    PairError(#[from] paired::PairError),
+}
+
+pub fn run(config: Config) -> Result<(), ProcessError> {
+    run_to(&config, || writer(&config))
+}
+
+/// Runs as [`run`] does, writing to what `open` returns, opened once there
+/// is something to write
+fn run_to<W: Write + Send>(
+    config: &Config,
+    open: impl FnOnce() -> Result<W, IoError>,
+) -> Result<(), ProcessError> {
+    let start = Instant::now();
+    // Histograms are kept by the count map as it counts, so are never counted
+    // through bucket files, compactly or as a set
+    let histogram = config.format == OutputFormat::Histogram;
+    let streamed = !(config.summary_per_record || config.per_record || histogram);
+    if let Some(max_memory) = config.max_memory.filter(|_| streamed) {
+        return match (config.k <= u64::MAX_K, config.distinct) {
+            (true, false) => {
+                count_external_and_output::<u64, i32, _>(config, max_memory, start, open)
+            }
+            (true, true) => {
+                count_external_and_output::<u64, (), _>(config, max_memory, start, open)
+            }
+            (false, false) => {
+                count_external_and_output::<u128, i32, _>(config, max_memory, start, open)
+            }
+            (false, true) => {
+                count_external_and_output::<u128, (), _>(config, max_memory, start, open)
+            }
+        };
+    }
+
+    let mut inputs = config
+        .paths
+        .iter()
+        .map(|path| read_input(path, config))
+        .collect::<Result<Vec<_>, _>>()?;
+    if config.paired {
+        paired::check_pairs(&config.paths, &inputs)?;
+    }
+    if config.dedup_exact {
+        eprintln!("{}", dedup::dedup_exact(&mut inputs));
+    }
+
+    if config.summary_per_record {
+        let summaries = inputs
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .into_par_iter()
+            .map(|(id, pieces)| RecordSummary::new(id, &reader::joined(&pieces), config.k))
+            .collect::<Vec<_>>();
+        let mut out = config.compression.encoder(open()?)?;
+        summary::write(&mut out, &summaries, config.delimiter.unwrap_or('\t'))?;
+        out.finish()?;
+        return Ok(());
+    }
+
+    if config.per_record {
+        let records = per_record::per_record(inputs.into_iter().flatten().collect(), config.k);
+        let mut out = config.compression.encoder(open()?)?;
+        per_record::write(&mut out, &records, config.delimiter.unwrap_or('\t'))?;
+        out.finish()?;
+        return Ok(());
+    }
+
+    #[cfg(feature = "compact")]
+    if config.backend == Backend::Compact && !histogram {
+        return count_compact_and_output(config, inputs, start, open);
+    }
+
+    match (config.k <= u64::MAX_K, config.distinct && !histogram) {
+        (true, false) => count_and_output::<u64, i32, _>(config, inputs, start, open),
+        (true, true) => count_and_output::<u64, (), _>(config, inputs, start, open),
+        (false, false) => count_and_output::<u128, i32, _>(config, inputs, start, open),
+        (false, true) => count_and_output::<u128, (), _>(config, inputs, start, open),
+    }
+}
+
+/// Counts canonical k-mers across every file in `paths` into one map,
+/// returning each with its count
+pub fn count_files<P: AsRef<Path>>(
+    paths: &[P],
+    k: usize,
+) -> Result<Vec<(String, i32)>, ProcessError> {
+    let config = Config {
+        k,
+        paths: paths.iter().map(|path| path.as_ref().into()).collect(),
+        ..Default::default()
+    };
+
+    match k <= u64::MAX_K {
+        true => KmerMap::<u64>::configured(&config).count_files(&config),
+        false => KmerMap::<u128>::configured(&config).count_files(&config),
+    }
+}
+
+/// Counts k-mers across every file in `config.paths` and writes them to
+/// `out` as the `krust` binary would given `config`, rather than to
+/// `config.output`
+///
+/// # Notes
+/// K-mers stay packed in the count map until written, each unpacked into the
+/// same buffer, so unlike [`count_files`] no `String` is held per k-mer.
+pub fn count_to_writer<W: Write + Send>(config: &Config, out: W) -> Result<(), ProcessError> {
+    run_to(config, || Ok(out))
+}
+
+/// Counts canonical k-mers across every file in `paths` as [`count_files`]
+/// does, returning them in `order` so results are the same from run to run
+///
+/// # Notes
+/// K-mers are sorted while still packed, which orders them as their strings
+/// would be, before any is unpacked.
+pub fn sorted_counts<P: AsRef<Path>>(
+    paths: &[P],
+    k: usize,
+    order: SortOrder,
+) -> Result<Vec<(String, i32)>, ProcessError> {
+    let config = Config {
+        k,
+        paths: paths.iter().map(|path| path.as_ref().into()).collect(),
+        sort: Some(order),
+        ..Default::default()
+    };
+
+    match k <= u64::MAX_K {
+        true => KmerMap::<u64>::configured(&config).count_files(&config),
+        false => KmerMap::<u128>::configured(&config).count_files(&config),
+    }
+}
+
+/// Reads one input's records, mapping it if asked, the build has `mmap`, and
+/// it's a regular file of plain FASTA
+fn read_input(path: &Path, config: &Config) -> Result<Vec<Sequence>, reader::ReadError> {
+    // Only regular files can be mapped; pipes are streamed instead
+    #[cfg(feature = "mmap")]
+    if config.mmap && std::fs::metadata(path).is_ok_and(|metadata| metadata.is_file()) {
+        if let Some(sequences) = reader::read_mapped(path, config.records.as_ref())? {
+            return Ok(sequences);
+        }
+    }
+
+    read(path, config.records.as_ref(), config.trim_quality)
+}
+
+/// Counts through bucket files holding about `max_memory` bytes of counts
+/// at once, streaming the inputs rather than reading them in first, then
+/// writes the counts out
+fn count_external_and_output<P: Packed, V: Tally, W: Write + Send>(
+    config: &Config,
+    max_memory: usize,
+    start: Instant,
+    open: impl FnOnce() -> Result<W, IoError>,
+) -> Result<(), ProcessError> {
+    let distinct_kmers = AtomicUsize::new(0);
+    let records = external::count::<P, V>(config, max_memory, &distinct_kmers)?;
+    output(
+        records,
+        None,
+        config,
+        (!config.deterministic).then(|| start.elapsed()),
+        open()?,
+    )?;
+
+    if config.report {
+        let report = Report {
+            distinct_kmers: distinct_kmers.into_inner(),
+            peak_map_bytes: None,
+            map_bytes: None,
+            peak_rss: memory::peak_rss(),
+        };
+        eprintln!("{report}");
+    }
+
+    Ok(())
+}
+
+/// Counts into a map keyed by packed k-mers of type `P`, keeping a `V` for
+/// each, then writes it out
+fn count_and_output<P: Packed, V: Tally, W: Write + Send>(
+    config: &Config,
+    inputs: Vec<Vec<Sequence>>,
+    start: Instant,
+    open: impl FnOnce() -> Result<W, IoError>,
+) -> Result<(), ProcessError> {
+    let baseline = memory::allocated();
+    memory::reset_peak();
+
+    let map = match config.format {
+        OutputFormat::Histogram => KmerMap::<P, V>::configured(config).with_histogram(),
+        _ => KmerMap::<P, V>::configured(config),
+    };
+    let count = |progress: Option<&ProgressTracker>| {
+        if config.filter_singletons {
+            let kmers = config
+                .expected_kmers
+                .unwrap_or_else(|| bases(inputs.iter().flatten()));
+            map.count_repeated(&inputs, config.k, &Bloom::new(kmers), progress);
+        } else if config.singleton_spill && map.histogram.is_none() {
+            return map.count_spilling(&inputs, config.k, progress).map(Some);
+        } else {
+            map.count_inputs(&inputs, config.k, progress);
+        }
+        Ok::<_, IoError>(None)
+    };
+    let spill = match config.progress {
+        true => {
+            let tracker = ProgressTracker::with_format(config.progress_format);
+            tracker.report(|| map.counts.len(), || count(Some(&tracker)))
+        }
+        false => count(None),
+    }?;
+
+    if let (Some(path), Some(reverse)) = (&config.bias_report, &map.reverse) {
+        let mut out = BufWriter::new(File::create(path)?);
+        bias::write(&mut out, &bias::gc_classes(&map.counts, reverse, config.k))?;
+        out.flush()?;
+    }
+
+    let since_baseline = |bytes: Option<usize>| Some(bytes?.saturating_sub(baseline?));
+    let mut report = Report {
+        distinct_kmers: map.counts.len(),
+        peak_map_bytes: since_baseline(memory::peak_allocated()),
+        map_bytes: since_baseline(memory::allocated()),
+        peak_rss: None,
+    };
+
+    let elapsed = (!config.deterministic).then(|| start.elapsed());
+    match (map.histogram(), spill) {
+        (Some(histogram), _) => {
+            let mut out = config.compression.encoder(open()?)?;
+            let histograms = BTreeMap::from([(config.k, histogram)]);
+            histo::write(
+                &mut out,
+                &histograms,
+                &Bins::default(),
+                HistoFormat::Jellyfish,
+            )?;
+            out.finish()?;
+        }
+        (None, Some(spill)) => {
+            let (spilled, rest) = spill.reconcile(&map.counts)?;
+            report.distinct_kmers += spilled;
+            let kept = output_filter(config);
+            let rest = rest
+                .filter(move |record| {
+                    record
+                        .as_ref()
+                        .map_or(true, |(packed_bits, count)| kept(*packed_bits, *count))
+                })
+                .map(|record| record.map(|(packed_bits, count)| (packed_bits, count, None)));
+            output(
+                map.stream(config).map(Ok).chain(rest),
+                Some(report.distinct_kmers),
+                config,
+                elapsed,
+                open()?,
+            )?;
+        }
+        (None, None) => map.output(config, elapsed, open()?)?,
+    }
+
+    if config.report {
+        report.peak_rss = memory::peak_rss();
+        eprintln!("{report}");
+    }
+
+    Ok(())
+}
+
+/// Counts k-mers of up to 32 bases into a [`CompactCounts`] table, then
+/// writes it out
+#[cfg(feature = "compact")]
+fn count_compact_and_output<W: Write + Send>(
+    config: &Config,
+    inputs: Vec<Vec<Sequence>>,
+    start: Instant,
+    open: impl FnOnce() -> Result<W, IoError>,
+) -> Result<(), ProcessError> {
+    let baseline = memory::allocated();
+    memory::reset_peak();
+
+    let counts = CompactCounts::new(config.k, config.expected_kmers.unwrap_or_default());
+    let count = |progress: Option<&ProgressTracker>| {
+        for sequences in &inputs {
+            for_each_sequence(sequences, progress, |pieces| {
+                kmer_batches(
+                    pieces,
+                    config.k,
+                    config.stride.unwrap_or(1),
+                    config.minimizer_window,
+                    !config.no_canonical,
+                    |batch| counts.increment_batch(batch),
+                )
+            });
+            if let Some(progress) = progress {
+                progress.finish_input()
+            }
+        }
+    };
+    match config.progress {
+        true => {
+            let tracker = ProgressTracker::with_format(config.progress_format);
+            tracker.report(|| counts.len(), || count(Some(&tracker)))
+        }
+        false => count(None),
+    }
+
+    let since_baseline = |bytes: Option<usize>| Some(bytes?.saturating_sub(baseline?));
+    let mut report = Report {
+        distinct_kmers: counts.len(),
+        peak_map_bytes: since_baseline(memory::peak_allocated()),
+        map_bytes: since_baseline(memory::allocated()),
+        peak_rss: None,
+    };
+
+    let kept = output_filter(config);
+    let records = counts
+        .into_counts()
+        .filter(move |(packed_bits, count)| kept(*packed_bits, *count))
+        .map(|(packed_bits, count)| Ok((packed_bits, count, None)));
+    output(
+        records,
+        Some(report.distinct_kmers),
+        config,
+        (!config.deterministic).then(|| start.elapsed()),
+        open()?,
+    )?;
+
+    if config.report {
+        report.peak_rss = memory::peak_rss();
+        eprintln!("{report}");
+    }
+
+    Ok(())
 }
 
-pub fn run<P>(path: P, k: usize) -> Result<(), ProcessError>
-where
-    P: AsRef<Path> + Debug,
-{
-    KmerMap::new().build(read(path)?, k)?.output(k)?;
+/// Records per batch sent to the writer thread.
+const WRITE_BATCH_LEN: usize = 4096;
+
+/// Batches queued for the writer thread before producing blocks.
+///
+/// # Notes
+/// At around 24 bytes a record this holds at most a few MB of records.
+const WRITE_QUEUE_LEN: usize = 64;
+
+/// `--output`, or `stdout`, buffered
+fn writer(config: &Config) -> Result<BufWriter<Box<dyn Write + Send>>, IoError> {
+    let out: Box<dyn Write + Send> = match &config.output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(stdout()),
+    };
+
+    Ok(BufWriter::new(out))
+}
+
+/// Writes every batch received, between the format's header and footer
+fn write_records<P: Packed, W: Write>(
+    mut buf: RecordWriter<Encoder<W>>,
+    elapsed: Option<Duration>,
+    batches: Receiver<Vec<Record<P>>>,
+) -> Result<(), IoError> {
+    buf.header(elapsed)?;
+    for batch in batches {
+        for (packed_bits, count, strand) in batch {
+            buf.record(packed_bits, count, strand)?
+        }
+    }
+    buf.footer()?;
+    buf.into_inner().finish()?;
 
     Ok(())
 }
 
-/// A custom `DashMap` w/ `FxHasher`.
+/// Inputs with fewer bases than this are counted on one thread.
+const SMALL_INPUT_BASES: usize = 4 << 20;
+
+/// K-mers gathered from a sequence between insertions into the count map.
+pub(crate) const INSERT_BATCH_LEN: usize = 1 << 14;
+
+/// A custom `DashMap` w/ `FxHasher`, or the identity hasher given
+/// `--hasher identity`.
 ///
 /// # Notes
 /// Useful: [Using a Custom Hash Function in Rust](https://docs.rs/hashers/1.0.1/hashers/#using-a-custom-hash-function-in-rust)
-type DashFx = DashMap<u64, i32, BuildHasherDefault<FxHasher>>;
+pub(crate) type CountMap<P = u64, V = i32> = DashMap<P, V, KmerBuildHasher>;
+
+/// What k-mers are counted in: a [`CountMap`], or, given `--backend compact`
+/// and the `compact` feature, a `compact::CompactCounts` table.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub enum Backend {
+    #[default]
+    DashMap,
+    Compact,
+}
+
+impl Backend {
+    pub const VARIANTS: [&'static str; 2] = ["dashmap", "compact"];
+}
+
+impl FromStr for Backend {
+    type Err = String;
 
-struct KmerMap(DashFx);
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dashmap" => Ok(Self::DashMap),
+            "compact" => Ok(Self::Compact),
+            _ => Err(format!("Unknown backend \"{s}\"")),
+        }
+    }
+}
+
+impl Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::DashMap => "dashmap",
+            Self::Compact => "compact",
+        })
+    }
+}
+
+/// What the count map keeps for each k-mer: its count, or, when only which
+/// k-mers occur matters, as for `--distinct`, nothing at all.
+pub(crate) trait Tally: Copy + Default + Send + Sync + 'static {
+    fn increment(&mut self);
+
+    /// Adds `n` occurrences counted elsewhere
+    fn add(&mut self, n: i32);
+
+    /// The count so far, `0` if not counting
+    fn count(self) -> i32;
+}
 
-impl KmerMap {
-    fn new() -> Self {
-        Self(DashMap::with_hasher(
-            BuildHasherDefault::<FxHasher>::default(),
-        ))
+impl Tally for i32 {
+    fn increment(&mut self) {
+        *self += 1
+    }
+
+    fn add(&mut self, n: i32) {
+        *self += n
+    }
+
+    fn count(self) -> i32 {
+        self
+    }
+}
+
+impl Tally for () {
+    fn increment(&mut self) {}
+
+    fn add(&mut self, _: i32) {}
+
+    fn count(self) -> i32 {
+        0
+    }
+}
+
+/// A packed canonical k-mer, its count, and its strand counts if tracked
+pub(crate) type Record<P = u64> = (P, i32, Option<StrandCounts>);
+
+/// How many occurrences of a canonical k-mer were read from each strand.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct StrandCounts {
+    /// Occurrences found as the canonical k-mer itself
+    pub forward: i32,
+    /// Occurrences found as the reverse complement of the canonical k-mer
+    pub reverse: i32,
+}
+
+impl StrandCounts {
+    pub fn total(&self) -> i32 {
+        self.forward + self.reverse
+    }
+}
+
+pub(crate) struct KmerMap<P: Packed = u64, V: Tally = i32> {
+    pub(crate) counts: CountMap<P, V>,
+    /// Reverse strand occurrences, tracked only when requested
+    pub(crate) reverse: Option<CountMap<P, V>>,
+    /// Only every this many-th k-mer window of a record is counted
+    stride: usize,
+    /// Only the minimizers of each this many consecutive k-mers are counted
+    minimizer_window: Option<usize>,
+    /// Count each k-mer as the lesser of it and its reverse complement,
+    /// rather than as read
+    canonical: bool,
+    /// Count-of-counts kept up to date while counting, when requested
+    histogram: Option<OnlineHistogram>,
+}
+
+impl<P: Packed, V: Tally> KmerMap<P, V> {
+    pub(crate) fn new(strand: bool) -> Self {
+        Self::with_capacity_and_hasher(strand, 0, HasherKind::default())
+    }
+
+    /// Counts as `config` asks: its stride, minimizer window, strandedness,
+    /// hasher and expected k-mers
+    pub(crate) fn configured(config: &Config) -> Self {
+        Self::with_capacity_and_hasher(
+            config.strand || config.bias_report.is_some(),
+            config.expected_kmers.unwrap_or_default(),
+            config.hasher,
+        )
+        .with_stride(config.stride.unwrap_or(1))
+        .with_minimizer_window(config.minimizer_window)
+        .with_canonical(!config.no_canonical)
+    }
+
+    /// Pre-sizes both maps for `capacity` distinct k-mers, so a good
+    /// estimate spares counting the repeated resizing of a growing map
+    pub(crate) fn with_capacity_and_hasher(
+        strand: bool,
+        capacity: usize,
+        hasher: HasherKind,
+    ) -> Self {
+        let map = || DashMap::with_capacity_and_hasher(capacity, KmerBuildHasher(hasher));
+
+        Self {
+            counts: map(),
+            reverse: strand.then(map),
+            stride: 1,
+            minimizer_window: None,
+            canonical: true,
+            histogram: None,
+        }
+    }
+
+    /// Counts only the k-mers starting every `stride` bases of a record,
+    /// from its first
+    pub(crate) fn with_stride(self, stride: usize) -> Self {
+        Self { stride, ..self }
+    }
+
+    /// Counts only the minimizers of each `minimizer_window` consecutive
+    /// k-mers, if given, rather than every k-mer
+    pub(crate) fn with_minimizer_window(self, minimizer_window: Option<usize>) -> Self {
+        Self {
+            minimizer_window,
+            ..self
+        }
+    }
+
+    /// Counts k-mers as read, never as their reverse complements, unless
+    /// `canonical`
+    pub(crate) fn with_canonical(self, canonical: bool) -> Self {
+        Self { canonical, ..self }
+    }
+
+    /// Keeps how many k-mers have each count as they are counted, so
+    /// [`KmerMap::histogram`] needn't go over the whole map
+    pub(crate) fn with_histogram(self) -> Self {
+        let histogram = Some(OnlineHistogram::new(self.counts.shards().len()));
+        Self { histogram, ..self }
+    }
+
+    /// How many distinct k-mers have each count, if kept
+    /// [`KmerMap::with_histogram`]
+    pub(crate) fn histogram(&self) -> Option<BTreeMap<i32, u64>> {
+        self.histogram.as_ref().map(OnlineHistogram::merged)
+    }
+
+    /// Hands the k-mers of `pieces` this map counts to `f` in batches, as
+    /// [`kmer_batches`] does
+    fn batches(&self, pieces: &[Bytes], k: usize, f: impl FnMut(&mut Vec<(P, bool)>)) {
+        kmer_batches::<P>(
+            pieces,
+            k,
+            self.stride,
+            self.minimizer_window,
+            self.canonical,
+            f,
+        )
+    }
+
+    /// Reads and counts each of `config.paths` in turn, then unpacks the
+    /// merged counts
+    fn count_files(self, config: &Config) -> Result<Vec<(String, i32)>, ProcessError> {
+        for path in &config.paths {
+            self.count(&read_input(path, config)?, config.k, None);
+        }
+
+        let mut counts = self
+            .counts
+            .into_iter()
+            .map(|(packed_bits, count)| (packed_bits, count.count()))
+            .collect::<Vec<_>>();
+        if let Some(order) = config.sort {
+            counts.sort_unstable_by_key(|(packed_bits, count)| order.key(*packed_bits, *count));
+        }
+
+        Ok(counts
+            .into_iter()
+            .map(|(packed_bits, count)| (kmer::unpack_to_string(packed_bits, config.k), count))
+            .collect())
+    }
+
+    /// Counts every input's sequences into the one map, one input after
+    /// another, marking each finished input on `progress`
+    fn count_inputs(&self, inputs: &[Vec<Sequence>], k: usize, progress: Option<&ProgressTracker>) {
+        for sequences in inputs {
+            self.count(sequences, k, progress);
+            if let Some(progress) = progress {
+                progress.finish_input()
+            }
+        }
+    }
+
+    /// Counts `inputs` as [`KmerMap::count_inputs`] does, moving the k-mers
+    /// counted once so far to disk whenever the map has doubled, checking
+    /// every [`SPILL_CHECK_BASES`] bases, and once more at the end,
+    /// returning them to be reconciled
+    fn count_spilling(
+        &self,
+        inputs: &[Vec<Sequence>],
+        k: usize,
+        progress: Option<&ProgressTracker>,
+    ) -> Result<SingletonSpill, IoError> {
+        let mut spill = SingletonSpill::new()?;
+        for sequences in inputs {
+            let mut rest = &sequences[..];
+            while !rest.is_empty() {
+                let mut bases = 0;
+                let len = rest
+                    .iter()
+                    .take_while(|(_, pieces)| {
+                        let within = bases < SPILL_CHECK_BASES;
+                        bases += pieces.iter().map(Bytes::len).sum::<usize>();
+                        within
+                    })
+                    .count();
+                let (chunk, next) = rest.split_at(len);
+                self.count(chunk, k, progress);
+                spill.evict_if_grown(&self.counts)?;
+                rest = next;
+            }
+            if let Some(progress) = progress {
+                progress.finish_input()
+            }
+        }
+        spill.evict(&self.counts)?;
+
+        Ok(spill)
+    }
+
+    /// Counts only the k-mers seen at least twice across `inputs`, so
+    /// k-mers seen once, mostly sequencing errors, never take up the map
+    ///
+    /// # Notes
+    /// A first pass adds a k-mer to the map, uncounted, only when `bloom`
+    /// has seen it before. A second counts the k-mers in the map exactly,
+    /// after which those seen once, let in by a false positive, are dropped.
+    /// `progress` follows the first pass.
+    fn count_repeated(
+        &self,
+        inputs: &[Vec<Sequence>],
+        k: usize,
+        bloom: &Bloom,
+        progress: Option<&ProgressTracker>,
+    ) {
+        for sequences in inputs {
+            for_each_sequence(sequences, progress, |pieces| {
+                self.batches(pieces, k, |batch| {
+                    batch.retain(|(packed_bits, _)| bloom.insert(*packed_bits));
+                    self.update_batch(batch, Update::Insert)
+                })
+            });
+            if let Some(progress) = progress {
+                progress.finish_input()
+            }
+        }
+        for sequences in inputs {
+            for_each_sequence(sequences, None, |pieces| {
+                self.batches(pieces, k, |batch| self.update_batch(batch, Update::Recount))
+            });
+        }
+
+        self.counts.retain(|_, count| count.count() > 1);
+        if let Some(histogram) = &self.histogram {
+            histogram.forget(1)
+        }
+        if let Some(reverse) = &self.reverse {
+            reverse.retain(|packed_bits, _| self.counts.contains_key(packed_bits))
+        }
     }
 
     /// Reads sequences from fasta records in parallel using [`rayon`](https://docs.rs/rayon/1.5.1/rayon/),
     /// using a customized [`dashmap`](https://docs.rs/dashmap/4.0.2/dashmap/struct.DashMap.html)
     /// with [`FxHasher`](https://docs.rs/fxhash/0.2.1/fxhash/struct.FxHasher.html) to update in parallel a
     /// hashmap of canonical k-mers (keys) and their frequency in the data (values)
-    fn build(
-        self,
-        sequences: rayon::vec::IntoIter<Bytes>,
+    pub(crate) fn count(
+        &self,
+        sequences: &[Sequence],
         k: usize,
-    ) -> Result<Self, Box<dyn Error>> {
-        sequences.for_each(|seq| self.process_sequence(&seq, &k));
+        progress: Option<&ProgressTracker>,
+    ) {
+        for_each_sequence(sequences, progress, |pieces| {
+            self.process_pieces(pieces, &k)
+        })
+    }
 
-        Ok(self)
+    /// Counts a sequence held in pieces, such as the lines of a multi-line
+    /// FASTA record, as if they were joined, without joining them
+    pub(crate) fn process_pieces(&self, pieces: &[Bytes], k: &usize) {
+        self.batches(pieces, *k, |batch| self.insert_batch(batch))
     }
 
-    /// Ignore substrings containing `N`
+    /// Counts a batch of `(canonical k-mer, found as its reverse complement)`
+    /// pairs, leaving it empty
     ///
     /// # Notes
-    /// Canonicalizes by lexicographically smaller of k-mer/reverse-complement
-    fn process_sequence(&self, seq: &Bytes, k: &usize) {
-        let mut i = 0;
+    /// Each key is hashed once, that hash both picking its shard and placing
+    /// it within the shard, rather than once by `DashMap` and again by the
+    /// shard's own map. Sorting the batch by shard then takes each shard's
+    /// lock once per batch instead of once per k-mer.
+    pub(crate) fn insert_batch(&self, batch: &mut Vec<(P, bool)>) {
+        self.update_batch(batch, Update::Increment)
+    }
 
-        while i <= seq.len() - k {
-            let sub = seq.slice(i..i + k);
+    /// Updates the map from a batch as `update` says, leaving the batch empty,
+    /// hashing and locking as [`KmerMap::insert_batch`] does
+    fn update_batch(&self, batch: &mut Vec<(P, bool)>, update: Update) {
+        let mut hashed = batch
+            .drain(..)
+            .map(|(packed_bits, reversed)| {
+                let hash = self.counts.hash_usize(&packed_bits);
+                (
+                    self.counts.determine_shard(hash),
+                    hash as u64,
+                    packed_bits,
+                    reversed,
+                )
+            })
+            .collect::<Vec<_>>();
+        hashed.sort_unstable_by_key(|(shard, ..)| *shard);
 
-            match Kmer::from_sub(sub) {
-                Ok(mut kmer) => self.process_valid_bytes(&mut kmer),
-                Err(invalid_byte_index) => i += invalid_byte_index,
+        // Both maps share a hasher, so a key's hash and shard are the same in each
+        let maps = [Some(&self.counts), self.reverse.as_ref()];
+        for (map, reversed_only) in maps.into_iter().zip([false, true]) {
+            let Some(map) = map else { continue };
+
+            for group in hashed.chunk_by(|a, b| a.0 == b.0) {
+                let mut shard = map.shards()[group[0].0].write();
+                // Taken under the shard's lock, so never contended
+                let mut histogram = match reversed_only {
+                    true => None,
+                    false => self
+                        .histogram
+                        .as_ref()
+                        .map(|histogram| histogram.shard(group[0].0)),
+                };
+                for &(_, hash, packed_bits, reversed) in group {
+                    // Inserted uncounted into both maps, so a recount finds it in each
+                    if reversed_only && !reversed && update != Update::Insert {
+                        continue;
+                    }
+                    let entry = shard
+                        .raw_entry_mut()
+                        .from_key_hashed_nocheck(hash, &packed_bits);
+                    let new = || SharedValue::new(V::default());
+                    match update {
+                        Update::Increment => {
+                            let count = entry.or_insert(packed_bits, new()).1.get_mut();
+                            if let Some(histogram) = &mut histogram {
+                                histo::increment(histogram, count.count())
+                            }
+                            count.increment()
+                        }
+                        Update::Insert => _ = entry.or_insert(packed_bits, new()),
+                        Update::Recount => {
+                            entry.and_modify(|_, count| {
+                                if let Some(histogram) = &mut histogram {
+                                    histo::increment(histogram, count.get().count())
+                                }
+                                count.get_mut().increment()
+                            });
+                        }
+                    }
+                }
             }
+        }
+    }
+
+    fn output<W: Write + Send>(
+        self,
+        config: &Config,
+        elapsed: Option<Duration>,
+        out: W,
+    ) -> Result<(), ProcessError> {
+        let distinct_kmers = self.counts.len();
+        output(
+            self.stream(config).map(Ok),
+            Some(distinct_kmers),
+            config,
+            elapsed,
+            out,
+        )
+    }
+
+    /// Yields packed k-mers with their counts, leaving unpacking to the writer
+    /// so no `String` is held per k-mer
+    pub(crate) fn stream(self, config: &Config) -> impl Iterator<Item = Record<P>> {
+        let Self {
+            counts, reverse, ..
+        } = self;
+        // Tracked for `--bias-report` alone, so not output
+        let reverse = reverse.filter(|_| config.strand);
+        let kept = output_filter(config);
 
-            i += 1
+        counts
+            .into_iter()
+            .map(|(packed_bits, count)| (packed_bits, count.count()))
+            .filter(move |(packed_bits, count)| kept(*packed_bits, *count))
+            .map(move |(packed_bits, count)| {
+                let strand = reverse.as_ref().map(|reverse| {
+                    let reverse = reverse.get(&packed_bits).map_or(0, |count| count.count());
+                    StrandCounts {
+                        forward: count - reverse,
+                        reverse,
+                    }
+                });
+                (packed_bits, count, strand)
+            })
+    }
+}
+
+/// Whether a k-mer with a count is output, given `--report-palindromes`,
+/// `--lower-count` and `--upper-count`
+fn output_filter<P: Packed>(config: &Config) -> impl Fn(P, i32) -> bool {
+    let k = config.k;
+    let report_palindromes = config.report_palindromes;
+    let counts_kept = config.lower_count.unwrap_or(0)..=config.upper_count.unwrap_or(u64::MAX);
+
+    move |packed_bits, count| {
+        (!report_palindromes || kmer::is_palindrome(packed_bits, k))
+            && counts_kept.contains(&(count as u64))
+    }
+}
+
+/// How [`KmerMap::update_batch`] updates the map with each k-mer of a batch.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Update {
+    /// Counts it, adding it if new
+    Increment,
+    /// Adds it uncounted, if new
+    Insert,
+    /// Counts it only if already in the map
+    Recount,
+}
+
+/// Hands each sequence's pieces to `f`, in parallel unless there are few,
+/// recording each sequence's bases on `progress`
+fn for_each_sequence(
+    sequences: &[Sequence],
+    progress: Option<&ProgressTracker>,
+    f: impl Fn(&[Bytes]) + Sync,
+) {
+    let each = |(_, pieces): &Sequence| {
+        f(pieces);
+        if let Some(progress) = progress {
+            progress.record(pieces.iter().map(Bytes::len).sum())
         }
+    };
+
+    // Small inputs are counted on this thread, skipping the start-up of
+    // `rayon`'s pool, which can take longer than the counting itself
+    match bases(sequences) < SMALL_INPUT_BASES {
+        true => sequences.iter().for_each(each),
+        false => sequences.par_iter().for_each(each),
     }
+}
 
-    /// Convert a valid sequence substring from a bytes string to a u64
-    fn process_valid_bytes(&self, kmer: &mut Kmer) {
-        kmer.pack_bits();
+/// Bases across `sequences`
+fn bases<'a>(sequences: impl IntoIterator<Item = &'a Sequence>) -> usize {
+    sequences
+        .into_iter()
+        .flat_map(|(_, pieces)| pieces)
+        .map(Bytes::len)
+        .sum()
+}
 
-        // If the k-mer as found in the sequence is already a key in the `Dashmap`,
-        // increment its value and move on
-        if let Some(mut count) = self.0.get_mut(&kmer.packed_bits) {
-            *count += 1;
-        } else {
-            kmer.canonical();
+/// Hands the canonical k-mers of a sequence held in pieces to `f` in batches,
+/// as [`canonical_batches`] does, or only its minimizers given a
+/// `minimizer_window`
+pub(crate) fn kmer_batches<P: Packed>(
+    pieces: &[Bytes],
+    k: usize,
+    stride: usize,
+    minimizer_window: Option<usize>,
+    canonical: bool,
+    f: impl FnMut(&mut Vec<(P, bool)>),
+) {
+    match minimizer_window {
+        Some(w) => minimizer_batches(pieces, k, w, f),
+        None => window_batches(pieces, k, stride, canonical, f),
+    }
+}
 
-            if kmer.reverse_complement {
-                // Re-initialize packed bits
-                kmer.packed_bits = Default::default();
-                // Compress the canonical k-mer into a 64-bit unsigned integer
-                kmer.pack_bits();
-            }
+/// Hands the canonical minimizers of each `w` consecutive k-mers of a
+/// sequence held in pieces, read as if joined, to `f` in batches
+fn minimizer_batches<P: Packed>(
+    pieces: &[Bytes],
+    k: usize,
+    w: usize,
+    mut f: impl FnMut(&mut Vec<(P, bool)>),
+) {
+    let seq = reader::joined(pieces);
+    let mut batch = Vec::with_capacity(INSERT_BATCH_LEN);
 
-            self.log(kmer);
+    for (_, packed_bits, reversed) in MinimizerIterator::<P>::new(&seq, k, w) {
+        batch.push((packed_bits, reversed));
+        if batch.len() == INSERT_BATCH_LEN {
+            f(&mut batch)
         }
     }
 
-    fn log(&self, kmer: &Kmer) {
-        *self.0.entry(kmer.packed_bits).or_insert(0) += 1
+    f(&mut batch)
+}
+
+/// Hands the canonical k-mers of a sequence held in pieces, read as if the
+/// pieces were joined, to `f` in batches of `(canonical k-mer, found as its
+/// reverse complement)` pairs, which `f` should leave empty, or, unless
+/// `canonical`, the k-mers as read, none found reversed
+///
+/// # Notes
+/// K-mers within a piece are read in place. Those spanning pieces are read
+/// from a small buffer of the up to `k - 1` bases before the join and the
+/// up to `k - 1` after it, all of whose k-mers cross the join.
+fn window_batches<P: Packed>(
+    pieces: &[Bytes],
+    k: usize,
+    stride: usize,
+    canonical: bool,
+    mut f: impl FnMut(&mut Vec<(P, bool)>),
+) {
+    let mut tail = Vec::with_capacity(2 * k);
+    let mut offset = 0;
+
+    for piece in pieces {
+        if !tail.is_empty() {
+            let head = &piece[..piece.len().min(k - 1)];
+            let junction = [&tail[..], head].concat();
+            sequence_batches(
+                &Bytes::from(junction),
+                k,
+                offset - tail.len(),
+                stride,
+                canonical,
+                &mut f,
+            );
+        }
+        sequence_batches(piece, k, offset, stride, canonical, &mut f);
+        offset += piece.len();
+
+        tail.extend_from_slice(&piece[piece.len().saturating_sub(k - 1)..]);
+        tail.drain(..tail.len().saturating_sub(k - 1));
     }
+}
 
-    fn output(self, k: usize) -> Result<(), ProcessError> {
-        let mut buf = BufWriter::new(stdout());
+/// Ignore substrings containing `N`, given how far into its record `seq`
+/// starts, which places its windows for `--stride`
+///
+/// # Notes
+/// Canonicalizes by lexicographically smaller of k-mer/reverse-complement,
+/// which for packed k-mers is the smaller integer
+fn sequence_batches<P: Packed>(
+    seq: &Bytes,
+    k: usize,
+    offset: usize,
+    stride: usize,
+    canonical: bool,
+    f: &mut impl FnMut(&mut Vec<(P, bool)>),
+) {
+    let mut batch = Vec::with_capacity(INSERT_BATCH_LEN);
 
-        for (kmer, count) in self.stream(k) {
-            writeln!(buf, ">{count}\n{kmer}")?
+    let windows = kmer::stranded_kmers::<P>(seq, k)
+        .filter(|(start, ..)| (offset + start).is_multiple_of(stride));
+    for (_, packed_bits, reverse_complement) in windows {
+        batch.push(match canonical && reverse_complement < packed_bits {
+            true => (reverse_complement, true),
+            false => (packed_bits, false),
+        });
+        if batch.len() == INSERT_BATCH_LEN {
+            f(&mut batch)
         }
+    }
+
+    f(&mut batch)
+}
+
+/// Writes records from a dedicated thread, fed batches through a bounded
+/// queue, so producing them (and sorting) isn't held to the pace of a
+/// slow consumer and a stalled consumer can't make the queue balloon
+fn output<P: Packed, W: Write + Send>(
+    records: impl Iterator<Item = Result<Record<P>, IoError>>,
+    distinct_kmers: Option<usize>,
+    config: &Config,
+    elapsed: Option<Duration>,
+    out: W,
+) -> Result<(), ProcessError> {
+    let out = config.compression.encoder(out)?;
+    let mut out = RecordWriter::new(out, config);
+    if let Some(distinct_kmers) = distinct_kmers {
+        out = out.with_distinct_kmers(distinct_kmers)
+    }
+    let (batches, received) = mpsc::sync_channel(WRITE_QUEUE_LEN);
+
+    thread::scope(|scope| {
+        let written = scope.spawn(move || write_records(out, elapsed, received));
+        let produced = produce(records, config, batches);
 
-        buf.flush()?;
+        // A failed write ends production early, so report it first
+        written.join().expect("writer thread panicked")?;
+        produced?;
 
         Ok(())
+    })
+}
+
+/// Sends records to the writer in batches of [`WRITE_BATCH_LEN`]
+fn produce<'a, P: Packed>(
+    records: impl Iterator<Item = Result<Record<P>, IoError>> + 'a,
+    config: &Config,
+    batches: SyncSender<Vec<Record<P>>>,
+) -> Result<(), IoError> {
+    let records: Box<dyn Iterator<Item = Result<Record<P>, IoError>> + 'a> = match config.sort {
+        Some(order) => sort::sorted(records, order, config.strand, sort::SORT_CHUNK_LEN)?,
+        None => Box::new(records),
+    };
+
+    let mut batch = Vec::with_capacity(WRITE_BATCH_LEN);
+    for record in records {
+        batch.push(record?);
+        if batch.len() == WRITE_BATCH_LEN {
+            let full = mem::replace(&mut batch, Vec::with_capacity(WRITE_BATCH_LEN));
+            // The writer only hangs up after an error, which it reports
+            if batches.send(full).is_err() {
+                return Ok(());
+            }
+        }
     }
+    let _ = batches.send(batch);
 
-    fn stream(self, k: usize) -> IntoIter<String, i32> {
-        self.0
-            .into_iter()
-            .par_bridge()
-            .map(|(packed_bits, count)| Kmer {
-                packed_bits,
-                count,
-                ..Default::default()
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::output::OutputFormat;
+    use std::collections::HashMap;
+
+    fn unpacked(
+        stream: impl Iterator<Item = Record>,
+        k: usize,
+    ) -> HashMap<String, (i32, Option<StrandCounts>)> {
+        stream
+            .map(|(packed_bits, count, strand)| {
+                (kmer::unpack_to_string(packed_bits, k), (count, strand))
             })
-            .map(|mut kmer| {
-                kmer.unpack_bits(k);
-                (String::from_utf8(kmer.bytes.to_vec()).unwrap(), kmer.count)
+            .collect()
+    }
+
+    #[test]
+    fn strand_counts_split_canonical_occurrences() {
+        let map = KmerMap::<u64>::new(true);
+        map.process_pieces(&[Bytes::from_static(b"ACGTNACG")], &3);
+
+        let config = Config {
+            k: 3,
+            strand: true,
+            ..Default::default()
+        };
+        let counts = unpacked(map.stream(&config), 3);
+        let strand = |forward, reverse| Some(StrandCounts { forward, reverse });
+        assert_eq!(counts["ACG"], (3, strand(2, 1)));
+        assert_eq!(counts.len(), 1);
+    }
+
+    #[test]
+    fn pieces_count_as_if_joined() {
+        let seq = b"ACGTTGCAnnACGGTACCATGNACGTACGGTAAC";
+        let splits: &[&[usize]] = &[&[], &[1], &[3, 4, 5], &[10, 11, 30], &[2, 4, 6, 8]];
+
+        for (splits, stride) in splits.iter().flat_map(|splits| [(splits, 1), (splits, 3)]) {
+            let pieces = [0]
+                .iter()
+                .chain(splits.iter())
+                .zip(splits.iter().chain([seq.len()].iter()))
+                .map(|(&start, &end)| Bytes::copy_from_slice(&seq[start..end]))
+                .collect::<Vec<_>>();
+
+            let joined = KmerMap::<u64>::new(true).with_stride(stride);
+            joined.process_pieces(&[Bytes::from_static(seq)], &5);
+            let split = KmerMap::<u64>::new(true).with_stride(stride);
+            split.process_pieces(&pieces, &5);
+
+            let config = Config {
+                k: 5,
+                strand: true,
+                ..Default::default()
+            };
+            assert_eq!(
+                unpacked(split.stream(&config), 5),
+                unpacked(joined.stream(&config), 5),
+                "split at {splits:?}, stride {stride}"
+            );
+        }
+    }
+
+    #[test]
+    fn stride_skips_windows_but_not_canonicalization() {
+        let map = KmerMap::<u64>::new(false).with_stride(2);
+        // Windows AAC, CCC and CGT, the last counted as ACG
+        map.process_pieces(&[Bytes::from_static(b"AACCCGT")], &3);
+
+        let config = Config {
+            k: 3,
+            ..Default::default()
+        };
+        let mut counts = unpacked(map.stream(&config), 3)
+            .into_iter()
+            .map(|(kmer, (count, _))| (kmer, count))
+            .collect::<Vec<_>>();
+        counts.sort();
+        assert_eq!(
+            counts,
+            [("AAC".into(), 1), ("ACG".into(), 1), ("CCC".into(), 1)]
+        );
+    }
+
+    #[test]
+    fn minimizer_window_counts_each_minimizer_once() {
+        let seq = b"ACGTTGCAACGGTACCATGGACGTACGGTAACCATGCATTGACC";
+        let map = KmerMap::<u64>::new(false).with_minimizer_window(Some(4));
+        map.process_pieces(&[&seq[..20], &seq[20..]].map(Bytes::copy_from_slice), &5);
+
+        let mut expected = HashMap::<_, i32>::new();
+        for (_, packed_bits, _) in MinimizerIterator::<u64>::new(seq, 5, 4) {
+            *expected
+                .entry(kmer::unpack_to_string(packed_bits, 5))
+                .or_default() += 1
+        }
+        let config = Config {
+            k: 5,
+            ..Default::default()
+        };
+        let counts = unpacked(map.stream(&config), 5)
+            .into_iter()
+            .map(|(kmer, (count, _))| (kmer, count))
+            .collect::<HashMap<_, _>>();
+        assert_eq!(counts, expected);
+        assert!(counts.len() < seq.len() - 4);
+    }
+
+    #[test]
+    fn count_bounds_are_inclusive() {
+        let map = KmerMap::<u64>::new(false);
+        // AA and AC twice, CC three times, CG once
+        map.process_pieces(&[Bytes::from_static(b"AAACCCCGT")], &2);
+
+        let config = Config {
+            k: 2,
+            lower_count: Some(2),
+            upper_count: Some(2),
+            ..Default::default()
+        };
+        let mut kmers = unpacked(map.stream(&config), 2)
+            .into_keys()
+            .collect::<Vec<_>>();
+        kmers.sort();
+        assert_eq!(kmers, ["AA", "AC"]);
+    }
+
+    #[test]
+    fn counts_kmers_longer_than_32() {
+        let map = KmerMap::<u128>::new(true);
+        let kmer = b"ACGGTCAGTTCAGAGCTAGGCTTACCGATGCATTGACCAGTT";
+        let seq = [&kmer[..], b"N", &kmer[..]].concat();
+        map.process_pieces(&[Bytes::from(seq)], &40);
+
+        let config = Config {
+            k: 40,
+            strand: true,
+            ..Default::default()
+        };
+        let counts = map
+            .stream(&config)
+            .map(|(packed_bits, count, strand)| {
+                (kmer::unpack_to_string(packed_bits, 40), (count, strand))
             })
-            .collect::<HashMap<String, i32>>()
+            .collect::<HashMap<_, _>>();
+        let strand = Some(StrandCounts {
+            forward: 0,
+            reverse: 2,
+        });
+        assert_eq!(counts.len(), 3);
+        assert_eq!(
+            counts["AACTGGTCAATGCATCGGTAAGCCTAGCTCTGAACTGACC"],
+            (2, strand)
+        );
+    }
+
+    #[test]
+    fn without_canonicalizing_strands_count_apart() {
+        let map = KmerMap::<u64>::new(false).with_canonical(false);
+        // The second piece is the reverse complement of the first
+        map.process_pieces(
+            &[Bytes::from_static(b"AAAC"), Bytes::from_static(b"NGTTT")],
+            &3,
+        );
+
+        let config = Config {
+            k: 3,
+            no_canonical: true,
+            ..Default::default()
+        };
+        let mut counts = unpacked(map.stream(&config), 3)
             .into_iter()
+            .map(|(kmer, (count, _))| (kmer, count))
+            .collect::<Vec<_>>();
+        counts.sort();
+        assert_eq!(
+            counts,
+            [("AAA", 1), ("AAC", 1), ("GTT", 1), ("TTT", 1)]
+                .map(|(kmer, count)| (kmer.into(), count))
+        );
+    }
+
+    #[test]
+    fn distinct_sets_keep_kmers_without_counts() {
+        let map = KmerMap::<u64, ()>::new(false);
+        map.process_pieces(&[Bytes::from_static(b"AAACCCCGT")], &2);
+
+        let config = Config {
+            k: 2,
+            distinct: true,
+            ..Default::default()
+        };
+        let mut kmers = map
+            .stream(&config)
+            .map(|(packed_bits, count, _)| (kmer::unpack_to_string(packed_bits, 2), count))
+            .collect::<Vec<_>>();
+        kmers.sort();
+        assert_eq!(
+            kmers,
+            [("AA", 0), ("AC", 0), ("CC", 0), ("CG", 0)].map(|(kmer, count)| (kmer.into(), count))
+        );
+    }
+
+    #[test]
+    fn filtering_singletons_keeps_exact_counts_of_the_rest() {
+        let inputs = [
+            vec![(
+                "a".to_string(),
+                vec![Bytes::from_static(b"ACGTTGCAnnACGGTACC")],
+            )],
+            vec![(
+                "b".to_string(),
+                vec![Bytes::from_static(b"ATGNACGTTGCAACGGTA")],
+            )],
+        ];
+        let config = Config {
+            k: 5,
+            strand: true,
+            ..Default::default()
+        };
+
+        let all = KmerMap::<u64>::new(true);
+        all.count_inputs(&inputs, 5, None);
+        let mut expected = unpacked(all.stream(&config), 5);
+        expected.retain(|_, (count, _)| *count > 1);
+
+        let repeated = KmerMap::<u64>::new(true);
+        repeated.count_repeated(&inputs, 5, &Bloom::new(100), None);
+        assert_eq!(repeated.counts.len(), expected.len());
+        assert_eq!(unpacked(repeated.stream(&config), 5), expected);
+    }
+
+    #[test]
+    fn count_files_merges_inputs() {
+        let dir = tempfile::tempdir().unwrap();
+        let fasta = dir.path().join("a.fa");
+        let fastq = dir.path().join("b.fq");
+        std::fs::write(&fasta, ">a\nACGT\n").unwrap();
+        std::fs::write(&fastq, "@b\nACGA\n+\nIIII\n").unwrap();
+
+        let mut counts = count_files(&[fasta, fastq], 3).unwrap();
+        counts.sort();
+        assert_eq!(counts, [("ACG".into(), 3), ("CGA".into(), 1)]);
+    }
+
+    #[test]
+    fn sorted_counts_orders_by_kmer_or_count() {
+        let dir = tempfile::tempdir().unwrap();
+        let fasta = dir.path().join("a.fa");
+        std::fs::write(&fasta, ">a\nAAAAACGTCC\n").unwrap();
+
+        let counts = |order| {
+            sorted_counts(&[&fasta], 3, order)
+                .unwrap()
+                .into_iter()
+                .map(|(kmer, count)| format!("{kmer} {count}"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        insta::assert_snapshot!(counts(SortOrder::Kmer), @"AAA 3, AAC 1, ACG 2, GAC 1, GGA 1");
+        insta::assert_snapshot!(counts(SortOrder::Count), @"AAC 1, GAC 1, GGA 1, ACG 2, AAA 3");
+        insta::assert_snapshot!(counts(SortOrder::CountDesc), @"AAA 3, ACG 2, AAC 1, GAC 1, GGA 1");
+    }
+
+    #[test]
+    fn count_to_writer_writes_as_configured() {
+        let dir = tempfile::tempdir().unwrap();
+        let fasta = dir.path().join("a.fa");
+        std::fs::write(&fasta, ">a\nAAAAACGTCC\n").unwrap();
+        let config = Config {
+            k: 3,
+            paths: vec![fasta],
+            format: OutputFormat::Tsv,
+            sort: Some(SortOrder::CountDesc),
+            strand: true,
+            ..Default::default()
+        };
+
+        let mut out = Vec::new();
+        count_to_writer(&config, &mut out).unwrap();
+        insta::assert_snapshot!(String::from_utf8(out).unwrap(), @r###"
+        AAA	3	3	0
+        ACG	2	1	1
+        AAC	1	1	0
+        GAC	1	0	1
+        GGA	1	0	1
+        "###);
+    }
+
+    #[test]
+    #[cfg(feature = "spill")]
+    fn count_to_writer_writes_what_run_does() {
+        let dir = tempfile::tempdir().unwrap();
+        let fasta = dir.path().join("a.fa");
+        std::fs::write(&fasta, ">a\nAAAAACGTCCGGATTACA\n").unwrap();
+        let config = |output: Option<std::path::PathBuf>, max_memory: Option<usize>| Config {
+            k: 3,
+            paths: vec![fasta.clone()],
+            format: OutputFormat::Tsv,
+            sort: Some(SortOrder::Kmer),
+            stride: Some(2),
+            no_canonical: true,
+            max_memory,
+            output,
+            ..Default::default()
+        };
+
+        for max_memory in [None, Some(1 << 20)] {
+            let path = dir.path().join("run.tsv");
+            run(config(Some(path.clone()), max_memory)).unwrap();
+            let mut out = Vec::new();
+            count_to_writer(&config(None, max_memory), &mut out).unwrap();
+            assert_eq!(
+                String::from_utf8(out).unwrap(),
+                std::fs::read_to_string(&path).unwrap()
+            );
+        }
+
+        let mut out = Vec::new();
+        count_to_writer(&config(None, None), &mut out).unwrap();
+        // Every other k-mer, each as read
+        insta::assert_snapshot!(String::from_utf8(out).unwrap(), @r###"
+        AAA	2
+        ACG	1
+        ATT	1
+        CCG	1
+        GGA	1
+        GTC	1
+        TAC	1
+        "###);
+    }
+
+    #[test]
+    #[cfg(all(feature = "mmap", feature = "compression"))]
+    fn mmap_reads_compressed_and_fastq_inputs_as_without_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let gz = dir.path().join("a.fa.gz");
+        let mut gzipped = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        gzipped.write_all(b">a\nACGTTGCA\nACGGTACC\n").unwrap();
+        std::fs::write(&gz, gzipped.finish().unwrap()).unwrap();
+        let fq = dir.path().join("b.fq");
+        std::fs::write(&fq, "@b\nACGTTGCAACGGTACC\n+\nIIIIIIIIIIIIIIII\n").unwrap();
+
+        let written = |path: &std::path::PathBuf, mmap| {
+            let config = Config {
+                k: 5,
+                paths: vec![path.clone()],
+                format: OutputFormat::Tsv,
+                sort: Some(SortOrder::Kmer),
+                mmap,
+                ..Default::default()
+            };
+            let mut out = Vec::new();
+            count_to_writer(&config, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(written(&gz, true), written(&gz, false));
+        assert_eq!(written(&fq, true), written(&fq, false));
+        assert_eq!(written(&gz, true), written(&fq, true));
+    }
+
+    #[test]
+    fn mmap_still_trims_fastq_inputs() {
+        let dir = tempfile::tempdir().unwrap();
+        let fastq = dir.path().join("a.fq");
+        std::fs::write(&fastq, "@r\nACGTACGTAA\n+\nIIIIIIII##\n").unwrap();
+        let fasta = dir.path().join("b.fa");
+        std::fs::write(&fasta, ">b\nACGTAC\n").unwrap();
+        let config = |mmap| Config {
+            k: 3,
+            paths: vec![fastq.clone(), fasta.clone()],
+            format: OutputFormat::Tsv,
+            sort: Some(SortOrder::Kmer),
+            trim_quality: Some(20),
+            mmap,
+            ..Default::default()
+        };
+
+        let written = |mmap| {
+            let mut out = Vec::new();
+            count_to_writer(&config(mmap), &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(written(true), written(false));
+        insta::assert_snapshot!(written(true), @r###"
+        ACG	6
+        GTA	4
+        "###);
+    }
+
+    #[test]
+    fn online_histogram_matches_the_counts() {
+        let seq = (0..20_000u64)
+            .map(|i| b"ACGT"[(fxhash::hash64(&(i / 3)) % 4) as usize])
+            .collect::<Vec<_>>();
+        let pieces = seq
+            .chunks(100)
+            .map(Bytes::copy_from_slice)
+            .collect::<Vec<_>>();
+        let map = KmerMap::<u64>::new(false).with_histogram();
+        pieces
+            .par_iter()
+            .for_each(|piece| map.process_pieces(std::slice::from_ref(piece), &5));
+
+        let mut expected = BTreeMap::new();
+        for count in map.counts.iter().map(|entry| *entry.value()) {
+            *expected.entry(count).or_insert(0) += 1
+        }
+        assert_eq!(map.histogram().unwrap(), expected);
+        assert_eq!(expected.values().sum::<u64>(), map.counts.len() as u64);
+    }
+
+    #[test]
+    fn histogram_format_matches_the_written_counts() {
+        let dir = tempfile::tempdir().unwrap();
+        let fasta = dir.path().join("a.fa");
+        let seq = (0..5_000u64)
+            .map(|i| b"ACGT"[(fxhash::hash64(&(i / 3)) % 4) as usize] as char)
+            .collect::<String>();
+        std::fs::write(&fasta, format!(">a\n{seq}\n")).unwrap();
+
+        for filter_singletons in [false, true] {
+            let written = |format| {
+                let config = Config {
+                    k: 5,
+                    paths: vec![fasta.clone()],
+                    format,
+                    filter_singletons,
+                    ..Default::default()
+                };
+                let mut out = Vec::new();
+                count_to_writer(&config, &mut out).unwrap();
+                String::from_utf8(out).unwrap()
+            };
+
+            let mut expected = BTreeMap::<u64, u64>::new();
+            for line in written(OutputFormat::Tsv).lines() {
+                let count = line.split('\t').nth(1).unwrap().parse().unwrap();
+                *expected.entry(count).or_insert(0) += 1
+            }
+            let expected = expected
+                .iter()
+                .map(|(count, kmers)| format!("{count} {kmers}\n"))
+                .collect::<String>();
+            assert_eq!(written(OutputFormat::Histogram), expected);
+        }
+    }
+
+    #[test]
+    fn report_palindromes_keeps_only_palindromes() {
+        let map = KmerMap::<u64>::new(false);
+        map.process_pieces(&[Bytes::from_static(b"ACGTTA")], &4);
+
+        let config = Config {
+            k: 4,
+            report_palindromes: true,
+            ..Default::default()
+        };
+        let counts = unpacked(map.stream(&config), 4);
+        assert_eq!(counts.into_keys().collect::<Vec<_>>(), vec!["ACGT"]);
     }
 }
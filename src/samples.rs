@@ -0,0 +1,215 @@
+//! `--samples`: a sheet of `path`, `sample_name` and optional `group`
+//! columns, so multi-sample outputs are labelled by sample and group rather
+//! than by file path.
+
+use std::{
+    collections::HashSet,
+    fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use thiserror::Error;
+
+/// Why a sample sheet can't be used.
+#[derive(Debug, Error)]
+pub enum SampleSheetError {
+    #[error("Unable to read sample sheet: {0}")]
+    Read(#[from] io::Error),
+
+    /// `line` counts from 1
+    #[error("line {line} of the sample sheet needs a path and a sample name, tab-separated")]
+    Columns { line: usize },
+
+    #[error("sample {0} is named more than once in the sample sheet")]
+    DuplicateName(String),
+
+    #[error("{0} is listed more than once in the sample sheet")]
+    DuplicatePath(String),
+}
+
+/// One row of a sample sheet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sample {
+    pub path: PathBuf,
+    pub name: String,
+    pub group: Option<String>,
+}
+
+/// Samples in the order listed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SampleSheet {
+    pub samples: Vec<Sample>,
+}
+
+impl SampleSheet {
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, SampleSheetError> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    /// Every sample's path, to stand in for inputs not given otherwise
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.samples
+            .iter()
+            .map(|sample| sample.path.clone())
+            .collect()
+    }
+
+    /// The sample listed for `path`, as written or, failing that, as the
+    /// same file
+    pub fn get<P: AsRef<Path>>(&self, path: P) -> Option<&Sample> {
+        let path = path.as_ref();
+        self.samples
+            .iter()
+            .find(|sample| sample.path == path)
+            .or_else(|| {
+                let path = path.canonicalize().ok()?;
+                self.samples.iter().find(|sample| {
+                    sample
+                        .path
+                        .canonicalize()
+                        .is_ok_and(|listed| listed == path)
+                })
+            })
+    }
+
+    /// The group of the sample called `name`
+    pub fn group(&self, name: &str) -> Option<&str> {
+        self.samples
+            .iter()
+            .find(|sample| sample.name == name)
+            .and_then(|sample| sample.group.as_deref())
+    }
+
+    /// Whether any sample has a group
+    pub fn has_groups(&self) -> bool {
+        self.samples.iter().any(|sample| sample.group.is_some())
+    }
+
+    /// The group of each sample in `names`, if any sample has one
+    pub fn groups<S: AsRef<str>>(&self, names: &[S]) -> Option<Vec<Option<&str>>> {
+        self.has_groups()
+            .then(|| names.iter().map(|name| self.group(name.as_ref())).collect())
+    }
+}
+
+/// Parses tab-separated `path`, `sample_name`, `group` rows, skipping blank
+/// lines, `#` comments and a header row starting `path`. Paths are taken as
+/// written, relative to where `krust` runs.
+impl FromStr for SampleSheet {
+    type Err = SampleSheetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut samples = Vec::new();
+        let mut names = HashSet::new();
+        let mut paths = HashSet::new();
+
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut columns = line.split('\t').map(str::trim);
+            let (Some(path), Some(name)) = (columns.next(), columns.next()) else {
+                return Err(SampleSheetError::Columns { line: i + 1 });
+            };
+            if samples.is_empty() && path.eq_ignore_ascii_case("path") {
+                continue;
+            }
+            if path.is_empty() || name.is_empty() {
+                return Err(SampleSheetError::Columns { line: i + 1 });
+            }
+            if !names.insert(name) {
+                return Err(SampleSheetError::DuplicateName(name.into()));
+            }
+            if !paths.insert(path) {
+                return Err(SampleSheetError::DuplicatePath(path.into()));
+            }
+
+            samples.push(Sample {
+                path: path.into(),
+                name: name.into(),
+                group: columns
+                    .next()
+                    .filter(|group| !group.is_empty())
+                    .map(String::from),
+            });
+        }
+
+        Ok(Self { samples })
+    }
+}
+
+/// The name of the sample at `path`: as listed in `samples`, if given and
+/// listed, and otherwise its file name without extensions
+pub fn name_of<P: AsRef<Path>>(samples: Option<&SampleSheet>, path: P) -> String {
+    match samples.and_then(|samples| samples.get(&path)) {
+        Some(sample) => sample.name.clone(),
+        None => sample_name(path),
+    }
+}
+
+/// A sample's name: its file name without compression or sequence format
+/// extensions, e.g. `gut_1` for `reads/gut_1.fq.gz`
+pub fn sample_name<P: AsRef<Path>>(path: P) -> String {
+    let mut name = path
+        .as_ref()
+        .file_name()
+        .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+
+    for extensions in [
+        &[".gz", ".zst", ".bz2", ".xz"][..],
+        &[".fa", ".fasta", ".fna", ".fq", ".fastq"],
+    ] {
+        if let Some(stem) = extensions.iter().find_map(|ext| name.strip_suffix(ext)) {
+            name = stem.to_string()
+        }
+    }
+
+    name
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sheets_label_paths_with_names_and_groups() {
+        let sheet = "path\tsample_name\tgroup\n\
+            # controls first\n\
+            reads/c1.fq.gz\tcontrol_1\tcontrol\n\
+            \n\
+            reads/t1.fq.gz\ttreated_1\tcase\r\n\
+            reads/x.fq.gz\tunassigned\n"
+            .parse::<SampleSheet>()
+            .unwrap();
+
+        assert_eq!(
+            sheet.samples[1],
+            Sample {
+                path: "reads/t1.fq.gz".into(),
+                name: "treated_1".into(),
+                group: Some("case".into()),
+            }
+        );
+        assert_eq!(sheet.group("control_1"), Some("control"));
+        assert_eq!(sheet.group("unassigned"), None);
+        assert_eq!(name_of(Some(&sheet), "reads/c1.fq.gz"), "control_1");
+        assert_eq!(name_of(Some(&sheet), "reads/other.fq.gz"), "other");
+
+        let e = "a.fa\tx\nb.fa\tx\n".parse::<SampleSheet>().unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "sample x is named more than once in the sample sheet"
+        );
+        let e = "a.fa\n".parse::<SampleSheet>().unwrap_err();
+        assert!(e.to_string().starts_with("line 1 of"), "{e}");
+    }
+
+    #[test]
+    fn sample_names_drop_format_extensions() {
+        assert_eq!(sample_name("reads/gut_1.fq.gz"), "gut_1");
+        assert_eq!(sample_name("ref.v2.fasta"), "ref.v2");
+        assert_eq!(sample_name("sample"), "sample");
+    }
+}
@@ -0,0 +1,202 @@
+//! `krust set-op`: union, intersection and subtraction of two k-mer count
+//! tables written with `--format raw`, for contamination screening and
+//! differential k-mer analysis.
+
+use std::{
+    error::Error,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    path::Path,
+    str::FromStr,
+};
+
+use fxhash::FxHashMap;
+
+use super::{
+    kmer::Packed,
+    output::{RAW_MAGIC, RAW_VERSION},
+};
+
+/// How two count tables are combined.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SetOp {
+    /// Every k-mer in either table, its counts summed
+    Union,
+    /// The k-mers in both tables, each with the lesser count
+    Intersect,
+    /// The k-mers of the first table absent from the second, with their
+    /// counts
+    Subtract,
+}
+
+impl SetOp {
+    pub const VARIANTS: [&'static str; 3] = ["union", "intersect", "subtract"];
+}
+
+impl FromStr for SetOp {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "union" => Ok(Self::Union),
+            "intersect" => Ok(Self::Intersect),
+            "subtract" => Ok(Self::Subtract),
+            _ => Err(format!("Unknown set operation \"{s}\"")),
+        }
+    }
+}
+
+/// Combines raw count tables `a` and `b` by `op`, writing the result as a raw
+/// table sorted by packed k-mer
+pub fn combine<W: Write>(a: &Path, b: &Path, op: SetOp, out: W) -> Result<(), Box<dyn Error>> {
+    let (a, b) = (RawTable::open(a)?, RawTable::open(b)?);
+    if a.k != b.k {
+        return Err(format!(
+            "Count tables have different k: {} for {} and {} for {}",
+            a.k, a.name, b.k, b.name
+        )
+        .into());
+    }
+
+    match a.k <= u64::MAX_K {
+        true => combine_tables::<u64, W>(a, b, op, out),
+        false => combine_tables::<u128, W>(a, b, op, out),
+    }
+}
+
+fn combine_tables<P: Packed, W: Write>(
+    mut a: RawTable,
+    mut b: RawTable,
+    op: SetOp,
+    out: W,
+) -> Result<(), Box<dyn Error>> {
+    let mut b_counts = FxHashMap::<P, u64>::default();
+    while let Some((packed_bits, count)) = b.next()? {
+        *b_counts.entry(packed_bits).or_insert(0) += count
+    }
+
+    let mut combined = Vec::new();
+    while let Some((packed_bits, count)) = a.next::<P>()? {
+        let combined_count = match (op, b_counts.remove(&packed_bits)) {
+            (SetOp::Union, b_count) => Some(count + b_count.unwrap_or(0)),
+            (SetOp::Intersect, b_count) => b_count.map(|b_count| count.min(b_count)),
+            (SetOp::Subtract, b_count) => b_count.is_none().then_some(count),
+        };
+        combined.extend(combined_count.map(|count| (packed_bits, count)));
+    }
+    if op == SetOp::Union {
+        combined.extend(b_counts);
+    }
+    combined.sort_unstable();
+
+    let mut out = BufWriter::new(out);
+    out.write_all(RAW_MAGIC)?;
+    out.write_all(&[RAW_VERSION, a.k as u8, 0, 0])?;
+    for (packed_bits, count) in combined {
+        packed_bits.write_le(&mut out)?;
+        out.write_all(&count.to_le_bytes())?;
+    }
+    out.flush()?;
+
+    Ok(())
+}
+
+/// A raw count table being read, its header checked.
+struct RawTable {
+    name: String,
+    k: usize,
+    reader: BufReader<File>,
+}
+
+impl RawTable {
+    fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let name = path.display().to_string();
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut header = [0; 8];
+        reader.read_exact(&mut header)?;
+        if &header[..4] != RAW_MAGIC || header[4] != RAW_VERSION {
+            return Err(format!("{name} is not an uncompressed krust --format raw file").into());
+        }
+
+        Ok(Self {
+            name,
+            k: header[5] as usize,
+            reader,
+        })
+    }
+
+    /// The next (packed k-mer, count) pair, or `None` at the end, failing on
+    /// a truncated record
+    fn next<P: Packed>(&mut self) -> io::Result<Option<(P, u64)>> {
+        if self.reader.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+        let mut record = [0; 24];
+        let record = &mut record[..P::BYTES + 8];
+        self.reader.read_exact(record)?;
+        let count = u64::from_le_bytes(record[P::BYTES..].try_into().expect("8 bytes"));
+
+        Ok(Some((P::from_le_slice(&record[..P::BYTES]), count)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::kmer;
+
+    fn table(k: usize, counts: &[(&str, u64)]) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(RAW_MAGIC).unwrap();
+        file.write_all(&[RAW_VERSION, k as u8, 0, 0]).unwrap();
+        for (kmer, count) in counts {
+            let packed_bits = kmer::pack_many::<u64>(&[kmer], k)[0].unwrap();
+            packed_bits.write_le(&mut file).unwrap();
+            file.write_all(&count.to_le_bytes()).unwrap();
+        }
+        file
+    }
+
+    fn combined(a: &Path, b: &Path, op: SetOp) -> Vec<(String, u64)> {
+        let mut out = Vec::new();
+        combine(a, b, op, &mut out).unwrap();
+        out[8..]
+            .chunks(16)
+            .map(|record| {
+                let packed_bits = u64::from_le_slice(&record[..8]);
+                let count = u64::from_le_bytes(record[8..].try_into().unwrap());
+                (kmer::unpack_to_string(packed_bits, 3), count)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn combines_counts_by_operation() {
+        let a = table(3, &[("ACG", 4), ("AAA", 1), ("CCC", 2)]);
+        let b = table(3, &[("CCC", 5), ("ACG", 3), ("AAC", 7)]);
+        let owned = |pairs: &[(&str, u64)]| {
+            pairs
+                .iter()
+                .map(|(kmer, count)| (kmer.to_string(), *count))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            combined(a.path(), b.path(), SetOp::Union),
+            owned(&[("AAA", 1), ("AAC", 7), ("ACG", 7), ("CCC", 7)])
+        );
+        assert_eq!(
+            combined(a.path(), b.path(), SetOp::Intersect),
+            owned(&[("ACG", 3), ("CCC", 2)])
+        );
+        assert_eq!(
+            combined(a.path(), b.path(), SetOp::Subtract),
+            owned(&[("AAA", 1)])
+        );
+
+        let other_k = table(4, &[("ACGT", 1)]);
+        let mut out = Vec::new();
+        assert!(combine(a.path(), other_k.path(), SetOp::Union, &mut out).is_err());
+    }
+}
@@ -0,0 +1,448 @@
+//! Sketches as [sourmash](https://sourmash.readthedocs.io) signatures: JSON
+//! files holding one or more named MinHash sketches, optionally gzipped, or
+//! the same sketches in krust's compact binary `.ksh` format.
+
+use std::{
+    error::Error,
+    fs::File,
+    io::{self, BufReader, Read, Write},
+    path::Path,
+};
+
+use flate2::read::MultiGzDecoder;
+use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    samples::{self, SampleSheet},
+    sketch::{ScaledSketch, Sketch, SEED},
+};
+
+/// The only hash function krust sketches with, and so can compare.
+const HASH_FUNCTION: &str = "0.murmur64";
+
+/// Starts a binary sketch file, followed by [`KSH_VERSION`] and three zero
+/// bytes.
+pub const KSH_MAGIC: &[u8; 4] = b"KSHB";
+
+/// Version of the binary sketch format written.
+pub const KSH_VERSION: u8 = 1;
+
+/// A named sketch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub name: String,
+    /// The file sketched
+    pub filename: String,
+    pub minhash: MinHash,
+}
+
+/// The two kinds of sketch a signature can hold.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MinHash {
+    /// A bottom-k sketch, the `num` of sourmash
+    Num(Sketch),
+    Scaled(ScaledSketch),
+}
+
+impl MinHash {
+    pub fn k(&self) -> usize {
+        match self {
+            Self::Num(sketch) => sketch.k,
+            Self::Scaled(sketch) => sketch.k,
+        }
+    }
+
+    fn hashes(&self) -> &[u64] {
+        match self {
+            Self::Num(sketch) => &sketch.hashes,
+            Self::Scaled(sketch) => &sketch.hashes,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SignatureJson {
+    class: String,
+    #[serde(default)]
+    email: String,
+    hash_function: String,
+    #[serde(default)]
+    filename: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(default)]
+    license: String,
+    signatures: Vec<MinHashJson>,
+    version: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct MinHashJson {
+    num: usize,
+    ksize: usize,
+    seed: u64,
+    max_hash: u64,
+    mins: Vec<u64>,
+    md5sum: String,
+    molecule: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    abundances: Option<Vec<u64>>,
+}
+
+/// Whether `path` names a signature file, JSON or binary, rather than
+/// sequences, or a Mash sketch file, which [`read`] explains it can't read
+pub fn is_signature<P: AsRef<Path>>(path: P) -> bool {
+    let path = path.as_ref().to_string_lossy();
+    path.ends_with(".sig") || path.ends_with(".sig.gz") || is_binary(&*path) || is_mash(&*path)
+}
+
+/// Whether `path` names a krust binary sketch file
+pub fn is_binary<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref().extension().is_some_and(|ext| ext == "ksh")
+}
+
+/// Whether `path` names a sketch file of Mash, whose Cap'n Proto format
+/// krust doesn't read
+fn is_mash<P: AsRef<Path>>(path: P) -> bool {
+    path.as_ref().extension().is_some_and(|ext| ext == "msh")
+}
+
+/// Sketches each FASTA or FASTQ file in parallel, keeping the `num` smallest
+/// hashes if given and otherwise one in about `scaled`, naming each as
+/// `samples` lists it if given
+pub fn sketch<P: AsRef<Path> + Sync>(
+    paths: &[P],
+    k: usize,
+    num: Option<usize>,
+    scaled: u64,
+    samples: Option<&SampleSheet>,
+) -> Result<Vec<Signature>, Box<dyn Error>> {
+    let signatures = paths
+        .par_iter()
+        .map(|path| {
+            let minhash = match num {
+                Some(size) => Sketch::from_path(path, k, size).map(MinHash::Num),
+                None => ScaledSketch::from_path(path, k, scaled).map(MinHash::Scaled),
+            }
+            .map_err(|e| format!("{}: {e}", path.as_ref().display()))?;
+            Ok(Signature {
+                name: samples::name_of(samples, path),
+                filename: path.as_ref().display().to_string(),
+                minhash,
+            })
+        })
+        .collect::<Result<_, String>>()?;
+
+    Ok(signatures)
+}
+
+/// Reads every DNA sketch in a signature file, skipping those of other
+/// molecules or hash seeds, which can't be compared with krust's, or every
+/// sketch in a binary one
+pub fn read<P: AsRef<Path>>(path: P) -> Result<Vec<Signature>, Box<dyn Error>> {
+    if is_mash(&path) {
+        return Err(
+            "Mash's .msh sketches can't be read; sketch the sequences with krust sketch instead"
+                .into(),
+        );
+    }
+    let mut reader = BufReader::new(File::open(&path)?);
+    let mut text = Vec::new();
+    reader.read_to_end(&mut text)?;
+    if text.starts_with(&[0x1f, 0x8b]) {
+        let mut decoded = Vec::new();
+        MultiGzDecoder::new(&text[..]).read_to_end(&mut decoded)?;
+        text = decoded;
+    }
+    if text.starts_with(KSH_MAGIC) || is_binary(&path) {
+        return Ok(read_binary(&text)?);
+    }
+
+    let files: Vec<SignatureJson> = serde_json::from_slice(&text)?;
+    let signatures = files
+        .into_iter()
+        .filter(|file| file.hash_function == HASH_FUNCTION)
+        .flat_map(|file| {
+            let name = file
+                .name
+                .filter(|name| !name.is_empty())
+                .unwrap_or_else(|| match file.filename.as_str() {
+                    "" | "-" => samples::sample_name(&path),
+                    filename => samples::sample_name(filename),
+                });
+            let filename = file.filename;
+            file.signatures
+                .into_iter()
+                .filter(|minhash| minhash.molecule.eq_ignore_ascii_case("dna"))
+                .filter(|minhash| minhash.seed == SEED)
+                .map(move |minhash| Signature {
+                    name: name.clone(),
+                    filename: filename.clone(),
+                    minhash: from_json(minhash),
+                })
+        })
+        .collect();
+
+    Ok(signatures)
+}
+
+fn from_json(minhash: MinHashJson) -> MinHash {
+    let MinHashJson {
+        num,
+        ksize,
+        max_hash,
+        mut mins,
+        ..
+    } = minhash;
+    mins.sort_unstable();
+
+    match num {
+        0 => MinHash::Scaled(ScaledSketch {
+            k: ksize,
+            scaled: (u64::MAX as f64 / max_hash.max(1) as f64).round() as u64,
+            hashes: mins,
+        }),
+        size => MinHash::Num(Sketch {
+            k: ksize,
+            size,
+            hashes: mins,
+        }),
+    }
+}
+
+/// Writes signatures as a sourmash JSON list, one signature object each
+pub fn write<W: Write>(out: &mut W, signatures: &[Signature]) -> serde_json::Result<()> {
+    let files = signatures
+        .iter()
+        .map(|signature| SignatureJson {
+            class: "sourmash_signature".to_string(),
+            email: String::new(),
+            hash_function: HASH_FUNCTION.to_string(),
+            filename: signature.filename.clone(),
+            name: Some(signature.name.clone()),
+            license: "CC0".to_string(),
+            signatures: vec![to_json(&signature.minhash)],
+            version: 0.4,
+        })
+        .collect::<Vec<_>>();
+
+    serde_json::to_writer(out, &files)
+}
+
+/// Writes signatures in the binary format: the [`KSH_MAGIC`] header and
+/// signature count, then for each its name and file name, length-prefixed,
+/// its kind, `0` for bottom-k or `1` for scaled, k, and `num` or `scaled`,
+/// and its hashes, counted, in ascending order
+///
+/// # Notes
+/// Integers are little-endian, lengths and counts `u32` and the rest `u64`,
+/// so a sketch takes 8 bytes a hash where JSON takes about 20.
+pub fn write_binary<W: Write>(out: &mut W, signatures: &[Signature]) -> io::Result<()> {
+    out.write_all(KSH_MAGIC)?;
+    out.write_all(&[KSH_VERSION, 0, 0, 0])?;
+    out.write_all(&(signatures.len() as u32).to_le_bytes())?;
+
+    for signature in signatures {
+        for text in [&signature.name, &signature.filename] {
+            out.write_all(&(text.len() as u32).to_le_bytes())?;
+            out.write_all(text.as_bytes())?;
+        }
+        let (kind, num_or_scaled) = match &signature.minhash {
+            MinHash::Num(sketch) => (0u8, sketch.size as u64),
+            MinHash::Scaled(sketch) => (1, sketch.scaled),
+        };
+        out.write_all(&[kind])?;
+        out.write_all(&(signature.minhash.k() as u64).to_le_bytes())?;
+        out.write_all(&num_or_scaled.to_le_bytes())?;
+        let hashes = signature.minhash.hashes();
+        out.write_all(&(hashes.len() as u32).to_le_bytes())?;
+        for hash in hashes {
+            out.write_all(&hash.to_le_bytes())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads signatures written by [`write_binary`] from the file's bytes,
+/// failing on a bad header or a truncated sketch
+///
+/// # Notes
+/// Every length is checked against the bytes left before anything is
+/// allocated for it, so a damaged file can't ask for more memory than it
+/// takes itself.
+fn read_binary(mut input: &[u8]) -> io::Result<Vec<Signature>> {
+    let input = &mut input;
+    let header = take(input, 8)
+        .ok()
+        .filter(|header| &header[..4] == KSH_MAGIC && header[4] == KSH_VERSION);
+    if header.is_none() {
+        return Err(invalid("not a krust binary sketch file of a known version"));
+    }
+
+    (0..read_u32(input)?)
+        .map(|_| {
+            let name = read_text(input)?;
+            let filename = read_text(input)?;
+            let kind = take(input, 1)?[0];
+            let k = read_u64(input)? as usize;
+            let num_or_scaled = read_u64(input)?;
+            let len = (read_u32(input)? as usize).checked_mul(8);
+            let hashes = take(input, len.unwrap_or(usize::MAX))?
+                .chunks_exact(8)
+                .map(|hash| u64::from_le_bytes(hash.try_into().expect("8 bytes")))
+                .collect();
+
+            let minhash = match kind {
+                0 => MinHash::Num(Sketch {
+                    k,
+                    size: num_or_scaled as usize,
+                    hashes,
+                }),
+                1 => MinHash::Scaled(ScaledSketch {
+                    k,
+                    scaled: num_or_scaled,
+                    hashes,
+                }),
+                _ => return Err(invalid("unknown kind of sketch")),
+            };
+            Ok(Signature {
+                name,
+                filename,
+                minhash,
+            })
+        })
+        .collect()
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// The next `len` bytes of `input`, failing if fewer are left
+fn take<'a>(input: &mut &'a [u8], len: usize) -> io::Result<&'a [u8]> {
+    if len > input.len() {
+        return Err(invalid("binary sketch file ends early"));
+    }
+    let (taken, rest) = input.split_at(len);
+    *input = rest;
+
+    Ok(taken)
+}
+
+fn read_u32(input: &mut &[u8]) -> io::Result<u32> {
+    Ok(u32::from_le_bytes(
+        take(input, 4)?.try_into().expect("4 bytes"),
+    ))
+}
+
+fn read_u64(input: &mut &[u8]) -> io::Result<u64> {
+    Ok(u64::from_le_bytes(
+        take(input, 8)?.try_into().expect("8 bytes"),
+    ))
+}
+
+/// A `u32` length, then that many bytes of UTF-8
+fn read_text(input: &mut &[u8]) -> io::Result<String> {
+    let len = read_u32(input)? as usize;
+    String::from_utf8(take(input, len)?.to_vec()).map_err(|e| invalid(&e.to_string()))
+}
+
+fn to_json(minhash: &MinHash) -> MinHashJson {
+    let (num, max_hash) = match minhash {
+        MinHash::Num(sketch) => (sketch.size, 0),
+        MinHash::Scaled(sketch) => (0, sketch.max_hash()),
+    };
+
+    MinHashJson {
+        num,
+        ksize: minhash.k(),
+        seed: SEED,
+        max_hash,
+        mins: minhash.hashes().to_vec(),
+        md5sum: md5sum(minhash),
+        molecule: "DNA".to_string(),
+        abundances: None,
+    }
+}
+
+/// The checksum sourmash identifies a sketch by: MD5 of its k, then each
+/// hash, as decimal text
+fn md5sum(minhash: &MinHash) -> String {
+    let mut context = md5::Context::new();
+    context.consume(minhash.k().to_string());
+    for hash in minhash.hashes() {
+        context.consume(hash.to_string());
+    }
+    format!("{:x}", context.compute())
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+
+    use super::*;
+
+    #[test]
+    fn signatures_survive_a_round_trip() {
+        let seqs = [Bytes::from_static(
+            b"ACGGTCAGTTCAGAGCTAGGCTTACCGATGCATTGACC",
+        )];
+        let signatures = vec![
+            Signature {
+                name: "a".to_string(),
+                filename: "a.fa".to_string(),
+                minhash: MinHash::Num(Sketch::from_seqs(&seqs, 5, 10)),
+            },
+            Signature {
+                name: "b".to_string(),
+                filename: "b.fa".to_string(),
+                minhash: MinHash::Scaled(ScaledSketch::from_seqs(&seqs, 5, 2)),
+            },
+        ];
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ab.sig");
+        write(&mut File::create(&path).unwrap(), &signatures).unwrap();
+        assert_eq!(read(&path).unwrap(), signatures);
+
+        let path = dir.path().join("ab.ksh");
+        write_binary(&mut File::create(&path).unwrap(), &signatures).unwrap();
+        assert!(is_signature(&path));
+        assert_eq!(read(&path).unwrap(), signatures);
+
+        // A count of hashes far past the end of the file
+        let mut damaged = std::fs::read(&path).unwrap();
+        let count = damaged.len() - 8 * signatures[1].minhash.hashes().len() - 4;
+        damaged[count..count + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        std::fs::write(&path, &damaged).unwrap();
+        let e = read(&path).unwrap_err();
+        assert_eq!(e.to_string(), "binary sketch file ends early");
+    }
+
+    #[test]
+    fn reads_sourmash_signatures() {
+        let json = r#"[{"class":"sourmash_signature","email":"","hash_function":"0.murmur64",
+            "filename":"reads/gut_1.fq.gz","license":"CC0","signatures":[
+            {"num":0,"ksize":31,"seed":42,"max_hash":18446744073709552,
+             "mins":[9007199254740991,1152921504606846],"md5sum":"x","molecule":"DNA"},
+            {"num":0,"ksize":10,"seed":42,"max_hash":18446744073709552,
+             "mins":[1],"md5sum":"x","molecule":"protein"}],"version":0.4}]"#;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("gut_1.sig");
+        std::fs::write(&path, json).unwrap();
+
+        let signatures = read(&path).unwrap();
+        assert_eq!(signatures.len(), 1);
+        assert_eq!(signatures[0].name, "gut_1");
+        assert_eq!(
+            signatures[0].minhash,
+            MinHash::Scaled(ScaledSketch {
+                k: 31,
+                scaled: 1000,
+                hashes: vec![1152921504606846, 9007199254740991],
+            })
+        );
+    }
+}
@@ -0,0 +1,686 @@
+//! Bottom-k and scaled MinHash sketches of canonical k-mers, and the
+//! distances and trees built from them.
+
+use std::{
+    collections::BTreeSet,
+    error::Error,
+    io::{self, Write},
+    path::Path,
+};
+
+use bytes::Bytes;
+use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+
+use super::{
+    kmer::{self, KmerByte},
+    samples::{self, SampleSheet},
+    signature::{self, MinHash},
+};
+
+/// Seed of [`hash`], the one sourmash and Mash use by default.
+pub const SEED: u64 = 42;
+
+/// Hashes a packed canonical k-mer as sourmash and Mash do: the first 64 bits
+/// of MurmurHash3 (x64, 128-bit) of its bases, seeded with [`SEED`], so
+/// sketches are comparable with theirs
+pub fn hash(packed_bits: u64, k: usize) -> u64 {
+    let mut kmer = [0; 32];
+    for (i, base) in kmer[..k].iter_mut().enumerate() {
+        *base = u8::from(KmerByte::from(packed_bits >> (2 * (k - 1 - i)) & 0b11));
+    }
+    murmur64(&kmer[..k], SEED)
+}
+
+/// The first 64 bits of MurmurHash3's x64, 128-bit variant
+fn murmur64(data: &[u8], seed: u64) -> u64 {
+    const C1: u64 = 0x87c3_7b91_1142_53d5;
+    const C2: u64 = 0x4cf5_ad43_2745_937f;
+    let (mut h1, mut h2) = (seed, seed);
+
+    let mut blocks = data.chunks_exact(16);
+    for block in &mut blocks {
+        let (k1, k2) = block.split_at(8);
+        let k1 = u64::from_le_bytes(k1.try_into().expect("8 bytes"));
+        let k2 = u64::from_le_bytes(k2.try_into().expect("8 bytes"));
+        h1 ^= k1.wrapping_mul(C1).rotate_left(31).wrapping_mul(C2);
+        h1 = h1
+            .rotate_left(27)
+            .wrapping_add(h2)
+            .wrapping_mul(5)
+            .wrapping_add(0x52dc_e729);
+        h2 ^= k2.wrapping_mul(C2).rotate_left(33).wrapping_mul(C1);
+        h2 = h2
+            .rotate_left(31)
+            .wrapping_add(h1)
+            .wrapping_mul(5)
+            .wrapping_add(0x3849_5ab5);
+    }
+
+    let tail = blocks.remainder();
+    let word = |bytes: &[u8]| {
+        bytes
+            .iter()
+            .rev()
+            .fold(0, |word, byte| word << 8 | *byte as u64)
+    };
+    if tail.len() > 8 {
+        h2 ^= word(&tail[8..])
+            .wrapping_mul(C2)
+            .rotate_left(33)
+            .wrapping_mul(C1);
+    }
+    if !tail.is_empty() {
+        h1 ^= word(&tail[..tail.len().min(8)])
+            .wrapping_mul(C1)
+            .rotate_left(31)
+            .wrapping_mul(C2);
+    }
+
+    let fmix = |mut h: u64| {
+        h ^= h >> 33;
+        h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+        h ^= h >> 33;
+        h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+        h ^ (h >> 33)
+    };
+    h1 ^= data.len() as u64;
+    h2 ^= data.len() as u64;
+    h1 = h1.wrapping_add(h2);
+    h2 = h2.wrapping_add(h1);
+    fmix(h1).wrapping_add(fmix(h2))
+}
+
+/// The `size` smallest canonical k-mer hashes of a sample.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sketch {
+    pub k: usize,
+    pub size: usize,
+    /// Ascending
+    pub hashes: Vec<u64>,
+}
+
+impl Sketch {
+    /// Sketches every record of a FASTA or FASTQ file, optionally compressed
+    pub fn from_path<P: AsRef<Path>>(
+        path: P,
+        k: usize,
+        size: usize,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut reader = needletail::parse_fastx_file(path)?;
+        let mut smallest = BTreeSet::new();
+
+        while let Some(record) = reader.next() {
+            let seq = Bytes::from(record?.seq().to_ascii_uppercase());
+            insert(&mut smallest, &seq, k, size)
+        }
+
+        Ok(Self::from_smallest(smallest, k, size))
+    }
+
+    /// Sketches the given sequences, which must be uppercase
+    pub fn from_seqs<'a, I: IntoIterator<Item = &'a Bytes>>(
+        seqs: I,
+        k: usize,
+        size: usize,
+    ) -> Self {
+        let mut smallest = BTreeSet::new();
+        for seq in seqs {
+            insert(&mut smallest, seq, k, size)
+        }
+
+        Self::from_smallest(smallest, k, size)
+    }
+
+    fn from_smallest(smallest: BTreeSet<u64>, k: usize, size: usize) -> Self {
+        Self {
+            k,
+            size,
+            hashes: smallest.into_iter().collect(),
+        }
+    }
+
+    /// Estimated Jaccard index of the two samples' k-mer sets
+    ///
+    /// # Notes
+    /// The shared fraction of the `size` smallest hashes of the union, which
+    /// is itself a bottom-k sketch of the union.
+    pub fn jaccard(&self, other: &Self) -> f64 {
+        match self.shared_of_union(other) {
+            (_, 0) => 0.0,
+            (shared, union) => shared as f64 / union as f64,
+        }
+    }
+
+    /// How many of the smallest hashes of the union, up to the smaller
+    /// `size`, the two sketches share, and how many there are
+    pub fn shared_of_union(&self, other: &Self) -> (usize, usize) {
+        let size = self.size.min(other.size);
+        let (mut a, mut b) = (
+            self.hashes.iter().peekable(),
+            other.hashes.iter().peekable(),
+        );
+        let (mut union, mut shared) = (0, 0);
+
+        while union < size {
+            match (a.peek(), b.peek()) {
+                (Some(x), Some(y)) if x == y => {
+                    shared += 1;
+                    a.next();
+                    b.next();
+                }
+                (Some(x), Some(y)) if x < y => {
+                    a.next();
+                }
+                (Some(_), Some(_)) => {
+                    b.next();
+                }
+                (Some(_), None) => {
+                    a.next();
+                }
+                (None, Some(_)) => {
+                    b.next();
+                }
+                (None, None) => break,
+            }
+            union += 1;
+        }
+
+        (shared, union)
+    }
+
+    /// Mash distance, an estimate of the per-base mutation rate between the
+    /// samples, from 0 for identical k-mer sets to 1 for disjoint ones
+    pub fn distance(&self, other: &Self) -> f64 {
+        let jaccard = self.jaccard(other);
+        match jaccard {
+            0.0 => 1.0,
+            1.0 => 0.0,
+            _ => (-(2.0 * jaccard / (1.0 + jaccard)).ln() / self.k as f64).min(1.0),
+        }
+    }
+
+    /// Estimated fraction of this sample's k-mers also in `other`
+    ///
+    /// # Notes
+    /// Only hashes no greater than the larger of the two sketches' maxima can
+    /// be compared, so those of this sketch above `other`'s are left out.
+    pub fn containment(&self, other: &Self) -> f64 {
+        let Some(&max) = other.hashes.last() else {
+            return 0.0;
+        };
+        let comparable = self.hashes.iter().filter(|hash| **hash <= max);
+        let (mut total, mut shared) = (0, 0);
+        for hash in comparable {
+            total += 1;
+            shared += other.hashes.binary_search(hash).is_ok() as usize;
+        }
+
+        match total {
+            0 => 0.0,
+            _ => shared as f64 / total as f64,
+        }
+    }
+}
+
+/// Every canonical k-mer hash of a sample in the lowest `1 / scaled` of the
+/// range, a FracMinHash sketch.
+///
+/// # Notes
+/// Unlike a bottom-k [`Sketch`], the share of k-mers kept doesn't depend on
+/// the sample's size, so sketches of a metagenome and of a small reference
+/// can be compared directly, by containment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScaledSketch {
+    pub k: usize,
+    pub scaled: u64,
+    /// Ascending
+    pub hashes: Vec<u64>,
+}
+
+impl ScaledSketch {
+    /// Sketches every record of a FASTA or FASTQ file, optionally compressed
+    pub fn from_path<P: AsRef<Path>>(
+        path: P,
+        k: usize,
+        scaled: u64,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut reader = needletail::parse_fastx_file(path)?;
+        let mut hashes = BTreeSet::new();
+
+        while let Some(record) = reader.next() {
+            let seq = Bytes::from(record?.seq().to_ascii_uppercase());
+            insert_scaled(&mut hashes, &seq, k, scaled)
+        }
+
+        Ok(Self::from_hashes(hashes, k, scaled))
+    }
+
+    /// Sketches the given sequences, which must be uppercase
+    pub fn from_seqs<'a, I: IntoIterator<Item = &'a Bytes>>(
+        seqs: I,
+        k: usize,
+        scaled: u64,
+    ) -> Self {
+        let mut hashes = BTreeSet::new();
+        for seq in seqs {
+            insert_scaled(&mut hashes, seq, k, scaled)
+        }
+
+        Self::from_hashes(hashes, k, scaled)
+    }
+
+    fn from_hashes(hashes: BTreeSet<u64>, k: usize, scaled: u64) -> Self {
+        Self {
+            k,
+            scaled,
+            hashes: hashes.into_iter().collect(),
+        }
+    }
+
+    /// The largest hash kept
+    pub fn max_hash(&self) -> u64 {
+        max_hash(self.scaled)
+    }
+
+    /// This sketch at a larger `scaled`, keeping only the hashes a sketch
+    /// made at that `scaled` would have
+    pub fn downsample(&self, scaled: u64) -> Self {
+        let max = max_hash(scaled);
+        Self {
+            k: self.k,
+            scaled: scaled.max(self.scaled),
+            hashes: self
+                .hashes
+                .iter()
+                .copied()
+                .take_while(|hash| *hash <= max)
+                .collect(),
+        }
+    }
+
+    /// Number of hashes this sketch shares with `other`
+    pub fn shared(&self, other: &Self) -> usize {
+        let (mut a, mut b) = (
+            self.hashes.iter().peekable(),
+            other.hashes.iter().peekable(),
+        );
+        let mut shared = 0;
+        while let (Some(x), Some(y)) = (a.peek(), b.peek()) {
+            match x.cmp(y) {
+                std::cmp::Ordering::Less => {
+                    a.next();
+                }
+                std::cmp::Ordering::Greater => {
+                    b.next();
+                }
+                std::cmp::Ordering::Equal => {
+                    shared += 1;
+                    a.next();
+                    b.next();
+                }
+            }
+        }
+
+        shared
+    }
+
+    /// Estimated fraction of this sample's k-mers also in `other`
+    pub fn containment(&self, other: &Self) -> f64 {
+        match self.hashes.len() {
+            0 => 0.0,
+            len => self.shared(other) as f64 / len as f64,
+        }
+    }
+}
+
+/// The largest hash a sketch keeps at `scaled`, computed as sourmash does
+pub fn max_hash(scaled: u64) -> u64 {
+    (u64::MAX as f64 / scaled.max(1) as f64) as u64
+}
+
+/// Adds `seq`'s k-mer hashes at or below the `scaled` threshold to `hashes`
+fn insert_scaled(hashes: &mut BTreeSet<u64>, seq: &Bytes, k: usize, scaled: u64) {
+    let max = max_hash(scaled);
+    hashes.extend(
+        kmer::canonical_kmers(seq, k)
+            .map(|packed_bits| hash(packed_bits, k))
+            .filter(|hash| *hash <= max),
+    );
+}
+
+/// Adds `seq`'s k-mer hashes to `smallest`, keeping only the `size` smallest
+fn insert(smallest: &mut BTreeSet<u64>, seq: &Bytes, k: usize, size: usize) {
+    for packed_bits in kmer::canonical_kmers(seq, k) {
+        let hash = hash(packed_bits, k);
+        if smallest.len() < size {
+            smallest.insert(hash);
+        } else if smallest.last().is_some_and(|max| hash < *max) && smallest.insert(hash) {
+            smallest.pop_last();
+        }
+    }
+}
+
+/// Row-major distances between samples.
+pub type DistanceMatrix = Vec<Vec<f64>>;
+
+/// Sketches a FASTA or FASTQ file, or reads the bottom-k sketches at `k` from
+/// a sourmash or binary signature file, naming each as `samples` lists it if given
+///
+/// # Notes
+/// A signature file's own names are kept unless it holds a single sketch at
+/// `k` and `samples` lists the file.
+fn load<P: AsRef<Path>>(
+    path: P,
+    k: usize,
+    size: usize,
+    samples: Option<&SampleSheet>,
+) -> Result<Vec<(String, Sketch)>, Box<dyn Error>> {
+    if !signature::is_signature(&path) {
+        return Ok(vec![(
+            samples::name_of(samples, &path),
+            Sketch::from_path(&path, k, size)?,
+        )]);
+    }
+
+    let mut sketches = signature::read(&path)?
+        .into_iter()
+        .filter_map(|signature| match signature.minhash {
+            MinHash::Num(sketch) if sketch.k == k => Some((signature.name, sketch)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    if let (Some(sample), [(name, _)]) = (
+        samples.and_then(|samples| samples.get(&path)),
+        &mut sketches[..],
+    ) {
+        *name = sample.name.clone()
+    }
+    match sketches.is_empty() {
+        true => Err(format!("no bottom-k sketch with k = {k}").into()),
+        false => Ok(sketches),
+    }
+}
+
+/// How far apart two sketched samples are.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Distance {
+    pub a: String,
+    pub b: String,
+    /// Mash distance
+    pub distance: f64,
+    pub jaccard: f64,
+    /// Hashes shared of the smallest of the union compared
+    pub shared: usize,
+    pub union: usize,
+}
+
+/// Sketches, or reads the bottom-k sketches at `k` of, the samples at `a`
+/// and `b`, and compares each of `a`'s with each of `b`'s
+pub fn dist<P: AsRef<Path> + Sync>(
+    a: P,
+    b: P,
+    k: usize,
+    size: usize,
+) -> Result<Vec<Distance>, Box<dyn Error>> {
+    let [a, b] = [a, b].map(|path| {
+        load(&path, k, size, None).map_err(|e| format!("{}: {e}", path.as_ref().display()))
+    });
+    let (a, b) = (a?, b?);
+
+    Ok(a.iter()
+        .flat_map(|(a, sketch_a)| {
+            b.iter().map(move |(b, sketch_b)| {
+                let (shared, union) = sketch_a.shared_of_union(sketch_b);
+                Distance {
+                    a: a.clone(),
+                    b: b.clone(),
+                    distance: sketch_a.distance(sketch_b),
+                    jaccard: sketch_a.jaccard(sketch_b),
+                    shared,
+                    union,
+                }
+            })
+        })
+        .collect())
+}
+
+/// Writes distances as tab-separated lines, as Mash does: the samples, the
+/// distance, the Jaccard index and the hashes shared, as `shared/union`
+pub fn write_distances<W: Write>(out: &mut W, distances: &[Distance]) -> io::Result<()> {
+    for distance in distances {
+        writeln!(
+            out,
+            "{}\t{}\t{:.6}\t{:.6}\t{}/{}",
+            distance.a,
+            distance.b,
+            distance.distance,
+            distance.jaccard,
+            distance.shared,
+            distance.union
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Sketches every sample in parallel, returning their names, from `samples`
+/// if given, and the all-vs-all Mash distances
+pub fn distance_matrix<P: AsRef<Path> + Sync>(
+    paths: &[P],
+    k: usize,
+    size: usize,
+    samples: Option<&SampleSheet>,
+) -> Result<(Vec<String>, DistanceMatrix), Box<dyn Error>> {
+    let samples = paths
+        .par_iter()
+        .map(|path| {
+            load(path, k, size, samples).map_err(|e| format!("{}: {e}", path.as_ref().display()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let (names, sketches): (Vec<_>, Vec<_>) = samples.into_iter().flatten().unzip();
+
+    let distances = sketches
+        .par_iter()
+        .map(|a| sketches.iter().map(|b| a.distance(b)).collect())
+        .collect();
+
+    Ok((names, distances))
+}
+
+/// Writes a square tab-separated matrix with a header row of names, and,
+/// given `groups`, a `group` column after the names, `NA` for samples
+/// without one
+pub fn write_matrix<W: Write>(
+    out: &mut W,
+    names: &[String],
+    groups: Option<&[Option<&str>]>,
+    distances: &[Vec<f64>],
+) -> io::Result<()> {
+    let group_header = match groups {
+        Some(_) => "\tgroup",
+        None => "",
+    };
+    writeln!(out, "sample{group_header}\t{}", names.join("\t"))?;
+    for (i, (name, row)) in names.iter().zip(distances).enumerate() {
+        write!(out, "{name}")?;
+        if let Some(groups) = groups {
+            write!(out, "\t{}", groups[i].unwrap_or("NA"))?;
+        }
+        for distance in row {
+            write!(out, "\t{distance:.6}")?;
+        }
+        writeln!(out)?;
+    }
+
+    Ok(())
+}
+
+/// Neighbour-joining tree of `names` in Newick format, from a symmetric
+/// distance matrix
+pub fn neighbor_joining(names: &[String], distances: &[Vec<f64>]) -> String {
+    let mut nodes = names
+        .iter()
+        .map(|name| newick_label(name))
+        .collect::<Vec<_>>();
+    let mut d = distances.to_vec();
+
+    while nodes.len() > 3 {
+        let n = nodes.len();
+        let sums = d
+            .iter()
+            .map(|row| row.iter().sum::<f64>())
+            .collect::<Vec<_>>();
+
+        // The pair minimizing the Q-criterion
+        let (mut i, mut j, mut best) = (0, 1, f64::INFINITY);
+        for a in 0..n {
+            for b in a + 1..n {
+                let q = (n - 2) as f64 * d[a][b] - sums[a] - sums[b];
+                if q < best {
+                    (i, j, best) = (a, b, q);
+                }
+            }
+        }
+
+        let to_i = (d[i][j] / 2.0 + (sums[i] - sums[j]) / (2.0 * (n - 2) as f64)).max(0.0);
+        let to_j = (d[i][j] - to_i).max(0.0);
+        let joined = format!("({}:{to_i:.6},{}:{to_j:.6})", nodes[i], nodes[j]);
+
+        let to_joined = (0..n)
+            .map(|m| (d[i][m] + d[j][m] - d[i][j]) / 2.0)
+            .collect::<Vec<_>>();
+
+        // Replace i with the new node, then drop j (j > i)
+        nodes[i] = joined;
+        for m in 0..n {
+            d[i][m] = to_joined[m];
+            d[m][i] = to_joined[m];
+        }
+        d[i][i] = 0.0;
+        nodes.remove(j);
+        d.remove(j);
+        for row in &mut d {
+            row.remove(j);
+        }
+    }
+
+    match nodes.as_slice() {
+        [a, b, c] => {
+            let to_a = ((d[0][1] + d[0][2] - d[1][2]) / 2.0).max(0.0);
+            let to_b = ((d[0][1] + d[1][2] - d[0][2]) / 2.0).max(0.0);
+            let to_c = ((d[0][2] + d[1][2] - d[0][1]) / 2.0).max(0.0);
+            format!("({a}:{to_a:.6},{b}:{to_b:.6},{c}:{to_c:.6});")
+        }
+        [a, b] => format!("({a}:{:.6},{b}:0.000000);", d[0][1]),
+        [a] => format!("{a};"),
+        _ => ";".to_string(),
+    }
+}
+
+/// Quotes Newick labels containing characters with meaning in the format
+fn newick_label(name: &str) -> String {
+    match name.contains(|c: char| "()[]':;,".contains(c) || c.is_whitespace()) {
+        true => format!("'{}'", name.replace('\'', "''")),
+        false => name.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sketch(seqs: &[&'static [u8]], size: usize) -> Sketch {
+        let seqs = seqs
+            .iter()
+            .map(|seq| Bytes::from_static(seq))
+            .collect::<Vec<_>>();
+        Sketch::from_seqs(&seqs, 5, size)
+    }
+
+    #[test]
+    fn hashes_match_sourmash() {
+        // sourmash's `hash_murmur("ACG", 42)`
+        assert_eq!(hash(0b00_01_10, 3), 1731421407650554201);
+        assert_eq!(murmur64(b"ACG", SEED), 1731421407650554201);
+    }
+
+    #[test]
+    fn identical_samples_are_at_distance_zero() {
+        let a = sketch(&[b"ACGGTCAGTTCAGAGCTAGGCTTACC"], 100);
+        let b = sketch(&[b"GGTAAGCCTAGCTCTGAACTGACCGT"], 100);
+        assert_eq!(a.hashes, b.hashes, "reverse complements share k-mers");
+        assert_eq!(a.distance(&b), 0.0);
+        assert_eq!(a.containment(&b), 1.0);
+    }
+
+    #[test]
+    fn disjoint_samples_are_at_distance_one() {
+        let a = sketch(&[b"AAAAAAAA"], 100);
+        let c = sketch(&[b"CACACACA"], 100);
+        assert_eq!(a.jaccard(&c), 0.0);
+        assert_eq!(a.distance(&c), 1.0);
+    }
+
+    #[test]
+    fn dist_compares_sketch_files_with_sequences() {
+        let dir = tempfile::tempdir().unwrap();
+        let fasta = dir.path().join("a.fa");
+        std::fs::write(&fasta, ">a\nACGGTCAGTTCAGAGCTAGGCTTACC\n").unwrap();
+        let signatures = signature::sketch(&[&fasta], 5, Some(8), 1000, None).unwrap();
+        let ksh = dir.path().join("a.ksh");
+        signature::write_binary(&mut std::fs::File::create(&ksh).unwrap(), &signatures).unwrap();
+        let other = dir.path().join("b.fa");
+        std::fs::write(&other, ">b\nACGGTCAGTTCAGAGCTAGGCTTTTTTTTTT\n").unwrap();
+
+        let mut out = Vec::new();
+        write_distances(&mut out, &dist(&ksh, &fasta, 5, 8).unwrap()).unwrap();
+        write_distances(&mut out, &dist(&ksh, &other, 5, 8).unwrap()).unwrap();
+        insta::assert_snapshot!(String::from_utf8(out).unwrap(), @r###"
+        a	a	0.000000	1.000000	8/8
+        a	b	0.030830	0.750000	6/8
+        "###);
+
+        let mash = dir.path().join("a.msh");
+        std::fs::write(&mash, b"\0\0\0\0").unwrap();
+        let e = dist(&mash, &fasta, 5, 8).unwrap_err();
+        assert!(
+            e.to_string().contains("Mash's .msh sketches can't be read"),
+            "{e}"
+        );
+    }
+
+    #[test]
+    fn sketches_keep_the_smallest_hashes() {
+        let full = sketch(&[b"ACGGTCAGTTCAGAGCTAGGCTTACC"], 100);
+        let small = sketch(&[b"ACGGTCAGTTCAGAGCTAGGCTTACC"], 4);
+        assert_eq!(small.hashes, full.hashes[..4]);
+    }
+
+    #[test]
+    fn scaled_sketches_keep_hashes_below_the_threshold() {
+        let seqs = [Bytes::from_static(
+            b"ACGGTCAGTTCAGAGCTAGGCTTACCGATGCATTGACC",
+        )];
+        let full = ScaledSketch::from_seqs(&seqs, 5, 1);
+        let scaled = ScaledSketch::from_seqs(&seqs, 5, 4);
+        assert!(scaled.hashes.len() < full.hashes.len());
+        assert!(scaled.hashes.iter().all(|hash| *hash <= scaled.max_hash()));
+        assert_eq!(scaled.containment(&full), 1.0);
+    }
+
+    #[test]
+    fn neighbor_joining_recovers_additive_tree() {
+        let names = ["a", "b", "c", "d"].map(String::from);
+        // ((a:1,b:2):1,c:1,d:3)
+        let d = vec![
+            vec![0.0, 3.0, 3.0, 5.0],
+            vec![3.0, 0.0, 4.0, 6.0],
+            vec![3.0, 4.0, 0.0, 4.0],
+            vec![5.0, 6.0, 4.0, 0.0],
+        ];
+        insta::assert_snapshot!(
+            neighbor_joining(&names, &d),
+            @"((a:1.000000,b:2.000000):1.000000,c:1.000000,d:3.000000);"
+        );
+    }
+}
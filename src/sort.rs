@@ -0,0 +1,316 @@
+#[cfg(feature = "spill")]
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    fs::File,
+    io::{BufReader, BufWriter, Read, Seek, Write},
+};
+use std::{
+    fmt::{self, Display},
+    io,
+    str::FromStr,
+};
+
+#[cfg(any(feature = "spill", test))]
+use super::run::StrandCounts;
+#[cfg(feature = "spill")]
+use super::spill;
+use super::{kmer::Packed, run::Record};
+
+/// Records sorted in memory before a chunk is spilled to a temporary file.
+///
+/// # Notes
+/// At around 24 bytes a record this caps sort buffers near 400 MB however
+/// many k-mers there are.
+pub(crate) const SORT_CHUNK_LEN: usize = 1 << 24;
+
+/// Order in which counted k-mers are written.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SortOrder {
+    /// Lexicographic, which is also the order of the packed integers
+    Kmer,
+    /// Least frequent first, ties in k-mer order
+    Count,
+    /// Most frequent first, ties in k-mer order
+    CountDesc,
+}
+
+impl SortOrder {
+    pub const VARIANTS: [&'static str; 3] = ["kmer", "count", "count-desc"];
+
+    /// Sort key of a packed k-mer with `count`, so ties, and every k-mer under
+    /// [`SortOrder::Kmer`], fall back on the packed integers
+    pub(crate) fn key<P: Packed>(&self, packed_bits: P, count: i32) -> (i64, P) {
+        match self {
+            Self::Kmer => (0, packed_bits),
+            Self::Count => (count as i64, packed_bits),
+            Self::CountDesc => (-(count as i64), packed_bits),
+        }
+    }
+}
+
+impl FromStr for SortOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "kmer" => Ok(Self::Kmer),
+            "count" => Ok(Self::Count),
+            "count-desc" => Ok(Self::CountDesc),
+            _ => Err(format!("Unknown sort order \"{s}\"")),
+        }
+    }
+}
+
+impl Display for SortOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Kmer => "kmer",
+            Self::Count => "count",
+            Self::CountDesc => "count-desc",
+        })
+    }
+}
+
+/// Sorts records in `order`, spilling sorted chunks of `chunk_len`
+/// records to temporary files and merging them lazily once there are more
+/// records than fit in one chunk, stopping at the first record that failed
+/// to be produced.
+#[cfg(feature = "spill")]
+pub(crate) fn sorted<P, I>(
+    records: I,
+    order: SortOrder,
+    strand: bool,
+    chunk_len: usize,
+) -> io::Result<Box<dyn Iterator<Item = io::Result<Record<P>>>>>
+where
+    P: Packed,
+    I: Iterator<Item = io::Result<Record<P>>>,
+{
+    let mut chunk = Vec::new();
+    let mut runs = Vec::new();
+
+    for record in records {
+        chunk.push(record?);
+        if chunk.len() == chunk_len {
+            runs.push(spill(&mut chunk, order, strand)?);
+        }
+    }
+
+    if runs.is_empty() {
+        chunk.sort_unstable_by_key(|(packed_bits, count, _)| order.key(*packed_bits, *count));
+        return Ok(Box::new(chunk.into_iter().map(Ok)));
+    }
+
+    if !chunk.is_empty() {
+        runs.push(spill(&mut chunk, order, strand)?);
+    }
+
+    Ok(Box::new(Merge::new(runs, order, strand)?))
+}
+
+/// Sorts records in `order` all in memory, however many there are, as
+/// without the `spill` feature there are no temporary files to spill to
+#[cfg(not(feature = "spill"))]
+pub(crate) fn sorted<P, I>(
+    records: I,
+    order: SortOrder,
+    _strand: bool,
+    _chunk_len: usize,
+) -> io::Result<Box<dyn Iterator<Item = io::Result<Record<P>>>>>
+where
+    P: Packed,
+    I: Iterator<Item = io::Result<Record<P>>>,
+{
+    let mut records = records.collect::<io::Result<Vec<_>>>()?;
+    records.sort_unstable_by_key(|(packed_bits, count, _)| order.key(*packed_bits, *count));
+
+    Ok(Box::new(records.into_iter().map(Ok)))
+}
+
+/// Sorts and writes out `chunk`, leaving it empty
+#[cfg(feature = "spill")]
+fn spill<P: Packed>(chunk: &mut Vec<Record<P>>, order: SortOrder, strand: bool) -> io::Result<Run> {
+    chunk.sort_unstable_by_key(|(packed_bits, count, _)| order.key(*packed_bits, *count));
+
+    let mut buf = BufWriter::new(spill::tempfile()?);
+    for (packed_bits, count, strand_counts) in chunk.drain(..) {
+        packed_bits.write_le(&mut buf)?;
+        buf.write_all(&count.to_le_bytes())?;
+        if strand {
+            let reverse = strand_counts.map_or(0, |strand_counts| strand_counts.reverse);
+            buf.write_all(&reverse.to_le_bytes())?;
+        }
+    }
+
+    let mut file = buf.into_inner().map_err(|e| e.into_error())?;
+    file.rewind()?;
+
+    Ok(Run(BufReader::new(file)))
+}
+
+/// One sorted chunk on disk.
+#[cfg(feature = "spill")]
+struct Run(BufReader<File>);
+
+#[cfg(feature = "spill")]
+impl Run {
+    fn next<P: Packed>(&mut self, strand: bool) -> io::Result<Option<Record<P>>> {
+        let mut packed_bits = [0; 16];
+        let packed_bits = &mut packed_bits[..P::BYTES];
+        match self.0.read_exact(packed_bits) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            result => result?,
+        }
+        let packed_bits = P::from_le_slice(packed_bits);
+
+        let mut count = [0; 4];
+        self.0.read_exact(&mut count)?;
+        let count = i32::from_le_bytes(count);
+
+        let strand_counts = match strand {
+            true => {
+                let mut reverse = [0; 4];
+                self.0.read_exact(&mut reverse)?;
+                let reverse = i32::from_le_bytes(reverse);
+                Some(StrandCounts {
+                    forward: count - reverse,
+                    reverse,
+                })
+            }
+            false => None,
+        };
+
+        Ok(Some((packed_bits, count, strand_counts)))
+    }
+}
+
+/// K-way merge of sorted runs.
+#[cfg(feature = "spill")]
+struct Merge<P> {
+    runs: Vec<Run>,
+    heads: Vec<Option<Record<P>>>,
+    heap: BinaryHeap<Reverse<((i64, P), usize)>>,
+    order: SortOrder,
+    strand: bool,
+}
+
+#[cfg(feature = "spill")]
+impl<P: Packed> Merge<P> {
+    fn new(mut runs: Vec<Run>, order: SortOrder, strand: bool) -> io::Result<Self> {
+        let mut heads = Vec::with_capacity(runs.len());
+        let mut heap = BinaryHeap::with_capacity(runs.len());
+
+        for (i, run) in runs.iter_mut().enumerate() {
+            let head = run.next(strand)?;
+            if let Some((packed_bits, count, _)) = head {
+                heap.push(Reverse((order.key(packed_bits, count), i)))
+            }
+            heads.push(head);
+        }
+
+        Ok(Self {
+            runs,
+            heads,
+            heap,
+            order,
+            strand,
+        })
+    }
+
+    fn advance(&mut self, i: usize) -> io::Result<Record<P>> {
+        let next = self.runs[i].next(self.strand)?;
+        if let Some((packed_bits, count, _)) = next {
+            self.heap
+                .push(Reverse((self.order.key(packed_bits, count), i)))
+        }
+
+        Ok(std::mem::replace(&mut self.heads[i], next).expect("head of a queued run"))
+    }
+}
+
+#[cfg(feature = "spill")]
+impl<P: Packed> Iterator for Merge<P> {
+    type Item = io::Result<Record<P>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse((_, i)) = self.heap.pop()?;
+
+        Some(self.advance(i))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn spilled_chunks_merge_in_order() {
+        let strand = Some(StrandCounts {
+            forward: 1,
+            reverse: 1,
+        });
+        let records = (0..100u64)
+            .rev()
+            .map(|packed_bits| (packed_bits * 7 % 101, 2, strand));
+
+        let sorted = sorted(records.map(Ok), SortOrder::Kmer, true, 8)
+            .unwrap()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+
+        assert_eq!(sorted.len(), 100);
+        assert!(sorted.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert!(sorted
+            .iter()
+            .all(|record| record.1 == 2 && record.2 == strand));
+    }
+
+    #[test]
+    fn wide_kmers_spill_and_merge() {
+        let records = (0..50u128)
+            .rev()
+            .map(|i| ((i << 100) | (i * 7 % 51), 1, None));
+
+        let sorted = sorted(records.map(Ok), SortOrder::Kmer, false, 8)
+            .unwrap()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+
+        assert_eq!(sorted.len(), 50);
+        assert!(sorted.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    }
+
+    #[test]
+    fn small_inputs_sort_in_memory() {
+        let records = [(3u64, 1, None), (1, 5, None), (2, 2, None)].into_iter();
+        let sorted = sorted(records.map(Ok), SortOrder::Kmer, false, 8)
+            .unwrap()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+
+        assert_eq!(sorted, vec![(1, 5, None), (2, 2, None), (3, 1, None)]);
+    }
+
+    #[test]
+    fn count_orders_spill_and_merge_with_ties_by_kmer() {
+        let records = (0..60u64)
+            .rev()
+            .map(|packed_bits| (packed_bits, (packed_bits % 4) as i32, None));
+
+        for order in [SortOrder::Count, SortOrder::CountDesc] {
+            let expected = {
+                let mut records = records.clone().collect::<Vec<_>>();
+                records.sort_by_key(|(packed_bits, count, _)| order.key(*packed_bits, *count));
+                records
+            };
+            for chunk_len in [8, 100] {
+                let sorted = sorted(records.clone().map(Ok), order, false, chunk_len)
+                    .unwrap()
+                    .collect::<io::Result<Vec<_>>>()
+                    .unwrap();
+                assert_eq!(sorted, expected, "{order}, chunks of {chunk_len}");
+            }
+        }
+    }
+}
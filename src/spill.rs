@@ -0,0 +1,207 @@
+//! `--singleton-spill`: k-mers counted once so far are moved from the count
+//! map to disk every so often while counting, so on error-heavy reads the
+//! map holds mostly repeated k-mers, and are added back when writing.
+
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Seek, Write},
+};
+
+#[cfg(not(feature = "spill"))]
+use super::output::unsupported;
+use super::{
+    kmer::Packed,
+    run::{CountMap, Tally},
+    sort::{self, SortOrder},
+};
+
+/// Bases counted between checks of whether to evict.
+pub(crate) const SPILL_CHECK_BASES: usize = 1 << 24;
+
+/// Distinct k-mers below which a count map is never evicted from before
+/// counting ends.
+const MIN_SPILL_KMERS: usize = 1 << 20;
+
+/// Spilled k-mers sorted at once to add up their occurrences, about 24 MB.
+const SPILL_SORT_CHUNK_LEN: usize = 1 << 20;
+
+/// An anonymous temporary file, deleted once closed, for counts that don't
+/// fit in memory
+///
+/// # Notes
+/// Built without the `spill` feature, there is nowhere to put them, so
+/// `--max-memory` and `--singleton-spill` fail here.
+pub(crate) fn tempfile() -> io::Result<File> {
+    #[cfg(feature = "spill")]
+    return ::tempfile::tempfile();
+    #[cfg(not(feature = "spill"))]
+    Err(unsupported("Spilling counts to disk", "spill"))
+}
+
+/// Packed k-mers evicted from a count map, one occurrence each.
+pub(crate) struct SingletonSpill {
+    file: BufWriter<File>,
+    /// Distinct k-mers left in the count map by the last eviction
+    kept: usize,
+}
+
+impl SingletonSpill {
+    pub(crate) fn new() -> io::Result<Self> {
+        Ok(Self {
+            file: BufWriter::new(tempfile()?),
+            kept: 0,
+        })
+    }
+
+    /// Evicts singletons from `counts` once it holds twice the k-mers the
+    /// last eviction left, so each eviction's pass over the map is paid for
+    /// by the k-mers added since
+    pub(crate) fn evict_if_grown<P: Packed, V: Tally>(
+        &mut self,
+        counts: &CountMap<P, V>,
+    ) -> io::Result<()> {
+        match counts.len() >= (2 * self.kept).max(MIN_SPILL_KMERS) {
+            true => self.evict(counts),
+            false => Ok(()),
+        }
+    }
+
+    /// Moves every k-mer counted exactly once out of `counts` to disk
+    pub(crate) fn evict<P: Packed, V: Tally>(&mut self, counts: &CountMap<P, V>) -> io::Result<()> {
+        let mut written = Ok(());
+        counts.retain(|packed_bits, count| {
+            if count.count() != 1 || written.is_err() {
+                return true;
+            }
+            written = packed_bits.write_le(&mut self.file);
+            false
+        });
+        self.kept = counts.len();
+
+        written
+    }
+
+    /// Adds the occurrences spilled of k-mers still in `counts` to their
+    /// counts, returning how many other k-mers were spilled and those k-mers,
+    /// in packed order, with their counts
+    ///
+    /// # Notes
+    /// A k-mer can be spilled more than once, each time it's seen once more
+    /// between evictions, so spilled k-mers are sorted, spilling to further
+    /// temporary files if need be, to add up their occurrences.
+    pub(crate) fn reconcile<P: Packed, V: Tally>(
+        self,
+        counts: &CountMap<P, V>,
+    ) -> io::Result<(usize, impl Iterator<Item = io::Result<(P, i32)>>)> {
+        let mut file = self.file.into_inner().map_err(|e| e.into_error())?;
+        file.rewind()?;
+        let spilled = SpilledKmers::<P>::new(file).map(|packed_bits| Ok((packed_bits?, 1, None)));
+        let sorted = sort::sorted(spilled, SortOrder::Kmer, false, SPILL_SORT_CHUNK_LEN)?;
+
+        let mut rest = BufWriter::new(tempfile()?);
+        let mut rest_len = 0;
+        let mut add_back = |packed_bits: P, occurrences: i32| -> io::Result<()> {
+            match counts.get_mut(&packed_bits) {
+                Some(mut count) => count.add(occurrences),
+                None => {
+                    packed_bits.write_le(&mut rest)?;
+                    rest.write_all(&occurrences.to_le_bytes())?;
+                    rest_len += 1;
+                }
+            }
+            Ok(())
+        };
+
+        let mut run = None;
+        for record in sorted {
+            let (packed_bits, ..) = record?;
+            match &mut run {
+                Some((last, occurrences)) if *last == packed_bits => *occurrences += 1,
+                _ => {
+                    if let Some((last, occurrences)) = run.replace((packed_bits, 1)) {
+                        add_back(last, occurrences)?
+                    }
+                }
+            }
+        }
+        if let Some((last, occurrences)) = run {
+            add_back(last, occurrences)?
+        }
+
+        let mut rest = rest.into_inner().map_err(|e| e.into_error())?;
+        rest.rewind()?;
+        let mut rest = BufReader::new(rest);
+        let records = (0..rest_len).map(move |_| {
+            let mut packed_bits = [0; 16];
+            rest.read_exact(&mut packed_bits[..P::BYTES])?;
+            let mut count = [0; 4];
+            rest.read_exact(&mut count)?;
+            Ok((
+                P::from_le_slice(&packed_bits[..P::BYTES]),
+                i32::from_le_bytes(count),
+            ))
+        });
+
+        Ok((rest_len, records))
+    }
+}
+
+/// The packed k-mers of a spill file.
+struct SpilledKmers<P> {
+    reader: BufReader<File>,
+    packed: std::marker::PhantomData<P>,
+}
+
+impl<P: Packed> SpilledKmers<P> {
+    fn new(file: File) -> Self {
+        Self {
+            reader: BufReader::new(file),
+            packed: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<P: Packed> Iterator for SpilledKmers<P> {
+    type Item = io::Result<P>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut packed_bits = [0; 16];
+        match self.reader.read_exact(&mut packed_bits[..P::BYTES]) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => None,
+            Err(e) => Some(Err(e)),
+            Ok(()) => Some(Ok(P::from_le_slice(&packed_bits[..P::BYTES]))),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "spill"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn spilled_occurrences_are_added_back() {
+        let counts = CountMap::<u64, i32>::default();
+        let mut spill = SingletonSpill::new().unwrap();
+
+        // 1 and 2 are spilled, then 2 is seen once more and spilled again,
+        // while 1 is seen twice more and stays, as does 3 all along
+        let count = |kmers: &[(u64, i32)]| {
+            kmers
+                .iter()
+                .for_each(|(packed_bits, count)| _ = counts.insert(*packed_bits, *count))
+        };
+        count(&[(1, 1), (2, 1), (3, 2)]);
+        spill.evict(&counts).unwrap();
+        count(&[(1, 2), (2, 1), (4, 1)]);
+        spill.evict(&counts).unwrap();
+
+        let (rest_len, rest) = spill.reconcile(&counts).unwrap();
+        let rest = rest.collect::<io::Result<Vec<_>>>().unwrap();
+        assert_eq!(rest_len, 2);
+        assert_eq!(rest, [(2, 2), (4, 1)]);
+
+        let mut counts = counts.into_iter().collect::<Vec<_>>();
+        counts.sort();
+        assert_eq!(counts, [(1, 3), (3, 2)]);
+    }
+}
@@ -0,0 +1,159 @@
+//! `krust suggest-k`: picks a k for a genome from a sample of its reads.
+
+use std::{
+    error::Error,
+    fmt::{self, Display},
+    path::Path,
+};
+
+use fxhash::FxHashMap;
+use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+
+use super::{
+    kmer,
+    preview::{Read, Sample},
+};
+
+/// The k values tried, odd so no k-mer is its own reverse complement.
+pub const K_LADDER: [usize; 9] = [15, 17, 19, 21, 23, 25, 27, 29, 31];
+
+/// Highest acceptable chance of a k-mer occurring in the genome at random.
+///
+/// # Notes
+/// `1 / 200`, which gives the familiar `k = log4(200 * genome size)`.
+pub const MAX_RANDOM_HIT: f64 = 0.005;
+
+/// Sample counts at one k.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rung {
+    pub k: usize,
+    /// Chance of a given k-mer occurring in the genome at random,
+    /// `genome size / 4^k`
+    pub random_hit: f64,
+    pub distinct: usize,
+    /// Distinct k-mers seen at least twice, and so unlikely to be errors
+    pub solid: usize,
+}
+
+/// Counts at each of [`K_LADDER`] and the k suggested from them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Suggestion {
+    pub genome_size: u64,
+    pub ladder: Vec<Rung>,
+    pub k: usize,
+}
+
+/// Counts a sample of `path` at each of [`K_LADDER`], then suggests the k
+/// with the most solid k-mers among those long enough to be unlikely to
+/// occur in a genome of `genome_size` bases at random
+///
+/// # Notes
+/// A lite take on `kmergenie`: too short a k and k-mers repeat across the
+/// genome; too long and sequencing errors leave fewer k-mers seen more than
+/// once.
+pub fn suggest_k<P: AsRef<Path>>(
+    path: P,
+    genome_size: u64,
+    head_mb: usize,
+    sample_len: usize,
+) -> Result<Suggestion, Box<dyn Error>> {
+    let sample = Sample::read(path, head_mb, sample_len)?;
+
+    Ok(Suggestion::of(&sample.reads, genome_size))
+}
+
+impl Suggestion {
+    fn of(reads: &[Read], genome_size: u64) -> Self {
+        let ladder = K_LADDER
+            .into_par_iter()
+            .map(|k| {
+                let mut counts = FxHashMap::<u64, u32>::default();
+                for packed_bits in reads
+                    .iter()
+                    .flat_map(|read| kmer::canonical_kmers(&read.seq, k))
+                {
+                    *counts.entry(packed_bits).or_insert(0) += 1
+                }
+
+                Rung {
+                    k,
+                    random_hit: genome_size as f64 / 4f64.powi(k as i32),
+                    distinct: counts.len(),
+                    solid: counts.values().filter(|count| **count > 1).count(),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let k = ladder
+            .iter()
+            .filter(|rung| rung.random_hit <= MAX_RANDOM_HIT)
+            // `max_by_key` keeps the last maximum, so reverse to prefer the
+            // shorter of equally good k
+            .rev()
+            .max_by_key(|rung| rung.solid)
+            .map_or(K_LADDER[K_LADDER.len() - 1], |rung| rung.k);
+
+        Self {
+            genome_size,
+            ladder,
+            k,
+        }
+    }
+}
+
+impl Display for Suggestion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "k\trandom_hit\tdistinct\tsolid")?;
+        for rung in &self.ladder {
+            writeln!(
+                f,
+                "{}\t{:.2e}\t{}\t{}",
+                rung.k, rung.random_hit, rung.distinct, rung.solid
+            )?;
+        }
+        write!(f, "suggested k: {}", self.k)
+    }
+}
+
+/// Parses a genome size in bases, with an optional `k`, `m` or `g` suffix,
+/// e.g. `4.6m` or `3.1g`
+pub fn parse_genome_size(s: &str) -> Result<u64, String> {
+    let lower = s.trim().to_ascii_lowercase();
+    let (number, scale) = match lower.chars().last() {
+        Some('k') => (&lower[..lower.len() - 1], 1e3),
+        Some('m') => (&lower[..lower.len() - 1], 1e6),
+        Some('g') => (&lower[..lower.len() - 1], 1e9),
+        _ => (&lower[..], 1.0),
+    };
+
+    match number.parse::<f64>() {
+        Ok(size) if size > 0.0 => Ok((size * scale).round() as u64),
+        _ => Err(format!("Issue with genome size \"{s}\"")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn genome_sizes_take_suffixes() {
+        assert_eq!(parse_genome_size("4.6m"), Ok(4_600_000));
+        assert_eq!(parse_genome_size("3G"), Ok(3_000_000_000));
+        assert_eq!(parse_genome_size("1200"), Ok(1200));
+        assert!(parse_genome_size("big").is_err());
+    }
+
+    #[test]
+    fn suggests_shortest_k_unlikely_to_repeat_at_random() {
+        // Error-free reads covering every k-mer twice, so solid counts are
+        // flat across the ladder and only the random-hit bound decides
+        let reads = ["ACGGTCAGTTCAGAGCTAGGCTTACCGATGCATTGACC"; 2].map(|seq| Read {
+            seq: seq.as_bytes().to_vec().into(),
+            qual: None,
+        });
+
+        assert_eq!(Suggestion::of(&reads, 1_000_000).k, 15);
+        assert_eq!(Suggestion::of(&reads, 3_000_000_000).k, 21);
+    }
+}
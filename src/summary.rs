@@ -0,0 +1,106 @@
+//! `--summary-per-record`: one QC line per input record instead of a k-mer
+//! table.
+
+use std::io::{self, Write};
+
+use bytes::Bytes;
+use fxhash::FxHashSet;
+
+use super::kmer::{self, Packed};
+
+/// Length, GC content and k-mer totals of one input record.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct RecordSummary {
+    pub id: String,
+    pub length: usize,
+    /// `G` and `C` as a fraction of `ACGT` bases, ignoring `N`s and the like
+    pub gc: f64,
+    /// Valid k-mers, i.e. those without an `N`
+    pub kmers: u64,
+    /// Distinct canonical k-mers
+    pub distinct: u64,
+}
+
+impl RecordSummary {
+    pub(crate) fn new(id: String, seq: &Bytes, k: usize) -> Self {
+        let (mut gc, mut acgt) = (0u64, 0u64);
+        for base in seq.iter() {
+            match base.to_ascii_uppercase() {
+                b'G' | b'C' => {
+                    gc += 1;
+                    acgt += 1
+                }
+                b'A' | b'T' => acgt += 1,
+                _ => (),
+            }
+        }
+
+        let (kmers, distinct) = match k <= u64::MAX_K {
+            true => tally::<u64>(seq, k),
+            false => tally::<u128>(seq, k),
+        };
+
+        Self {
+            id,
+            length: seq.len(),
+            gc: match acgt {
+                0 => 0.0,
+                _ => gc as f64 / acgt as f64,
+            },
+            kmers,
+            distinct,
+        }
+    }
+}
+
+/// Canonical k-mers in `seq`, and how many of them are distinct
+fn tally<P: Packed>(seq: &Bytes, k: usize) -> (u64, u64) {
+    let mut kmers = 0;
+    let mut distinct = FxHashSet::default();
+    for packed_bits in kmer::canonical_kmers::<P>(seq, k) {
+        distinct.insert(packed_bits);
+        kmers += 1;
+    }
+
+    (kmers, distinct.len() as u64)
+}
+
+/// Writes a header line, then one line per summary
+pub(crate) fn write<W: Write>(
+    out: &mut W,
+    summaries: &[RecordSummary],
+    delimiter: char,
+) -> io::Result<()> {
+    let d = delimiter;
+    writeln!(out, "record{d}length{d}gc{d}kmers{d}distinct")?;
+    for summary in summaries {
+        writeln!(
+            out,
+            "{}{d}{}{d}{:.4}{d}{}{d}{}",
+            summary.id, summary.length, summary.gc, summary.kmers, summary.distinct
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn summarizes_each_record() {
+        let summaries = [
+            RecordSummary::new("chr1".into(), &Bytes::from_static(b"ACGTNACG"), 3),
+            RecordSummary::new("tiny".into(), &Bytes::from_static(b"AC"), 3),
+        ];
+
+        let mut out = Vec::new();
+        write(&mut out, &summaries, '\t').unwrap();
+        insta::assert_snapshot!(String::from_utf8(out).unwrap(), @r###"
+        record	length	gc	kmers	distinct
+        chr1	8	0.5714	3	1
+        tiny	2	0.5000	0	0
+        "###);
+    }
+}
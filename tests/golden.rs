@@ -0,0 +1,355 @@
+//! Compares `krust` output, for every fixture, k and output format, against
+//! the k-mer counts of a naive reference model of `jellyfish count -C`, and
+//! against `jellyfish` dumps recorded in `tests/fixtures`.
+//!
+//! The dumps can only be recorded where `jellyfish` is installed, with
+//! `cargo test --test golden -- --ignored record_fixtures`, which also
+//! rewrites the FASTA fixtures. Until they are, the comparison against them
+//! is skipped with a warning.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use krust::kmer;
+
+const FORMATS: [&str; 5] = ["fasta", "tsv", "csv", "json", "raw"];
+
+/// Names the `jellyfish` that recorded the dumps; written last, so it's only
+/// there once every dump is
+const RECORDED_WITH: &str = "jellyfish.version";
+
+/// K-mer lengths every fixture is recorded at.
+const K_VALUES: [usize; 4] = [3, 11, 21, 31];
+
+/// A fixture's name and the function generating its FASTA text.
+type Fixture = (&'static str, fn() -> String);
+
+const FIXTURES: [Fixture; 4] = [
+    ("soft_masked", soft_masked),
+    ("n_heavy", n_heavy),
+    ("multi_line", multi_line),
+    ("crlf", crlf),
+];
+
+/// `xorshift64*`, so fixtures come out identical everywhere without a
+/// dependency.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn bases(&mut self, len: usize) -> String {
+        (0..len)
+            .map(|_| ['A', 'C', 'G', 'T'][self.below(4) as usize])
+            .collect()
+    }
+}
+
+/// Wraps `seq` at `width` columns, as most FASTA writers do
+fn wrap(seq: &str, width: usize) -> String {
+    seq.as_bytes()
+        .chunks(width)
+        .map(|line| String::from_utf8_lossy(line) + "\n")
+        .collect()
+}
+
+/// Repeat-masked stretches in lowercase, which `jellyfish` counts like any
+/// other bases
+fn soft_masked() -> String {
+    let mut rng = Rng(0x5eed_0001);
+    let seq = rng
+        .bases(400)
+        .chars()
+        .enumerate()
+        .map(|(i, base)| match (i / 40) % 3 {
+            1 => base.to_ascii_lowercase(),
+            _ => base,
+        })
+        .collect::<String>();
+
+    format!(">masked\n{}", wrap(&seq, 80))
+}
+
+/// Runs of `N` of assorted lengths, plus a record shorter than any k
+fn n_heavy() -> String {
+    let mut rng = Rng(0x5eed_0002);
+    let mut seq = String::new();
+    while seq.len() < 400 {
+        let bases = 1 + rng.below(40) as usize;
+        seq.push_str(&rng.bases(bases));
+        let gap = 1 + rng.below(6) as usize;
+        seq.push_str(&"N".repeat(gap));
+    }
+
+    format!(">gappy\n{}>tiny\nAC\n", wrap(&seq, 80))
+}
+
+/// Several records wrapped at 60 columns, so k-mers span line breaks
+fn multi_line() -> String {
+    let mut rng = Rng(0x5eed_0003);
+
+    (0..3)
+        .map(|i| format!(">chr{i}\n{}", wrap(&rng.bases(150 + 50 * i), 60)))
+        .collect()
+}
+
+/// Records with Windows (`\r\n`) line endings and header descriptions, as
+/// saved by editors and tools on Windows
+fn crlf() -> String {
+    let mut rng = Rng(0x5eed_0004);
+
+    (0..2)
+        .map(|i| {
+            let seq = wrap(&rng.bases(120 + 40 * i), 50).replace('\n', "\r\n");
+            format!(">contig{i} sample=lab{i}\r\n{seq}")
+        })
+        .collect()
+}
+
+/// Canonical k-mer counts of FASTA text, as `jellyfish count -C` reports them
+///
+/// # Notes
+/// Bases are uppercased and any k-mer containing something other than
+/// `ACGT` is skipped.
+fn reference_counts(fasta: &str, k: usize) -> BTreeMap<String, u64> {
+    let mut counts = BTreeMap::new();
+
+    for record in fasta.split('>').skip(1) {
+        let seq = record
+            .lines()
+            .skip(1)
+            .collect::<String>()
+            .to_ascii_uppercase();
+
+        for kmer in seq.as_bytes().windows(k) {
+            let kmer = String::from_utf8_lossy(kmer);
+            if !kmer.chars().all(|base| "ACGT".contains(base)) {
+                continue;
+            }
+            let reverse_complement = kmer
+                .chars()
+                .rev()
+                .map(|base| match base {
+                    'A' => 'T',
+                    'C' => 'G',
+                    'G' => 'C',
+                    _ => 'A',
+                })
+                .collect::<String>();
+            let canonical = kmer.to_string().min(reverse_complement);
+            *counts.entry(canonical).or_insert(0) += 1;
+        }
+    }
+
+    counts
+}
+
+/// Counts with `jellyfish`, which must be on the `PATH`
+fn jellyfish_counts(fasta: &Path, k: usize) -> BTreeMap<String, u64> {
+    let dir = tempfile::tempdir().unwrap();
+    let db = dir.path().join("mers.jf");
+
+    let counted = Command::new("jellyfish")
+        .args(["count", "-C", "-s", "1M", "-m", &k.to_string(), "-o"])
+        .arg(&db)
+        .arg(fasta)
+        .status()
+        .expect("jellyfish must be on the PATH to record dumps");
+    assert!(counted.success(), "jellyfish count: {counted}");
+
+    let dump = Command::new("jellyfish")
+        .args(["dump", "-c"])
+        .arg(&db)
+        .output()
+        .unwrap();
+    assert!(dump.status.success(), "jellyfish dump: {dump:?}");
+
+    String::from_utf8(dump.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| {
+            let (kmer, count) = line.split_once(' ').unwrap();
+            (kmer.to_string(), count.parse().unwrap())
+        })
+        .collect()
+}
+
+/// `jellyfish dump` format: `>{count}` then `{k-mer}`
+fn dump(counts: &BTreeMap<String, u64>) -> String {
+    counts
+        .iter()
+        .map(|(kmer, count)| format!(">{count}\n{kmer}\n"))
+        .collect()
+}
+
+/// Parses [`dump`] output
+fn parse_dump(dump: &str) -> BTreeMap<String, u64> {
+    dump.lines()
+        .collect::<Vec<_>>()
+        .chunks(2)
+        .map(|pair| {
+            let count = pair[0].trim_start_matches('>').parse().unwrap();
+            (pair[1].to_string(), count)
+        })
+        .collect()
+}
+
+fn dump_path(name: &str, k: usize) -> PathBuf {
+    fixtures().join(format!("{name}.k{k}.dump"))
+}
+
+fn fixtures() -> &'static Path {
+    Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures"))
+}
+
+fn krust(k: usize, fasta: &Path, format: &str, args: &[&str]) -> Vec<u8> {
+    let output = Command::new(env!("CARGO_BIN_EXE_krust"))
+        .arg(k.to_string())
+        .arg(fasta)
+        .args(["--format", format])
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+
+    output.stdout
+}
+
+fn parse(output: &[u8], k: usize, format: &str) -> BTreeMap<String, u64> {
+    let text = || String::from_utf8(output.to_vec()).unwrap();
+    let columns = |line: &str, delimiter| {
+        let (kmer, count) = line.split_once(delimiter).unwrap();
+        (kmer.to_string(), count.parse().unwrap())
+    };
+
+    match format {
+        "fasta" => parse_dump(&text()),
+        "tsv" => text().lines().map(|line| columns(line, '\t')).collect(),
+        "csv" => text()
+            .lines()
+            .skip(1)
+            .map(|line| columns(line, ','))
+            .collect(),
+        "json" => {
+            let json: serde_json::Value = serde_json::from_str(&text()).unwrap();
+            json["counts"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|record| {
+                    let kmer = record["kmer"].as_str().unwrap().to_string();
+                    (kmer, record["count"].as_u64().unwrap())
+                })
+                .collect()
+        }
+        "raw" => {
+            assert_eq!(&output[..6], &[b'K', b'R', b'A', b'W', 1, k as u8]);
+            output[8..]
+                .chunks(16)
+                .map(|pair| {
+                    let packed_bits = u64::from_le_bytes(pair[..8].try_into().unwrap());
+                    let count = u64::from_le_bytes(pair[8..].try_into().unwrap());
+                    (kmer::unpack_to_string(packed_bits, k), count)
+                })
+                .collect()
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Checks every output format, and `--mmap`, of each fixture at each k
+/// against `expected`
+fn check_outputs(expected: impl Fn(&str, &str, usize) -> BTreeMap<String, u64>) {
+    for (name, _) in FIXTURES {
+        let fasta = fixtures().join(format!("{name}.fa"));
+        let text = fs::read_to_string(&fasta).unwrap();
+
+        for k in K_VALUES {
+            let expected = expected(name, &text, k);
+
+            for format in FORMATS {
+                let counts = parse(&krust(k, &fasta, format, &[]), k, format);
+                assert_eq!(counts, expected, "{name}, k = {k}, --format {format}");
+            }
+
+            let counts = parse(&krust(k, &fasta, "tsv", &["--mmap"]), k, "tsv");
+            assert_eq!(counts, expected, "{name}, k = {k}, --mmap");
+        }
+    }
+}
+
+#[test]
+fn fixtures_are_up_to_date() {
+    for (name, fixture) in FIXTURES {
+        let recorded = fs::read_to_string(fixtures().join(format!("{name}.fa"))).unwrap();
+        assert_eq!(recorded, fixture(), "regenerate {name}.fa");
+    }
+}
+
+#[test]
+fn reference_counts_are_canonical_and_skip_invalid_bases() {
+    let counts = reference_counts(">a\nACg\nTNA\n>b\nCG\n", 3);
+    insta::assert_snapshot!(format!("{counts:?}"), @r###"{"ACG": 2}"###);
+    assert_eq!(parse_dump(&dump(&counts)), counts);
+}
+
+#[test]
+fn output_matches_the_reference_model() {
+    check_outputs(|_, fasta, k| reference_counts(fasta, k));
+}
+
+#[test]
+fn output_matches_recorded_jellyfish_dumps() {
+    let Ok(version) = fs::read_to_string(fixtures().join(RECORDED_WITH)) else {
+        // Written straight to stderr, which the test harness doesn't capture
+        writeln!(
+            std::io::stderr(),
+            "warning: no jellyfish dumps in tests/fixtures, skipping; record them with \
+             `cargo test --test golden -- --ignored record_fixtures`"
+        )
+        .unwrap();
+        return;
+    };
+    assert!(
+        version.starts_with("jellyfish"),
+        "{RECORDED_WITH}: {version}"
+    );
+
+    check_outputs(|name, _, k| parse_dump(&fs::read_to_string(dump_path(name, k)).unwrap()));
+}
+
+/// Rewrites the FASTA fixtures and records their dumps with `jellyfish`
+#[test]
+#[ignore = "needs jellyfish on the PATH, and rewrites tests/fixtures"]
+fn record_fixtures() {
+    let version = Command::new("jellyfish")
+        .arg("--version")
+        .output()
+        .expect("jellyfish must be on the PATH to record dumps");
+    let _ = fs::remove_file(fixtures().join(RECORDED_WITH));
+
+    for (name, fixture) in FIXTURES {
+        let path = fixtures().join(format!("{name}.fa"));
+        fs::write(&path, fixture()).unwrap();
+
+        for k in K_VALUES {
+            fs::write(dump_path(name, k), dump(&jellyfish_counts(&path, k))).unwrap();
+        }
+    }
+
+    fs::write(fixtures().join(RECORDED_WITH), version.stdout).unwrap();
+}